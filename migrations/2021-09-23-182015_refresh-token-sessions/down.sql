@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE refresh_tokens
+    DROP COLUMN id,
+    DROP COLUMN created_at,
+    DROP COLUMN ip_address,
+    DROP COLUMN user_agent,
+    DROP COLUMN device_label;
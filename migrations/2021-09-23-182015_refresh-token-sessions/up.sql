@@ -0,0 +1,12 @@
+-- Turns refresh tokens into sessions that can be listed & revoked by the user
+ALTER TABLE refresh_tokens
+    -- Opaque identifier used to refer to a session without exposing the token itself
+    ADD COLUMN id uuid UNIQUE NOT NULL DEFAULT gen_random_uuid(),
+    -- When the token was created
+    ADD COLUMN created_at timestamp NOT NULL DEFAULT now(),
+    -- IP address of the client that requested the token
+    ADD COLUMN ip_address text,
+    -- User agent of the client that requested the token
+    ADD COLUMN user_agent text,
+    -- Optional user-provided name for the device, e.g. "work laptop"
+    ADD COLUMN device_label varchar(255);
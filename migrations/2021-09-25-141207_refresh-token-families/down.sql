@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE security_events;
+
+DROP INDEX refresh_tokens_family_id_idx;
+
+ALTER TABLE refresh_tokens
+    DROP COLUMN family_id,
+    DROP COLUMN parent_id;
@@ -0,0 +1,25 @@
+-- Groups refresh tokens into rotation chains, so reuse of an old token only revokes its own chain
+ALTER TABLE refresh_tokens
+    -- All tokens rotated from the same login share a family
+    ADD COLUMN family_id uuid NOT NULL DEFAULT gen_random_uuid(),
+    -- The token this one was rotated from (NULL for the first token of a family)
+    ADD COLUMN parent_id uuid REFERENCES refresh_tokens(id) ON DELETE SET NULL;
+
+CREATE INDEX refresh_tokens_family_id_idx ON refresh_tokens (family_id);
+
+-- Stores security-relevant events, e.g. reuse of a refresh token
+CREATE TABLE security_events (
+    id uuid DEFAULT gen_random_uuid() PRIMARY KEY,
+
+    -- The user the event concerns, if any
+    user_id uuid REFERENCES users(id) ON DELETE CASCADE,
+    -- Machine-readable type of event, e.g. "refresh_token_reuse"
+    kind varchar(64) NOT NULL,
+    -- Optional human-readable details
+    details text,
+    -- IP address & user agent of the client that triggered the event
+    ip_address text,
+    user_agent text,
+    -- When the event occured
+    created_at timestamp NOT NULL DEFAULT now()
+);
@@ -0,0 +1,16 @@
+ALTER TABLE users
+    -- Secret used to generate TOTP codes (NULL if 2FA was never set up)
+    ADD COLUMN totp_secret bytea,
+    -- Whether the secret has been confirmed, meaning logging in requires a code
+    ADD COLUMN totp_enabled boolean NOT NULL DEFAULT false,
+    -- Time step of the last accepted code, used to prevent codes from being replayed
+    ADD COLUMN totp_last_step bigint;
+
+-- Single-use codes that can be used instead of a TOTP code, e.g. when a phone is lost
+CREATE TABLE recovery_codes (
+    user_id uuid NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- SHA-256 hash of the code
+    code_hash bytea NOT NULL,
+
+    PRIMARY KEY (user_id, code_hash)
+);
@@ -0,0 +1,19 @@
+-- Long-lived tokens for scripts & other non-interactive clients
+CREATE TABLE personal_access_tokens (
+    id uuid DEFAULT gen_random_uuid() PRIMARY KEY,
+
+    -- The user the token acts on behalf of
+    user_id uuid NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- Name to recognize the token by, e.g. "publishing script"
+    name varchar(255) NOT NULL,
+    -- SHA-256 hash of the token; the token itself is only shown once
+    token_hash bytea UNIQUE NOT NULL,
+    -- What the token can be used for, e.g. "posts:write"
+    scopes text[] NOT NULL,
+    -- When the token was created
+    created_at timestamp NOT NULL DEFAULT now(),
+    -- When the token expires (NULL if it stays valid until revoked)
+    expires_at timestamp,
+    -- When the token was last used (is NULL until used)
+    last_used_at timestamp
+);
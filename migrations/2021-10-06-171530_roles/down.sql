@@ -0,0 +1,12 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE posts DROP COLUMN author_id;
+
+ALTER TABLE users ADD COLUMN admin boolean NOT NULL DEFAULT false;
+
+UPDATE users SET admin = true WHERE role = 'admin';
+
+ALTER TABLE users DROP COLUMN role;
+
+DROP TABLE role_permissions;
+DROP TABLE roles;
+DROP TABLE permissions;
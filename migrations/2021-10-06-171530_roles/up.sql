@@ -0,0 +1,56 @@
+-- Permissions that can be granted to roles; these are checked by the API's routes
+CREATE TABLE permissions (
+    -- Name of the permission, e.g. "posts:write"
+    name varchar(64) PRIMARY KEY,
+    -- What the permission allows
+    description text NOT NULL
+);
+
+CREATE TABLE roles (
+    -- Name of the role, e.g. "editor"
+    name varchar(32) PRIMARY KEY,
+    -- What the role is meant for
+    description text NOT NULL
+);
+
+CREATE TABLE role_permissions (
+    role varchar(32) NOT NULL REFERENCES roles(name) ON DELETE CASCADE ON UPDATE CASCADE,
+    permission varchar(64) NOT NULL REFERENCES permissions(name) ON DELETE CASCADE ON UPDATE CASCADE,
+
+    PRIMARY KEY (role, permission)
+);
+
+INSERT INTO permissions (name, description) VALUES
+    ('posts:write', 'Create posts & edit or delete your own posts'),
+    ('posts:edit-any', 'Edit or delete any post'),
+    ('sections:write', 'Create & manage sections'),
+    ('users:read', 'View users & their sessions'),
+    ('users:write', 'Create & manage users');
+
+INSERT INTO roles (name, description) VALUES
+    ('reader', 'Can only read public content'),
+    ('author', 'Can write their own posts'),
+    ('editor', 'Can write posts & edit any post'),
+    ('admin', 'Can do everything, including managing users');
+
+INSERT INTO role_permissions (role, permission) VALUES
+    ('author', 'posts:write'),
+    ('editor', 'posts:write'),
+    ('editor', 'posts:edit-any'),
+    ('admin', 'posts:write'),
+    ('admin', 'posts:edit-any'),
+    ('admin', 'sections:write'),
+    ('admin', 'users:read'),
+    ('admin', 'users:write');
+
+-- The admin flag is replaced by the admin role
+ALTER TABLE users
+    ADD COLUMN role varchar(32) NOT NULL DEFAULT 'reader' REFERENCES roles(name) ON UPDATE CASCADE;
+
+UPDATE users SET role = 'admin' WHERE admin;
+
+ALTER TABLE users DROP COLUMN admin;
+
+-- User who wrote the post (is NULL if the user has been removed)
+ALTER TABLE posts
+    ADD COLUMN author_id uuid REFERENCES users(id) ON DELETE SET NULL;
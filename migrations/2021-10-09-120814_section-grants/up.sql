@@ -0,0 +1,11 @@
+-- Grants a user access to a single section, on top of the permissions granted by their role
+CREATE TABLE section_grants (
+    user_id uuid NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    section_id uuid NOT NULL REFERENCES sections(id) ON DELETE CASCADE,
+    -- Either "write", "edit-any" or "manage"; each level includes the ones before it
+    level varchar(16) NOT NULL CHECK (level IN ('write', 'edit-any', 'manage')),
+    -- When the access was granted
+    created_at timestamp NOT NULL DEFAULT now(),
+
+    PRIMARY KEY (user_id, section_id)
+);
@@ -0,0 +1,15 @@
+-- Failed login attempts, used to slow down & lock out password guessing
+CREATE TABLE login_throttles (
+    -- Either "user" or "ip"
+    kind varchar(8) NOT NULL CHECK (kind IN ('user', 'ip')),
+    -- The username or IP address the failures were made for
+    subject varchar(255) NOT NULL,
+    -- Amount of consecutive failed attempts
+    failures integer NOT NULL DEFAULT 1,
+    -- When the last failed attempt was made
+    last_failure_at timestamp NOT NULL DEFAULT now(),
+    -- Login attempts are rejected until this time (is NULL if attempts aren't delayed)
+    locked_until timestamp,
+
+    PRIMARY KEY (kind, subject)
+);
@@ -0,0 +1,3 @@
+-- Set when an admin resets a user's password; the user can't log in until they've changed it
+ALTER TABLE users
+    ADD COLUMN must_change_password boolean NOT NULL DEFAULT false;
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE password_reset_tokens;
+
+ALTER TABLE users DROP COLUMN email;
@@ -0,0 +1,15 @@
+-- Address used to send password reset emails to; stored in lowercase
+ALTER TABLE users
+    ADD COLUMN email varchar(255) UNIQUE;
+
+-- Single-use tokens that are emailed to users who forgot their password
+CREATE TABLE password_reset_tokens (
+    -- SHA-256 hash of the token; the token itself is only sent to the user
+    token_hash bytea PRIMARY KEY,
+    -- The user whose password can be reset using the token
+    user_id uuid NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- When the token was created
+    created_at timestamp NOT NULL DEFAULT now(),
+    -- When the token expires
+    expires_at timestamp NOT NULL
+);
@@ -0,0 +1,19 @@
+-- Invite codes allowing people to register an account themselves
+CREATE TABLE invites (
+    id uuid DEFAULT gen_random_uuid() PRIMARY KEY,
+
+    -- SHA-256 hash of the invite code; the code itself is only shown once
+    code_hash bytea UNIQUE NOT NULL,
+    -- Role given to users registering with this invite (NULL for the default role)
+    role varchar(32) REFERENCES roles(name) ON DELETE CASCADE ON UPDATE CASCADE,
+    -- How many accounts can be registered using this invite
+    max_uses integer NOT NULL DEFAULT 1 CHECK (max_uses > 0),
+    -- How many accounts have been registered using this invite
+    uses integer NOT NULL DEFAULT 0,
+    -- The admin who created the invite (NULL if they've been removed)
+    created_by uuid REFERENCES users(id) ON DELETE SET NULL,
+    -- When the invite was created
+    created_at timestamp NOT NULL DEFAULT now(),
+    -- When the invite expires (NULL if it stays valid until used up)
+    expires_at timestamp
+);
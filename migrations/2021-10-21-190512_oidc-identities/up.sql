@@ -0,0 +1,13 @@
+-- Links identities at an OpenID Connect provider to local users
+CREATE TABLE oidc_identities (
+    -- Issuer URL of the provider
+    issuer varchar(255) NOT NULL,
+    -- Identifier of the user at the provider; only unique per issuer
+    subject varchar(255) NOT NULL,
+    -- The local user the identity logs in as
+    user_id uuid NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- When the identity was first used to log in
+    created_at timestamp NOT NULL DEFAULT now(),
+
+    PRIMARY KEY (issuer, subject)
+);
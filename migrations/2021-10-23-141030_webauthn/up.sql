@@ -0,0 +1,27 @@
+-- Security keys & passkeys users can log in with instead of a password
+CREATE TABLE webauthn_credentials (
+    id uuid DEFAULT gen_random_uuid() PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- Name given to the credential by its user, e.g. "YubiKey"
+    name varchar(255) NOT NULL,
+    -- ID the authenticator uses for the credential
+    credential_id bytea NOT NULL UNIQUE,
+    -- The credential's public key & settings as serialized by webauthn-rs
+    credential text NOT NULL,
+    -- Last signature counter reported by the authenticator. This column is authoritative, as the
+    -- counter inside the serialized credential isn't updated.
+    sign_count bigint NOT NULL DEFAULT 0,
+    created_at timestamp NOT NULL DEFAULT now(),
+    last_used_at timestamp
+);
+
+-- Registration & login ceremonies in progress; each one can only be completed once
+CREATE TABLE webauthn_challenges (
+    id uuid DEFAULT gen_random_uuid() PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- Either "register" or "login"
+    kind varchar(16) NOT NULL,
+    -- Ceremony state as serialized by webauthn-rs, which contains the challenge
+    state text NOT NULL,
+    expires_at timestamp NOT NULL
+);
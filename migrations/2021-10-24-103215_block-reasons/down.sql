@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE users DROP COLUMN blocked_by;
+ALTER TABLE users DROP COLUMN blocked_at;
+ALTER TABLE users DROP COLUMN blocked_reason;
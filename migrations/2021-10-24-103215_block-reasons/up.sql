@@ -0,0 +1,10 @@
+-- Why the user was blocked; shown to the user when they try to log in
+ALTER TABLE users
+    ADD COLUMN blocked_reason text;
+-- When the user was blocked
+ALTER TABLE users
+    ADD COLUMN blocked_at timestamp;
+-- Admin that blocked the user; NULL if the user was blocked automatically, e.g. after reusing a
+-- refresh token
+ALTER TABLE users
+    ADD COLUMN blocked_by uuid REFERENCES users(id) ON DELETE SET NULL;
@@ -0,0 +1,33 @@
+-- Append-only log of security-relevant actions, e.g. logins & changes made by admins
+CREATE TABLE audit_events (
+    id uuid DEFAULT gen_random_uuid() PRIMARY KEY,
+    -- User that performed the action; NULL if it wasn't performed by a logged-in user. This isn't
+    -- a foreign key, so events outlive the users they mention.
+    actor_id uuid,
+    -- What happened, e.g. "user.block"
+    action varchar(64) NOT NULL,
+    -- Kind of object the action was performed on, e.g. "user"
+    target_type varchar(32),
+    -- Identifier of the object, e.g. the user's ID
+    target_id varchar(255),
+    -- Additional information, e.g. why a user was blocked
+    details text,
+    ip_address text,
+    user_agent text,
+    created_at timestamp NOT NULL DEFAULT now()
+);
+
+CREATE INDEX audit_events_created_at_idx ON audit_events (created_at);
+CREATE INDEX audit_events_actor_id_idx ON audit_events (actor_id);
+CREATE INDEX audit_events_target_idx ON audit_events (target_type, target_id);
+
+-- Events can be removed once they're past the retention period, but never changed
+CREATE FUNCTION reject_audit_event_update() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit events can''t be modified';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_events_append_only
+    BEFORE UPDATE ON audit_events
+    FOR EACH ROW EXECUTE PROCEDURE reject_audit_event_update();
@@ -0,0 +1,4 @@
+-- Incremented whenever the user's access tokens should stop being accepted, e.g. after they've been
+-- blocked or their role changed. Access tokens carry the version they were issued with.
+ALTER TABLE users
+    ADD COLUMN token_version integer NOT NULL DEFAULT 0;
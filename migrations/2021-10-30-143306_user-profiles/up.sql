@@ -0,0 +1,16 @@
+-- Public information about a user, shown on their profile & next to their posts
+CREATE TABLE user_profiles (
+    user_id uuid PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    -- Name shown instead of the username
+    display_name varchar(64),
+    bio text,
+    -- Reference to the user's avatar, e.g. a URL
+    avatar varchar(255),
+    website varchar(255),
+    -- Links to the user's profiles on other sites
+    social_links text[] NOT NULL DEFAULT '{}',
+    -- Email address shown publicly; unrelated to the address used for password resets
+    public_email varchar(255),
+    -- When the profile was last changed
+    updated_at timestamp NOT NULL DEFAULT now()
+);
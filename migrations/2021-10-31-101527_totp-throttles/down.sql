@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM login_throttles WHERE kind = 'totp';
+
+ALTER TABLE login_throttles
+    DROP CONSTRAINT login_throttles_kind_check,
+    ADD CONSTRAINT login_throttles_kind_check CHECK (kind IN ('user', 'ip'));
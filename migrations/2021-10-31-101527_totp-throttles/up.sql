@@ -0,0 +1,4 @@
+-- Wrong two-factor codes are throttled per user ID as well
+ALTER TABLE login_throttles
+    DROP CONSTRAINT login_throttles_kind_check,
+    ADD CONSTRAINT login_throttles_kind_check CHECK (kind IN ('user', 'ip', 'totp'));
@@ -0,0 +1,4 @@
+-- Password reset requests are throttled per IP address as well
+ALTER TABLE login_throttles
+    DROP CONSTRAINT login_throttles_kind_check,
+    ADD CONSTRAINT login_throttles_kind_check CHECK (kind IN ('user', 'ip', 'totp', 'reset'));
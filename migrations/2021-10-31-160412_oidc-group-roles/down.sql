@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE oidc_identities
+    DROP COLUMN role_from_groups;
@@ -0,0 +1,4 @@
+-- Whether the identity's user was made an admin because of their groups at the provider, in which
+-- case they lose the role again once they leave those groups
+ALTER TABLE oidc_identities
+    ADD COLUMN role_from_groups boolean NOT NULL DEFAULT false;
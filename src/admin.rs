@@ -1,58 +1,964 @@
-use diesel::PgConnection;
-use rocket::serde::json::Json;
+use diesel::{Connection, PgConnection};
+use rocket::{serde::json::Json, State};
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::{
-    auth::pass::hash_password,
-    db,
+    auth::{
+        invites,
+        pass::{self, hash_password},
+        permissions::{Permission, SectionLevel, SectionsWrite, UsersRead},
+        revocation::TokenVersions,
+    },
+    db::{
+        self,
+        audit_events::{Actor, Target},
+    },
     errors::{RbError, RbResult},
-    guards::Admin,
-    RbDbConn,
+    guards::{Admin, Authorized, ClientInfo, Principal},
+    RbConfig, RbDbConn,
 };
 
-// #[get("/users")]
-// pub async fn get_users(_admin: Admin, conn: RbDbConn) -> RbResult<Json<Vec<db::User>>>
-// {
-//     Ok(Json(conn.run(|c| db::users::all(c)).await?))
-// }
+/// Makes sure the given role exists, as the database would otherwise just return a generic error.
+pub fn check_role_exists(conn: &PgConnection, role: &str) -> RbResult<()>
+{
+    db::roles::find(conn, role)?
+        .map(|_| ())
+        .ok_or(RbError::UMUnknownRole)
+}
+
+/// Finds a user given either their ID or their username.
+pub fn resolve_user(conn: &PgConnection, id_or_username: &str) -> RbResult<db::User>
+{
+    let user = match Uuid::parse_str(id_or_username) {
+        Ok(user_id) => db::users::find(conn, user_id),
+        Err(_) => db::users::find_by_username(conn, id_or_username)?,
+    };
+
+    user.ok_or(RbError::UMUnknownUser)
+}
+
+/// Lists users ordered by username. At most 500 users are returned at once; 50 if no limit is
+/// given.
+///
+/// # Arguments
+///
+/// * `_auth` - guard ensuring the user is allowed to view users
+/// * `conn` - guard providing a connection to the database
+/// * `search` - only return users whose username contains this, ignoring case
+/// * `offset` - amount of users to skip
+/// * `limit` - maximum amount of users to return
+#[get("/users?<search>&<offset>&<limit>")]
+pub async fn get_users(
+    _auth: Authorized<UsersRead>,
+    conn: RbDbConn,
+    search: Option<String>,
+    offset: Option<u32>,
+    limit: Option<u32>,
+) -> RbResult<Json<Vec<db::User>>>
+{
+    let offset = offset.unwrap_or(0);
+    let limit = limit.unwrap_or(50).min(500);
+
+    conn.run(move |c| db::users::get(c, search.as_deref(), offset, limit))
+        .await
+        .map(Json)
+}
 
 #[post("/users", data = "<user>")]
-pub async fn create_user(_admin: Admin, conn: RbDbConn, user: Json<db::NewUser>) -> RbResult<()>
+pub async fn create_user(
+    admin: Admin,
+    conn: RbDbConn,
+    conf: &State<RbConfig>,
+    client: ClientInfo,
+    user: Json<db::NewUser>,
+) -> RbResult<Json<db::User>>
 {
-    Ok(conn
-        .run(move |c| db::users::create(c, &user.into_inner()))
-        .await?)
+    let mut user = user.into_inner();
+
+    db::users::check_username(&user.username)?;
+    pass::check_password(&user.password)?;
+    user.password = hash_password(&conf.argon2, &user.password)?;
+    user.email = user.email.map(|email| email.trim().to_lowercase());
+
+    conn.run(move |c| {
+        c.transaction::<_, RbError, _>(|| {
+            if let Some(role) = &user.role {
+                check_role_exists(c, role)?;
+            }
+
+            let created = db::users::create(c, &user)?;
+
+            db::audit_events::record(
+                c,
+                &client.actor(Some(admin.0.id)),
+                "user.create",
+                Some(Target::User(created.id)),
+                None,
+            )?;
+
+            Ok(created)
+        })
+    })
+    .await
+    .map(Json)
 }
 
-#[get("/users/<user_id_str>")]
+/// Returns a single user.
+///
+/// # Arguments
+///
+/// * `_auth` - guard ensuring the user is allowed to view users
+/// * `conn` - guard providing a connection to the database
+/// * `id_or_username` - ID or username of the user
+#[get("/users/<id_or_username>")]
 pub async fn get_user_info(
-    _admin: Admin,
+    _auth: Authorized<UsersRead>,
+    conn: RbDbConn,
+    id_or_username: String,
+) -> RbResult<Json<db::User>>
+{
+    conn.run(move |c| resolve_user(c, &id_or_username))
+        .await
+        .map(Json)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserPatch
+{
+    #[serde(flatten)]
+    changes: db::PatchUser,
+    /// Blocks or unblocks the user
+    blocked: Option<bool>,
+    /// Required when blocking the user
+    blocked_reason: Option<String>,
+}
+
+/// Updates a user's username, role, email address or whether they're blocked, returning the updated
+/// user. Changing the username or role revokes the user's access tokens, while blocking the user
+/// revokes all of their sessions as well.
+///
+/// # Arguments
+///
+/// * `admin` - guard ensuring the user is an admin
+/// * `conn` - guard providing a connection to the database
+/// * `versions` - cached token versions
+/// * `client` - information about the client making the request
+/// * `id_or_username` - ID or username of the user to update
+/// * `patch` - the changes to apply
+#[patch("/users/<id_or_username>", data = "<patch>")]
+pub async fn patch_user(
+    admin: Admin,
     conn: RbDbConn,
-    user_id_str: &str,
+    versions: &State<TokenVersions>,
+    client: ClientInfo,
+    id_or_username: String,
+    patch: Json<UserPatch>,
 ) -> RbResult<Json<db::User>>
 {
-    let user_id = Uuid::parse_str(user_id_str).map_err(|_| RbError::UMUnknownUser)?;
+    let UserPatch {
+        mut changes,
+        blocked,
+        blocked_reason,
+    } = patch.into_inner();
+
+    if let Some(username) = &changes.username {
+        db::users::check_username(username)?;
+    }
 
-    match conn.run(move |c| db::users::find(c, user_id)).await {
-        Some(user) => Ok(Json(user)),
-        None => Err(RbError::UMUnknownUser),
+    changes.email = changes.email.map(|email| email.trim().to_lowercase());
+    let reason = blocked_reason
+        .map(|reason| reason.trim().to_string())
+        .filter(|reason| !reason.is_empty());
+
+    if blocked == Some(true) && reason.is_none() {
+        return Err(RbError::UMMissingBlockReason);
     }
+
+    let user = conn
+        .run(move |c| {
+            c.transaction::<_, RbError, _>(|| {
+                let mut user = resolve_user(c, &id_or_username)?;
+                let actor = client.actor(Some(admin.0.id));
+
+                // Otherwise, an admin could lock themselves out
+                if blocked == Some(true) && user.id == admin.0.id {
+                    return Err(RbError::UMBlockSelf);
+                }
+
+                if let Some(role) = &changes.role {
+                    // Otherwise, an admin could take away their own permission to manage users,
+                    // possibly leaving nobody who can
+                    if user.id == admin.0.id && *role != user.role {
+                        return Err(RbError::UMChangeOwnRole);
+                    }
+
+                    check_role_exists(c, role)?;
+                }
+
+                let mut changed = Vec::new();
+
+                if changes.username.is_some() {
+                    changed.push("username");
+                }
+
+                if changes.role.is_some() {
+                    changed.push("role");
+                }
+
+                if changes.email.is_some() {
+                    changed.push("email");
+                }
+
+                // Diesel refuses to run an update without any changes
+                if !changed.is_empty() {
+                    user = db::users::update(c, user.id, &changes)?;
+
+                    db::audit_events::record(
+                        c,
+                        &actor,
+                        "user.update",
+                        Some(Target::User(user.id)),
+                        Some(changed.join(", ")),
+                    )?;
+                }
+
+                match (blocked, reason) {
+                    (Some(true), Some(reason)) if !user.blocked => {
+                        db::users::block(c, user.id, &reason, &actor)?
+                    },
+                    (Some(false), _) if user.blocked => {
+                        db::users::unblock(c, user.id)?;
+
+                        db::audit_events::record(
+                            c,
+                            &actor,
+                            "user.unblock",
+                            Some(Target::User(user.id)),
+                            None,
+                        )?;
+                    },
+                    _ => return Ok(user),
+                }
+
+                db::users::find(c, user.id).ok_or(RbError::UMUnknownUser)
+            })
+        })
+        .await?;
+    versions.forget(user.id);
+
+    Ok(Json(user))
 }
 
-pub fn create_admin_user(conn: &PgConnection, username: &str, password: &str) -> RbResult<bool>
+/// Removes a user. Their sessions, personal access tokens, security keys & section grants are
+/// removed along with them, & their access tokens stop being accepted. Their posts are kept, but
+/// lose their author.
+///
+/// # Arguments
+///
+/// * `admin` - guard ensuring the user is an admin
+/// * `conn` - guard providing a connection to the database
+/// * `versions` - cached token versions
+/// * `client` - information about the client making the request
+/// * `id_or_username` - ID or username of the user to remove
+#[delete("/users/<id_or_username>")]
+pub async fn delete_user(
+    admin: Admin,
+    conn: RbDbConn,
+    versions: &State<TokenVersions>,
+    client: ClientInfo,
+    id_or_username: String,
+) -> RbResult<()>
 {
-    let pass_hashed = hash_password(password)?;
+    let user_id = conn
+        .run(move |c| {
+            c.transaction::<_, RbError, _>(|| {
+                let user = resolve_user(c, &id_or_username)?;
+
+                // Otherwise, the last admin could remove themselves
+                if user.id == admin.0.id {
+                    return Err(RbError::UMDeleteSelf);
+                }
+
+                db::users::delete(c, user.id)?;
+
+                db::audit_events::record(
+                    c,
+                    &client.actor(Some(admin.0.id)),
+                    "user.delete",
+                    Some(Target::User(user.id)),
+                    Some(user.username),
+                )?;
+
+                Ok(user.id)
+            })
+        })
+        .await?;
+    versions.forget(user_id);
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PasswordReset
+{
+    /// Password the user can log in with once, after which they have to change it
+    temporary_password: String,
+}
+
+/// Replaces a user's password with a temporary one, which they have to change before being able to
+/// log in. All of the user's sessions are revoked.
+///
+/// # Arguments
+///
+/// * `admin` - guard ensuring the user is an admin
+/// * `conn` - guard providing a connection to the database
+/// * `conf` - application configuration
+/// * `versions` - cached token versions
+/// * `client` - information about the client making the request
+/// * `user_id` - ID of the user whose password should be reset
+#[post("/users/<user_id>/password")]
+pub async fn reset_password(
+    admin: Admin,
+    conn: RbDbConn,
+    conf: &State<RbConfig>,
+    versions: &State<TokenVersions>,
+    client: ClientInfo,
+    user_id: Uuid,
+) -> RbResult<Json<PasswordReset>>
+{
+    let temporary_password = pass::generate_password();
+    let password = temporary_password.clone();
+    let argon2 = conf.argon2.clone();
+
+    conn.run(move |c| {
+        c.transaction::<_, RbError, _>(|| {
+            db::users::find(c, user_id).ok_or(RbError::UMUnknownUser)?;
+
+            pass::change_password(c, &argon2, user_id, &password, true)?;
+
+            db::audit_events::record(
+                c,
+                &client.actor(Some(admin.0.id)),
+                "user.password_reset",
+                Some(Target::User(user_id)),
+                None,
+            )
+        })
+    })
+    .await?;
+    versions.forget(user_id);
+
+    Ok(Json(PasswordReset { temporary_password }))
+}
+
+#[derive(Serialize)]
+pub struct RoleInfo
+{
+    #[serde(flatten)]
+    role: db::Role,
+    permissions: Vec<String>,
+}
+
+/// Lists all roles, along with the permissions they grant.
+///
+/// # Arguments
+///
+/// * `_auth` - guard ensuring the user is allowed to view users
+/// * `conn` - guard providing a connection to the database
+#[get("/roles")]
+pub async fn get_roles(
+    _auth: Authorized<UsersRead>,
+    conn: RbDbConn,
+) -> RbResult<Json<Vec<RoleInfo>>>
+{
+    conn.run(|c| {
+        db::roles::all(c)?
+            .into_iter()
+            .map(|role| {
+                let permissions = db::roles::permissions(c, &role.name)?;
+
+                Ok(RoleInfo { role, permissions })
+            })
+            .collect::<RbResult<Vec<_>>>()
+    })
+    .await
+    .map(Json)
+}
+
+#[derive(Deserialize)]
+pub struct RoleChange
+{
+    role: String,
+}
+
+/// Changes a user's role. The user's current access tokens are revoked, so the new permissions
+/// apply as soon as they refresh their tokens.
+///
+/// # Arguments
+///
+/// * `admin` - guard ensuring the user is an admin
+/// * `conn` - guard providing a connection to the database
+/// * `versions` - cached token versions
+/// * `client` - information about the client making the request
+/// * `user_id` - ID of the user to update
+/// * `change` - the new role
+#[put("/users/<user_id>/role", data = "<change>")]
+pub async fn set_user_role(
+    admin: Admin,
+    conn: RbDbConn,
+    versions: &State<TokenVersions>,
+    client: ClientInfo,
+    user_id: Uuid,
+    change: Json<RoleChange>,
+) -> RbResult<()>
+{
+    conn.run(move |c| {
+        c.transaction::<_, RbError, _>(|| {
+            let user = db::users::find(c, user_id).ok_or(RbError::UMUnknownUser)?;
+
+            // Otherwise, an admin could take away their own permission to manage users, possibly
+            // leaving nobody who can
+            if user.id == admin.0.id && change.role != user.role {
+                return Err(RbError::UMChangeOwnRole);
+            }
+
+            check_role_exists(c, &change.role)?;
+
+            db::users::set_role(c, user_id, &change.role)?;
+
+            db::audit_events::record(
+                c,
+                &client.actor(Some(admin.0.id)),
+                "user.role",
+                Some(Target::User(user_id)),
+                Some(change.into_inner().role),
+            )
+        })
+    })
+    .await?;
+    versions.forget(user_id);
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct BlockRequest
+{
+    /// Why the user is being blocked; shown to the user when they try to log in
+    reason: String,
+}
+
+/// Blocks a user, preventing them from logging in or refreshing their tokens. All of the user's
+/// sessions & access tokens are revoked.
+///
+/// # Arguments
+///
+/// * `admin` - guard ensuring the user is an admin
+/// * `conn` - guard providing a connection to the database
+/// * `versions` - cached token versions
+/// * `client` - information about the client making the request
+/// * `user_id` - ID of the user to block
+/// * `request` - why the user is being blocked
+#[post("/users/<user_id>/block", data = "<request>")]
+pub async fn block_user(
+    admin: Admin,
+    conn: RbDbConn,
+    versions: &State<TokenVersions>,
+    client: ClientInfo,
+    user_id: Uuid,
+    request: Json<BlockRequest>,
+) -> RbResult<()>
+{
+    let reason = request.into_inner().reason.trim().to_string();
+
+    if reason.is_empty() {
+        return Err(RbError::UMMissingBlockReason);
+    }
+
+    // Otherwise, an admin could lock themselves out
+    if user_id == admin.0.id {
+        return Err(RbError::UMBlockSelf);
+    }
+
+    conn.run(move |c| {
+        db::users::find(c, user_id).ok_or(RbError::UMUnknownUser)?;
+
+        db::users::block(c, user_id, &reason, &client.actor(Some(admin.0.id)))
+    })
+    .await?;
+    versions.forget(user_id);
+
+    Ok(())
+}
+
+/// Unblocks a user, allowing them to log in again.
+///
+/// # Arguments
+///
+/// * `admin` - guard ensuring the user is an admin
+/// * `conn` - guard providing a connection to the database
+/// * `client` - information about the client making the request
+/// * `user_id` - ID of the user to unblock
+#[delete("/users/<user_id>/block")]
+pub async fn unblock_user(
+    admin: Admin,
+    conn: RbDbConn,
+    client: ClientInfo,
+    user_id: Uuid,
+) -> RbResult<()>
+{
+    conn.run(move |c| {
+        c.transaction::<_, RbError, _>(|| {
+            db::users::find(c, user_id).ok_or(RbError::UMUnknownUser)?;
+
+            db::users::unblock(c, user_id)?;
+
+            db::audit_events::record(
+                c,
+                &client.actor(Some(admin.0.id)),
+                "user.unblock",
+                Some(Target::User(user_id)),
+                None,
+            )
+        })
+    })
+    .await
+}
+
+/// Section managers can grant & revoke `write` & `edit-any` access to their section, while
+/// `manage` access can only be granted & revoked by users allowed to manage every section.
+fn check_can_grant(
+    conn: &PgConnection,
+    principal: &Principal,
+    section_id: Uuid,
+    level: SectionLevel,
+) -> RbResult<()>
+{
+    if level == SectionLevel::Manage {
+        principal.check(SectionsWrite::NAME)
+    } else {
+        principal.check_section(conn, section_id, SectionLevel::Manage)
+    }
+}
+
+/// Lists the users that have been granted access to the given section.
+///
+/// # Arguments
+///
+/// * `principal` - user making the request; must be allowed to manage the section
+/// * `conn` - guard providing a connection to the database
+/// * `section_id` - ID of the section
+#[get("/sections/<section_id>/grants")]
+pub async fn get_section_grants(
+    principal: Principal,
+    conn: RbDbConn,
+    section_id: Uuid,
+) -> RbResult<Json<Vec<db::SectionGrant>>>
+{
+    conn.run(move |c| {
+        principal.check_section(c, section_id, SectionLevel::Manage)?;
+
+        db::section_grants::find_by_section(c, section_id)
+    })
+    .await
+    .map(Json)
+}
+
+#[derive(Deserialize)]
+pub struct SectionGrantRequest
+{
+    level: SectionLevel,
+}
+
+/// Grants a user access to a section. If the user already has access, their level of access is
+/// replaced.
+///
+/// # Arguments
+///
+/// * `principal` - user making the request; must be allowed to manage the section
+/// * `conn` - guard providing a connection to the database
+/// * `client` - information about the client making the request
+/// * `section_id` - ID of the section
+/// * `user_id` - ID of the user to grant access to
+/// * `grant` - level of access to grant
+#[put("/sections/<section_id>/grants/<user_id>", data = "<grant>")]
+pub async fn grant_section_access(
+    principal: Principal,
+    conn: RbDbConn,
+    client: ClientInfo,
+    section_id: Uuid,
+    user_id: Uuid,
+    grant: Json<SectionGrantRequest>,
+) -> RbResult<Json<db::SectionGrant>>
+{
+    let level = grant.level;
+
+    conn.run(move |c| {
+        c.transaction::<_, RbError, _>(|| {
+            check_can_grant(c, &principal, section_id, level)?;
+
+            // Downgrading a manager requires the same access as granting manage access
+            if let Some(existing) = db::section_grants::find(c, user_id, section_id)? {
+                if let Some(existing_level) = SectionLevel::from_name(&existing.level) {
+                    check_can_grant(c, &principal, section_id, existing_level)?;
+                }
+            }
+
+            db::sections::find(c, section_id)?.ok_or(RbError::SMUnknownSection)?;
+            db::users::find(c, user_id).ok_or(RbError::UMUnknownUser)?;
+
+            let grant = db::section_grants::upsert(
+                c,
+                &db::NewSectionGrant {
+                    user_id,
+                    section_id,
+                    level: level.name().to_string(),
+                },
+            )?;
+
+            db::audit_events::record(
+                c,
+                &client.actor(Some(principal.id)),
+                "section.grant",
+                Some(Target::Section(section_id)),
+                Some(format!("{} access for user {}", level.name(), user_id)),
+            )?;
+
+            Ok(grant)
+        })
+    })
+    .await
+    .map(Json)
+}
+
+/// Revokes a user's access to a section.
+///
+/// # Arguments
+///
+/// * `principal` - user making the request; must be allowed to manage the section
+/// * `conn` - guard providing a connection to the database
+/// * `client` - information about the client making the request
+/// * `section_id` - ID of the section
+/// * `user_id` - ID of the user whose access should be revoked
+#[delete("/sections/<section_id>/grants/<user_id>")]
+pub async fn revoke_section_access(
+    principal: Principal,
+    conn: RbDbConn,
+    client: ClientInfo,
+    section_id: Uuid,
+    user_id: Uuid,
+) -> RbResult<()>
+{
+    conn.run(move |c| {
+        c.transaction::<_, RbError, _>(|| {
+            // Only managers get to know whether a grant exists
+            principal.check_section(c, section_id, SectionLevel::Manage)?;
+
+            let grant =
+                db::section_grants::find(c, user_id, section_id)?.ok_or(RbError::SMUnknownGrant)?;
+
+            if let Some(level) = SectionLevel::from_name(&grant.level) {
+                check_can_grant(c, &principal, section_id, level)?;
+            }
+
+            db::section_grants::delete(c, user_id, section_id)?;
+
+            db::audit_events::record(
+                c,
+                &client.actor(Some(principal.id)),
+                "section.revoke",
+                Some(Target::Section(section_id)),
+                Some(format!("{} access for user {}", grant.level, user_id)),
+            )
+        })
+    })
+    .await
+}
+
+/// Lists the usernames & IP addresses whose login attempts are currently being delayed or locked
+/// out.
+///
+/// # Arguments
+///
+/// * `_auth` - guard ensuring the user is allowed to view users
+/// * `conn` - guard providing a connection to the database
+#[get("/lockouts")]
+pub async fn get_lockouts(
+    _auth: Authorized<UsersRead>,
+    conn: RbDbConn,
+) -> RbResult<Json<Vec<db::LoginThrottle>>>
+{
+    conn.run(|c| db::login_throttles::all_locked(c))
+        .await
+        .map(Json)
+}
+
+/// Clears the failed login attempts of a username or IP address, a user's wrong two-factor codes or
+/// an IP address's password reset requests, lifting any lockout.
+///
+/// # Arguments
+///
+/// * `admin` - guard ensuring the user is an admin
+/// * `conn` - guard providing a connection to the database
+/// * `client` - information about the client making the request
+/// * `kind` - either "user", "ip", "totp" or "reset"
+/// * `subject` - the username, IP address or user ID
+#[delete("/lockouts/<kind>/<subject>")]
+pub async fn clear_lockout(
+    admin: Admin,
+    conn: RbDbConn,
+    client: ClientInfo,
+    kind: String,
+    subject: String,
+) -> RbResult<()>
+{
+    conn.run(move |c| {
+        c.transaction::<_, RbError, _>(|| {
+            if !db::login_throttles::delete(c, &kind, &subject)? {
+                return Err(RbError::AuthUnknownLockout);
+            }
+
+            db::audit_events::record(
+                c,
+                &client.actor(Some(admin.0.id)),
+                "lockout.clear",
+                Some(Target::Lockout(kind, subject)),
+                None,
+            )
+        })
+    })
+    .await
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InviteRequest
+{
+    role: Option<String>,
+    max_uses: Option<i32>,
+    expires_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InviteResponse
+{
+    #[serde(flatten)]
+    info: db::Invite,
+    /// The actual invite code; this is the only time it's returned
+    code: String,
+}
+
+/// Lists all invites, including used up & expired ones.
+///
+/// # Arguments
+///
+/// * `_auth` - guard ensuring the user is allowed to view users
+/// * `conn` - guard providing a connection to the database
+#[get("/invites")]
+pub async fn get_invites(
+    _auth: Authorized<UsersRead>,
+    conn: RbDbConn,
+) -> RbResult<Json<Vec<db::Invite>>>
+{
+    conn.run(|c| db::invites::all(c)).await.map(Json)
+}
+
+/// Creates a new invite. Users registering with it get the given role, or the default role if none
+/// is given.
+///
+/// # Arguments
+///
+/// * `admin` - guard ensuring the user is an admin
+/// * `conn` - guard providing a connection to the database
+/// * `client` - information about the client making the request
+/// * `invite` - role, maximum amount of uses & optional expiry date of the invite
+#[post("/invites", data = "<invite>")]
+pub async fn create_invite(
+    admin: Admin,
+    conn: RbDbConn,
+    client: ClientInfo,
+    invite: Json<InviteRequest>,
+) -> RbResult<Json<InviteResponse>>
+{
+    let invite = invite.into_inner();
+
+    if matches!(invite.max_uses, Some(max_uses) if max_uses < 1) {
+        return Err(RbError::UMInvalidInvite);
+    }
+
+    let (code, code_hash) = invites::generate_code();
+
+    let info = conn
+        .run(move |c| {
+            c.transaction::<_, RbError, _>(|| {
+                if let Some(role) = &invite.role {
+                    check_role_exists(c, role)?;
+                }
+
+                let info = db::invites::create(
+                    c,
+                    &db::NewInvite {
+                        code_hash,
+                        role: invite.role,
+                        max_uses: invite.max_uses,
+                        created_by: admin.0.id,
+                        expires_at: invite.expires_at,
+                    },
+                )?;
+
+                db::audit_events::record(
+                    c,
+                    &client.actor(Some(admin.0.id)),
+                    "invite.create",
+                    Some(Target::Invite(info.id)),
+                    None,
+                )?;
+
+                Ok(info)
+            })
+        })
+        .await?;
+
+    Ok(Json(InviteResponse { info, code }))
+}
+
+/// Removes an invite, so it can't be used anymore. Users who already registered using it are kept.
+///
+/// # Arguments
+///
+/// * `admin` - guard ensuring the user is an admin
+/// * `conn` - guard providing a connection to the database
+/// * `client` - information about the client making the request
+/// * `invite_id` - ID of the invite to remove
+#[delete("/invites/<invite_id>")]
+pub async fn delete_invite(
+    admin: Admin,
+    conn: RbDbConn,
+    client: ClientInfo,
+    invite_id: Uuid,
+) -> RbResult<()>
+{
+    conn.run(move |c| {
+        c.transaction::<_, RbError, _>(|| {
+            if !db::invites::delete(c, invite_id)? {
+                return Err(RbError::UMUnknownInvite);
+            }
+
+            db::audit_events::record(
+                c,
+                &client.actor(Some(admin.0.id)),
+                "invite.delete",
+                Some(Target::Invite(invite_id)),
+                None,
+            )
+        })
+    })
+    .await
+}
+
+/// Query parameters used to filter the audit log
+#[derive(FromForm)]
+pub struct AuditQuery
+{
+    actor: Option<Uuid>,
+    action: Option<String>,
+    #[field(name = "targetType")]
+    target_type: Option<String>,
+    #[field(name = "targetId")]
+    target_id: Option<String>,
+    /// Only return events created at or after this time, e.g. "2021-10-26T18:15:02"
+    since: Option<String>,
+    /// Only return events created before this time
+    until: Option<String>,
+    offset: Option<u32>,
+    limit: Option<u32>,
+}
+
+/// Parses an optional date used to filter the audit log.
+fn parse_audit_date(date: Option<String>) -> RbResult<Option<chrono::NaiveDateTime>>
+{
+    date.map(|date| date.parse().map_err(|_| RbError::ALInvalidFilter))
+        .transpose()
+}
+
+/// Returns the audit log events matching the given filter, newest first. At most 500 events are
+/// returned at once; 50 if no limit is given.
+///
+/// # Arguments
+///
+/// * `_admin` - guard ensuring the user is an admin
+/// * `conn` - guard providing a connection to the database
+/// * `query` - filter & pagination parameters
+#[get("/audit?<query..>")]
+pub async fn get_audit_events(
+    _admin: Admin,
+    conn: RbDbConn,
+    query: AuditQuery,
+) -> RbResult<Json<Vec<db::AuditEvent>>>
+{
+    let filter = db::audit_events::AuditFilter {
+        actor_id: query.actor,
+        action: query.action,
+        target_type: query.target_type,
+        target_id: query.target_id,
+        since: parse_audit_date(query.since)?,
+        until: parse_audit_date(query.until)?,
+    };
+    let offset = query.offset.unwrap_or(0);
+    let limit = query.limit.unwrap_or(50).min(500);
+
+    conn.run(move |c| db::audit_events::find(c, &filter, offset, limit))
+        .await
+        .map(Json)
+}
+
+/// Creates the initial admin user, unless an admin already exists. Existing users are never
+/// modified. Returns whether the user was created.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `username` - username of the new admin
+/// * `password_hash` - hash of the new admin's password
+/// * `actor` - who is creating the admin
+pub fn create_admin_user(
+    conn: &PgConnection,
+    username: &str,
+    password_hash: String,
+    actor: &Actor,
+) -> RbResult<bool>
+{
+    if db::users::admin_exists(conn)? {
+        return Ok(false);
+    }
+
     let new_user = db::NewUser {
         username: username.to_string(),
-        password: pass_hashed,
-        admin: true,
+        password: password_hash,
+        email: None,
+        role: Some(db::roles::ADMIN.to_string()),
     };
 
-    if db::users::find_by_username(conn, username).is_ok() {
-        db::users::create(conn, &new_user);
-    }
-    // db::users::create_or_update(conn, &new_user)
-    //     .map_err(|_| RbError::Custom("Couldn't create admin."))?;
+    let res = conn.transaction::<_, RbError, _>(|| {
+        let user = db::users::create(conn, &new_user)?;
 
-    Ok(true)
+        db::audit_events::record(
+            conn,
+            actor,
+            "user.bootstrap",
+            Some(Target::User(user.id)),
+            None,
+        )
+    });
+
+    match res {
+        Ok(()) => Ok(true),
+        // Another instance might've created the admin in the meantime
+        Err(RbError::UMDuplicateUser) if db::users::admin_exists(conn)? => Ok(false),
+        Err(err) => Err(err),
+    }
 }
@@ -0,0 +1,164 @@
+//! Cookie mode allows browser clients to store their tokens in HttpOnly cookies instead of
+//! JavaScript-accessible storage. As browsers send these cookies automatically, state-changing
+//! requests have to prove they were made by the frontend itself by copying the CSRF cookie's value
+//! into the `X-CSRF-Token` header (the double-submit pattern).
+
+use rand::{thread_rng, Rng};
+use rocket::{
+    http::{Cookie, CookieJar, Method, SameSite},
+    request::Request,
+};
+use serde::Serialize;
+
+use super::jwt::JWTResponse;
+use crate::RbConfig;
+
+/// Cookie containing the access token
+pub const ACCESS_COOKIE: &str = "rb_access";
+/// Cookie containing the refresh token; it's only sent to the authentication routes
+pub const REFRESH_COOKIE: &str = "rb_refresh";
+/// Cookie containing the CSRF token; this one can be read by JavaScript
+pub const CSRF_COOKIE: &str = "rb_csrf";
+/// Header state-changing requests have to copy the CSRF token into
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+/// Path the access token cookie is sent to
+const ACCESS_PATH: &str = "/api";
+/// Path the refresh token cookie is sent to
+const REFRESH_PATH: &str = "/api/auth";
+/// Amount of random bytes in a CSRF token
+const CSRF_TOKEN_SIZE: usize = 32;
+
+/// Response to a login or refresh in cookie mode. The tokens themselves are only sent as cookies.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CookieSession
+{
+    /// Same value as the CSRF cookie, for convenience
+    csrf_token: String,
+}
+
+/// Newly issued tokens, returned either in the response body or as cookies.
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum TokenResponse
+{
+    Body(JWTResponse),
+    Cookies(CookieSession),
+}
+
+impl TokenResponse
+{
+    /// Returns the tokens in the response body, or sets them as cookies if requested.
+    ///
+    /// # Arguments
+    ///
+    /// * `tokens` - the newly issued tokens
+    /// * `jar` - the request's cookies
+    /// * `conf` - application configuration
+    /// * `use_cookies` - whether the client wants to use cookie mode
+    pub fn new(tokens: JWTResponse, jar: &CookieJar<'_>, conf: &RbConfig, use_cookies: bool)
+        -> Self
+    {
+        if use_cookies {
+            Self::Cookies(set(jar, conf, tokens))
+        } else {
+            Self::Body(tokens)
+        }
+    }
+}
+
+fn build(
+    name: &'static str,
+    value: String,
+    path: &'static str,
+    max_age: i64,
+    secure: bool,
+) -> Cookie<'static>
+{
+    Cookie::build(name, value)
+        .path(path)
+        .secure(secure)
+        .http_only(name != CSRF_COOKIE)
+        .same_site(SameSite::Strict)
+        .max_age(time::Duration::seconds(max_age))
+        .finish()
+}
+
+/// Stores the tokens in cookies, along with a new CSRF token.
+///
+/// # Arguments
+///
+/// * `jar` - the request's cookies
+/// * `conf` - application configuration
+/// * `tokens` - the tokens to store
+pub fn set(jar: &CookieJar<'_>, conf: &RbConfig, tokens: JWTResponse) -> CookieSession
+{
+    let secure = conf.cookies.secure;
+
+    let mut bytes = [0u8; CSRF_TOKEN_SIZE];
+    thread_rng().fill(&mut bytes[..]);
+    let csrf_token = base64::encode_config(bytes, base64::URL_SAFE_NO_PAD);
+
+    jar.add(build(
+        ACCESS_COOKIE,
+        tokens.token,
+        ACCESS_PATH,
+        conf.jwt.access_token_expire,
+        secure,
+    ));
+    jar.add(build(
+        REFRESH_COOKIE,
+        tokens.refresh_token,
+        REFRESH_PATH,
+        conf.jwt.refresh_token_expire,
+        secure,
+    ));
+    jar.add(build(
+        CSRF_COOKIE,
+        csrf_token.clone(),
+        "/",
+        conf.jwt.refresh_token_expire,
+        secure,
+    ));
+
+    CookieSession { csrf_token }
+}
+
+/// Removes all authentication cookies.
+///
+/// # Arguments
+///
+/// * `jar` - the request's cookies
+pub fn clear(jar: &CookieJar<'_>)
+{
+    // Cookies can only be removed using the path they were set with
+    jar.remove(Cookie::build(ACCESS_COOKIE, "").path(ACCESS_PATH).finish());
+    jar.remove(
+        Cookie::build(REFRESH_COOKIE, "")
+            .path(REFRESH_PATH)
+            .finish(),
+    );
+    jar.remove(Cookie::build(CSRF_COOKIE, "").path("/").finish());
+}
+
+/// Checks whether a request authenticated using cookies has a valid CSRF token. Safe requests,
+/// which shouldn't change anything, don't need one.
+///
+/// # Arguments
+///
+/// * `req` - the request to check
+pub fn check_csrf(req: &Request<'_>) -> bool
+{
+    if matches!(req.method(), Method::Get | Method::Head | Method::Options) {
+        return true;
+    }
+
+    match (
+        req.cookies().get(CSRF_COOKIE),
+        req.headers().get_one(CSRF_HEADER),
+    ) {
+        (Some(cookie), Some(header)) => !header.is_empty() && cookie.value() == header,
+        _ => false,
+    }
+}
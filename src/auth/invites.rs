@@ -0,0 +1,58 @@
+//! Invites allow people to register an account themselves, instead of an admin having to create it
+//! for them. An invite can give new users a specific role, & can be limited in how often & how long
+//! it can be used.
+
+use rocket::{serde::json::Json, State};
+use serde::Deserialize;
+
+use super::{hash_token, pass, random_token};
+use crate::{db, errors::RbResult, RbConfig, RbDbConn};
+
+/// Amount of random bytes in an invite code
+const CODE_SIZE: usize = 24;
+
+/// Generates a new invite code, returning the code along with its hash.
+pub fn generate_code() -> (String, Vec<u8>)
+{
+    random_token(CODE_SIZE)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Registration
+{
+    invite_code: String,
+    username: String,
+    password: String,
+}
+
+/// Registers a new user using an invite code. The user gets the role specified by the invite.
+///
+/// # Arguments
+///
+/// * `conn` - guard providing a connection to the database
+/// * `conf` - application configuration
+/// * `registration` - invite code, username & password of the new user
+#[post("/register", data = "<registration>")]
+pub async fn register(
+    conn: RbDbConn,
+    conf: &State<RbConfig>,
+    registration: Json<Registration>,
+) -> RbResult<()>
+{
+    let registration = registration.into_inner();
+
+    db::users::check_username(&registration.username)?;
+    pass::check_password(&registration.password)?;
+
+    let code_hash = hash_token(&registration.invite_code);
+    let new_user = db::NewUser {
+        username: registration.username,
+        password: pass::hash_password(&conf.argon2, &registration.password)?,
+        role: None,
+        email: None,
+    };
+
+    conn.run(move |c| db::invites::redeem(c, &code_hash, new_user))
+        .await
+}
@@ -1,14 +1,13 @@
 use chrono::Utc;
-use diesel::PgConnection;
-use hmac::{Hmac, NewMac};
-use jwt::SignWithKey;
+use diesel::{Connection, PgConnection};
 use rand::{thread_rng, Rng};
 use serde::{Deserialize, Serialize};
-use sha2::Sha256;
 
+use super::keys::JwtKeys;
 use crate::{
     db,
     errors::{RbError, RbResult},
+    guards::ClientInfo,
     RbJwtConf,
 };
 
@@ -16,8 +15,8 @@ use crate::{
 #[serde(rename_all = "camelCase")]
 pub struct JWTResponse
 {
-    token: String,
-    refresh_token: String,
+    pub token: String,
+    pub refresh_token: String,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -25,33 +24,119 @@ pub struct Claims
 {
     pub id: uuid::Uuid,
     pub username: String,
-    pub admin: bool,
+    /// Role of the user at the time the token was issued
+    pub role: String,
+    /// Permissions granted by the user's role
+    pub permissions: Vec<String>,
+    /// Token version of the user at the time the token was issued
+    pub ver: i32,
+    /// Expiration time
     pub exp: i64,
+    /// Time at which the token was issued
+    pub iat: i64,
+    /// Time before which the token must not be accepted
+    pub nbf: i64,
+    /// Issuer of the token
+    pub iss: String,
+    /// Intended recipient of the token
+    pub aud: String,
+    /// Unique ID of the token
+    pub jti: uuid::Uuid,
 }
 
+impl Claims
+{
+    /// Validates the time-based claims, allowing for the configured clock skew, and checks whether
+    /// the token was issued by & for this instance.
+    ///
+    /// # Arguments
+    ///
+    /// * `jwt` - JWT configuration to validate against
+    pub fn validate(&self, jwt: &RbJwtConf) -> RbResult<()>
+    {
+        if self.iss != jwt.issuer || self.aud != jwt.audience {
+            return Err(RbError::AuthUnauthorized);
+        }
+
+        let now = Utc::now().timestamp();
+
+        // Tokens from the future can't be trusted
+        if self.nbf > now + jwt.leeway || self.iat > now + jwt.leeway {
+            return Err(RbError::AuthUnauthorized);
+        }
+
+        if self.exp < now - jwt.leeway {
+            return Err(RbError::AuthTokenExpired);
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether the user has been granted the given permission.
+    pub fn has_permission(&self, permission: &str) -> bool
+    {
+        self.permissions.iter().any(|p| p == permission)
+    }
+}
+
+/// Generates a new JWT & refresh token pair for the given user, storing the refresh token as a new
+/// session. The refresh token starts a new token family. As every way of logging in ends up here,
+/// this is also where logins are recorded in the audit log.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `jwt` - JWT configuration
+/// * `keys` - keys to sign the JWT with
+/// * `user` - user to generate the tokens for
+/// * `client` - information about the client requesting the tokens
+/// * `device_label` - optional user-provided name for the session
 pub fn generate_jwt_token(
     conn: &PgConnection,
     jwt: &RbJwtConf,
+    keys: &JwtKeys,
     user: &db::User,
+    client: &ClientInfo,
+    device_label: Option<String>,
 ) -> RbResult<JWTResponse>
 {
-    let key: Hmac<Sha256> = Hmac::new_from_slice(jwt.key.as_bytes())
-        .map_err(|_| RbError::Custom("Couldn't create Hmac key."))?;
+    let tokens = create_token_pair(conn, jwt, keys, user, client, device_label, None)?;
+    db::audit_events::record(conn, &client.actor(Some(user.id)), "auth.login", None, None)?;
 
+    Ok(tokens)
+}
+
+/// Creates a JWT & refresh token pair. If a parent token is provided, the new refresh token is
+/// added to the parent's family, continuing its session.
+fn create_token_pair(
+    conn: &PgConnection,
+    jwt: &RbJwtConf,
+    keys: &JwtKeys,
+    user: &db::User,
+    client: &ClientInfo,
+    device_label: Option<String>,
+    parent: Option<&db::RefreshToken>,
+) -> RbResult<JWTResponse>
+{
     let current_time = Utc::now();
 
     // Create the claims
     let claims = Claims {
         id: user.id,
         username: user.username.clone(),
-        admin: user.admin,
-        exp: current_time.timestamp() + jwt.refresh_token_expire,
+        role: user.role.clone(),
+        permissions: db::roles::permissions(conn, &user.role)?,
+        ver: user.token_version,
+        exp: current_time.timestamp() + jwt.access_token_expire,
+        iat: current_time.timestamp(),
+        nbf: current_time.timestamp(),
+        iss: jwt.issuer.clone(),
+        aud: jwt.audience.clone(),
+        jti: uuid::Uuid::new_v4(),
     };
 
     // Sign the claims into a new token
-    let token = claims
-        .sign_with_key(&key)
-        .map_err(|_| RbError::Custom("Couldn't sign JWT."))?;
+    let token = keys.sign(&claims)?;
 
     // Generate a random refresh token
     let mut refresh_token = vec![0u8; jwt.refresh_token_size];
@@ -67,6 +152,11 @@ pub fn generate_jwt_token(
             token: refresh_token.to_vec(),
             user_id: user.id,
             expires_at: refresh_expire,
+            ip_address: client.ip_address.clone(),
+            user_agent: client.user_agent.clone(),
+            device_label,
+            family_id: parent.map(|p| p.family_id),
+            parent_id: parent.map(|p| p.id),
         },
     )?;
 
@@ -79,7 +169,9 @@ pub fn generate_jwt_token(
 pub fn refresh_token(
     conn: &PgConnection,
     jwt: &RbJwtConf,
+    keys: &JwtKeys,
     refresh_token: &str,
+    client: &ClientInfo,
 ) -> RbResult<JWTResponse>
 {
     let token_bytes =
@@ -89,19 +181,13 @@ pub fn refresh_token(
     let (token_entry, user) =
         db::tokens::find_with_user(conn, &token_bytes).ok_or(RbError::AuthInvalidRefreshToken)?;
 
-    // If we see that the token has already been used before, we block the user.
     if token_entry.last_used_at.is_some() {
-        // If we fail to block the user, the end user must know
-        if let Err(err) = db::users::block(conn, token_entry.user_id) {
-            return Err(err);
-        }
-
-        return Err(RbError::AuthDuplicateRefreshToken);
+        return revoke_reused_family(conn, jwt, &token_entry, &user, client);
     }
 
     // Then we check if the user is blocked
     if user.blocked {
-        return Err(RbError::AuthBlockedUser);
+        return Err(RbError::AuthBlockedUser(user.blocked_reason.clone()));
     }
 
     // Now we check if the token has already expired
@@ -111,8 +197,122 @@ pub fn refresh_token(
         return Err(RbError::AuthTokenExpired);
     }
 
-    // We update the last_used_at value for the refresh token
-    db::tokens::update_last_used_at(conn, &token_entry.token, cur_time)?;
+    let rotated = conn.transaction::<_, RbError, _>(|| {
+        // Marking the token as used only succeeds once, so concurrent refreshes using the same
+        // token can't both continue the session
+        if !db::tokens::update_last_used_at(conn, &token_entry.token, cur_time)? {
+            return Ok(None);
+        }
+
+        // The new refresh token continues the same session, so it keeps its label
+        let tokens = create_token_pair(
+            conn,
+            jwt,
+            keys,
+            &user,
+            client,
+            token_entry.device_label.clone(),
+            Some(&token_entry),
+        )?;
+        db::audit_events::record(
+            conn,
+            &client.actor(Some(user.id)),
+            "auth.refresh",
+            None,
+            None,
+        )?;
+
+        Ok(Some(tokens))
+    })?;
+
+    match rotated {
+        Some(tokens) => Ok(tokens),
+        // Another request used the token in the meantime
+        None => revoke_reused_family(conn, jwt, &token_entry, &user, client),
+    }
+}
+
+/// Handles the reuse of a refresh token. If a token has already been used before, someone else
+/// might have gotten hold of it. We can't know which party holds the newest token of the family, so
+/// the entire family gets revoked, in a single transaction. Always returns
+/// `AuthDuplicateRefreshToken`.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `jwt` - JWT configuration
+/// * `token_entry` - the reused refresh token
+/// * `user` - owner of the token
+/// * `client` - information about the client reusing the token
+fn revoke_reused_family(
+    conn: &PgConnection,
+    jwt: &RbJwtConf,
+    token_entry: &db::RefreshToken,
+    user: &db::User,
+    client: &ClientInfo,
+) -> RbResult<JWTResponse>
+{
+    conn.transaction::<_, RbError, _>(|| {
+        db::tokens::delete_family(conn, token_entry.family_id)?;
+        db::users::revoke_access_tokens(conn, user.id)?;
+
+        db::security_events::create(
+            conn,
+            &db::NewSecurityEvent {
+                user_id: Some(user.id),
+                kind: String::from("refresh_token_reuse"),
+                details: Some(format!(
+                    "Revoked token family {} after reuse of refresh token {}.",
+                    token_entry.family_id, token_entry.id
+                )),
+                ip_address: client.ip_address.clone(),
+                user_agent: client.user_agent.clone(),
+            },
+        )?;
+
+        if jwt.block_on_reuse {
+            db::users::block(
+                conn,
+                user.id,
+                "A refresh token was reused, so your account might be compromised.",
+                &client.actor(None),
+            )?;
+        }
+
+        Ok(())
+    })?;
+
+    Err(RbError::AuthDuplicateRefreshToken(user.id))
+}
+
+/// Revokes the given refresh token, effectively ending the session it belongs to. The owner's
+/// access tokens are revoked as well, as they can't be traced back to a single session; their other
+/// sessions simply have to refresh. Returns the ID of the token's owner.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `refresh_token` - base64-encoded refresh token to revoke
+/// * `everywhere` - whether to revoke all of the token owner's refresh tokens instead
+pub fn revoke_refresh_token(
+    conn: &PgConnection,
+    refresh_token: &str,
+    everywhere: bool,
+) -> RbResult<uuid::Uuid>
+{
+    let token_bytes =
+        base64::decode(refresh_token).map_err(|_| RbError::AuthInvalidRefreshToken)?;
+
+    let token_entry =
+        db::tokens::find(conn, &token_bytes)?.ok_or(RbError::AuthInvalidRefreshToken)?;
+
+    if everywhere {
+        db::tokens::delete_by_user(conn, token_entry.user_id)?;
+    } else {
+        db::tokens::delete_family(conn, token_entry.family_id)?;
+    }
+
+    db::users::revoke_access_tokens(conn, token_entry.user_id)?;
 
-    generate_jwt_token(conn, jwt, &user)
+    Ok(token_entry.user_id)
 }
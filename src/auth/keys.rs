@@ -0,0 +1,244 @@
+//! Manages the keys used to sign & verify JWTs. Tokens are signed using either the configured
+//! asymmetric signing key or the shared HMAC key. Asymmetric keys are identified by the token's
+//! `kid` header, which allows accepting multiple keys while rotating them. Their public halves are
+//! published as a JSON Web Key Set, so other services can verify our tokens without the secret.
+
+use std::{collections::HashMap, sync::Arc};
+
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use openssl::pkey::{Id, PKey, Public};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    errors::{RbError, RbResult},
+    RbJwtConf, RbJwtKeyConf,
+};
+
+/// Public key in JSON Web Key format
+#[derive(Serialize)]
+pub struct Jwk
+{
+    kty: &'static str,
+    kid: String,
+    alg: &'static str,
+    #[serde(rename = "use")]
+    use_: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    crv: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    x: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    n: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    e: Option<String>,
+}
+
+/// JSON Web Key Set containing all public keys accepted by this instance
+#[derive(Serialize)]
+pub struct Jwks
+{
+    keys: Vec<Jwk>,
+}
+
+struct KeyStore
+{
+    /// `kid` of the signing key; `None` if tokens are signed using the HMAC key
+    signing_kid: Option<String>,
+    signing_alg: Algorithm,
+    signing_key: EncodingKey,
+    /// Used to verify tokens without a `kid` header; `None` if such tokens are rejected
+    hmac_key: Option<DecodingKey>,
+    /// Asymmetric keys, indexed by their `kid`
+    verification_keys: HashMap<String, (Algorithm, DecodingKey)>,
+    jwks: Jwks,
+}
+
+/// Keys used to sign & verify JWTs. Cloning this struct is cheap.
+#[derive(Clone)]
+pub struct JwtKeys(Arc<KeyStore>);
+
+impl JwtKeys
+{
+    /// Loads all keys defined in the JWT configuration.
+    ///
+    /// # Arguments
+    ///
+    /// * `jwt` - JWT configuration
+    pub fn load(jwt: &RbJwtConf) -> RbResult<Self>
+    {
+        let mut verification_keys = HashMap::new();
+        let mut jwks = Jwks { keys: Vec::new() };
+
+        let (signing_kid, signing_alg, signing_key) = match &jwt.signing_key {
+            Some(key_conf) => {
+                let pem = read_pem(key_conf)?;
+                let signing_key = match key_conf.algorithm {
+                    Algorithm::EdDSA => EncodingKey::from_ed_pem(&pem),
+                    Algorithm::RS256 => EncodingKey::from_rsa_pem(&pem),
+                    _ => return Err(RbError::Custom("Unsupported JWT key algorithm.")),
+                }
+                .map_err(|_| RbError::Custom("Invalid JWT signing key."))?;
+
+                (Some(key_conf.kid.clone()), key_conf.algorithm, signing_key)
+            },
+            None => (
+                None,
+                Algorithm::HS256,
+                EncodingKey::from_secret(jwt.key.as_bytes()),
+            ),
+        };
+
+        // The signing key's public half is accepted as well, of course
+        for key_conf in jwt.signing_key.iter().chain(jwt.verification_keys.iter()) {
+            let (decoding_key, jwk) = load_public_key(key_conf)?;
+
+            if verification_keys
+                .insert(key_conf.kid.clone(), (key_conf.algorithm, decoding_key))
+                .is_some()
+            {
+                return Err(RbError::Custom("Duplicate JWT key ID."));
+            }
+
+            jwks.keys.push(jwk);
+        }
+
+        // Once tokens are signed asymmetrically, accepting HMAC tokens would allow anyone knowing
+        // the shared secret to forge them, so this has to be enabled explicitly
+        let hmac_key = if jwt.signing_key.is_none() || jwt.accept_hmac_tokens {
+            Some(DecodingKey::from_secret(jwt.key.as_bytes()))
+        } else {
+            None
+        };
+
+        Ok(Self(Arc::new(KeyStore {
+            signing_kid,
+            signing_alg,
+            signing_key,
+            hmac_key,
+            verification_keys,
+            jwks,
+        })))
+    }
+
+    /// Signs the given claims into a new token using the current signing key.
+    pub fn sign<T: Serialize>(&self, claims: &T) -> RbResult<String>
+    {
+        let mut header = Header::new(self.0.signing_alg);
+        header.kid = self.0.signing_kid.clone();
+
+        jsonwebtoken::encode(&header, claims, &self.0.signing_key)
+            .map_err(|_| RbError::Custom("Couldn't sign JWT."))
+    }
+
+    /// Verifies the token's signature & returns its claims. Tokens with a `kid` header are verified
+    /// using the matching asymmetric key, others using the HMAC key. If a signing key is
+    /// configured, tokens without a `kid` are rejected unless HMAC tokens are explicitly accepted.
+    ///
+    /// **NOTE**: this only checks the signature; the claims themselves should still be validated.
+    pub fn verify<T: DeserializeOwned>(&self, token: &str) -> RbResult<T>
+    {
+        let header = jsonwebtoken::decode_header(token).map_err(|_| RbError::AuthUnauthorized)?;
+
+        let (alg, key) = match &header.kid {
+            Some(kid) => {
+                let (alg, key) = self
+                    .0
+                    .verification_keys
+                    .get(kid)
+                    .ok_or(RbError::AuthUnauthorized)?;
+
+                (*alg, key)
+            },
+            None => (
+                Algorithm::HS256,
+                self.0.hmac_key.as_ref().ok_or(RbError::AuthUnauthorized)?,
+            ),
+        };
+
+        // Claim validation is done by the caller
+        let mut validation = Validation::new(alg);
+        validation.validate_exp = false;
+        validation.required_spec_claims.clear();
+
+        jsonwebtoken::decode(token, key, &validation)
+            .map(|data| data.claims)
+            .map_err(|_| RbError::AuthUnauthorized)
+    }
+
+    /// Returns the public keys in JWKS format.
+    pub fn jwks(&self) -> &Jwks
+    {
+        &self.0.jwks
+    }
+}
+
+fn invalid_key<E>(_: E) -> RbError
+{
+    RbError::Custom("Invalid JWT key.")
+}
+
+fn read_pem(key_conf: &RbJwtKeyConf) -> RbResult<Vec<u8>>
+{
+    std::fs::read(&key_conf.path).map_err(|_| RbError::Custom("Couldn't read JWT key file."))
+}
+
+/// Loads the public half of a PEM-encoded key, which can be either a public or private key.
+fn load_public_key(key_conf: &RbJwtKeyConf) -> RbResult<(DecodingKey, Jwk)>
+{
+    let pem = read_pem(key_conf)?;
+
+    let pkey: PKey<Public> = match PKey::private_key_from_pem(&pem) {
+        Ok(private) => {
+            PKey::public_key_from_der(&private.public_key_to_der().map_err(invalid_key)?)
+                .map_err(invalid_key)?
+        },
+        Err(_) => PKey::public_key_from_pem(&pem).map_err(invalid_key)?,
+    };
+
+    let encode = |bytes: &[u8]| base64::encode_config(bytes, base64::URL_SAFE_NO_PAD);
+
+    match key_conf.algorithm {
+        Algorithm::EdDSA if pkey.id() == Id::ED25519 => {
+            let x = encode(&pkey.raw_public_key().map_err(invalid_key)?);
+            let decoding_key = DecodingKey::from_ed_components(&x).map_err(invalid_key)?;
+
+            Ok((
+                decoding_key,
+                Jwk {
+                    kty: "OKP",
+                    kid: key_conf.kid.clone(),
+                    alg: "EdDSA",
+                    use_: "sig",
+                    crv: Some("Ed25519"),
+                    x: Some(x),
+                    n: None,
+                    e: None,
+                },
+            ))
+        },
+        Algorithm::RS256 if pkey.id() == Id::RSA => {
+            let rsa = pkey.rsa().map_err(invalid_key)?;
+            let n = encode(&rsa.n().to_vec());
+            let e = encode(&rsa.e().to_vec());
+            let decoding_key = DecodingKey::from_rsa_components(&n, &e).map_err(invalid_key)?;
+
+            Ok((
+                decoding_key,
+                Jwk {
+                    kty: "RSA",
+                    kid: key_conf.kid.clone(),
+                    alg: "RS256",
+                    use_: "sig",
+                    crv: None,
+                    x: None,
+                    n: Some(n),
+                    e: Some(e),
+                },
+            ))
+        },
+        Algorithm::EdDSA | Algorithm::RS256 => {
+            Err(RbError::Custom("JWT key doesn't match its algorithm."))
+        },
+        _ => Err(RbError::Custom("Unsupported JWT key algorithm.")),
+    }
+}
@@ -1,20 +1,93 @@
-use rocket::{serde::json::Json, State};
-use serde::Deserialize;
+use rand::{thread_rng, Rng};
+use rocket::{http::CookieJar, serde::json::Json, State};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 use self::{
+    cookies::{CookieSession, TokenResponse},
     jwt::{generate_jwt_token, JWTResponse},
-    pass::verify_user,
+    keys::{Jwks, JwtKeys},
+    revocation::TokenVersions,
+};
+use crate::{
+    db::{self, audit_events::Target},
+    errors::{RbError, RbResult},
+    guards::{ClientInfo, RefreshCookie, User},
+    RbConfig, RbDbConn,
 };
-use crate::{errors::RbResult, guards::User, RbConfig, RbDbConn};
 
+pub mod cookies;
+pub mod invites;
 pub mod jwt;
+pub mod keys;
+pub mod oidc;
 pub mod pass;
+pub mod password_reset;
+pub mod permissions;
+pub mod personal_tokens;
+pub mod revocation;
+pub mod sessions;
+pub mod setup;
+pub mod throttle;
+pub mod totp;
+pub mod two_factor;
+pub mod webauthn;
+
+/// Generates a random, URL-safe string from the given amount of random bytes.
+pub fn random_string(size: usize) -> String
+{
+    let mut bytes = vec![0u8; size];
+    thread_rng().fill(&mut bytes[..]);
+
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+/// Hashes a random token such as an invite code or a password reset token. Only these hashes are
+/// stored, so a leaked database can't be used to redeem them. The tokens are random enough that a
+/// plain SHA-256 hash suffices.
+pub fn hash_token(token: &str) -> Vec<u8>
+{
+    Sha256::digest(token.as_bytes()).to_vec()
+}
+
+/// Generates a new random token, returning it along with its hash.
+///
+/// # Arguments
+///
+/// * `size` - amount of random bytes in the token
+pub fn random_token(size: usize) -> (String, Vec<u8>)
+{
+    let token = random_string(size);
+    let token_hash = hash_token(&token);
+
+    (token, token_hash)
+}
 
 #[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Credentials
 {
     username: String,
     password: String,
+    /// Optional name for the new session, e.g. "work laptop"
+    device_label: Option<String>,
+    /// Whether to set the tokens as cookies instead of returning them
+    #[serde(default)]
+    cookies: bool,
+}
+
+/// Response to a successful login. Users with two-factor authentication enabled receive a challenge
+/// token instead, which can be exchanged for a token pair at `/api/auth/2fa/verify`.
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum LoginResponse
+{
+    Tokens(TokenResponse),
+    #[serde(rename_all = "camelCase")]
+    TwoFactorChallenge
+    {
+        challenge_token: String,
+    },
 }
 
 #[post("/login")]
@@ -27,21 +100,145 @@ pub async fn already_logged_in(_user: User) -> String
 pub async fn login(
     conn: RbDbConn,
     conf: &State<RbConfig>,
+    keys: &State<JwtKeys>,
+    client: ClientInfo,
+    jar: &CookieJar<'_>,
     credentials: Json<Credentials>,
-) -> RbResult<Json<JWTResponse>>
+) -> RbResult<Json<LoginResponse>>
 {
-    let credentials = credentials.into_inner();
+    let Credentials {
+        username,
+        password,
+        device_label,
+        cookies,
+    } = credentials.into_inner();
+    db::tokens::check_device_label(device_label.as_deref())?;
+
     let jwt = conf.jwt.clone();
+    let keys = keys.inner().clone();
+    let throttle = conf.throttle.clone();
+    let argon2 = conf.argon2.clone();
+    let throttle_client = client.clone();
 
-    // Get the user, if credentials are valid
+    // Get the user, if credentials are valid & the client isn't being throttled
     let user = conn
-        .run(move |c| verify_user(c, &credentials.username, &credentials.password))
+        .run(move |c| {
+            let res = throttle::verify_user(
+                c,
+                &throttle,
+                &argon2,
+                &username,
+                &password,
+                &throttle_client,
+            );
+
+            // Throttled attempts never got to check the credentials, so they aren't recorded
+            if let Err(RbError::AuthInvalidCredentials | RbError::AuthBlockedUser(_)) = &res {
+                db::audit_events::record(
+                    c,
+                    &throttle_client.actor(None),
+                    "auth.login_failed",
+                    Some(Target::Username(username)),
+                    None,
+                )?;
+            }
+
+            res
+        })
+        .await?;
+
+    if user.must_change_password {
+        return Err(RbError::AuthPasswordChangeRequired);
+    }
+
+    // Users with 2FA enabled first have to provide a code
+    if user.totp_enabled {
+        let challenge_token = totp::create_challenge(&jwt, &conf.totp, &keys, &user, device_label)?;
+
+        return Ok(Json(LoginResponse::TwoFactorChallenge { challenge_token }));
+    }
+
+    let tokens = conn
+        .run(move |c| generate_jwt_token(c, &jwt, &keys, &user, &client, device_label))
         .await?;
 
-    Ok(Json(
-        conn.run(move |c| generate_jwt_token(c, &jwt, &user))
-            .await?,
-    ))
+    Ok(Json(LoginResponse::Tokens(TokenResponse::new(
+        tokens, jar, conf, cookies,
+    ))))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PasswordChange
+{
+    username: String,
+    current_password: String,
+    new_password: String,
+    /// Required if the user has two-factor authentication enabled
+    totp_code: Option<String>,
+}
+
+/// Changes a user's password given their current one, logging them out everywhere. This doesn't
+/// require being logged in, as users whose password has been reset can't log in until they've
+/// changed it.
+///
+/// # Arguments
+///
+/// * `conn` - guard providing a connection to the database
+/// * `conf` - application configuration
+/// * `versions` - cached token versions
+/// * `client` - information about the client making the request
+/// * `change` - the user's credentials & new password
+#[post("/password", data = "<change>")]
+pub async fn change_password(
+    conn: RbDbConn,
+    conf: &State<RbConfig>,
+    versions: &State<TokenVersions>,
+    client: ClientInfo,
+    change: Json<PasswordChange>,
+) -> RbResult<()>
+{
+    let change = change.into_inner();
+    let throttle = conf.throttle.clone();
+    let argon2 = conf.argon2.clone();
+
+    pass::check_password(&change.new_password)?;
+
+    let user_id = conn
+        .run(move |c| {
+            // The username's failures are only cleared once the two-factor code has been checked
+            let user = throttle::verify_credentials(
+                c,
+                &throttle,
+                &argon2,
+                &change.username,
+                &change.current_password,
+                &client,
+            )?;
+
+            // Otherwise, knowing the password would be enough to lock the user out
+            if user.totp_enabled {
+                let code = change.totp_code.ok_or(RbError::AuthInvalidTotpCode)?;
+
+                if let Err(err) = totp::verify_code(c, &throttle, &user, &code, &client) {
+                    if let RbError::AuthInvalidTotpCode = err {
+                        throttle::register_failure(c, &throttle, &change.username, &client)?;
+                    }
+
+                    return Err(err);
+                }
+            }
+
+            throttle::register_success(c, &change.username)?;
+
+            pass::change_password(c, &argon2, user.id, &change.new_password, false)?;
+
+            Ok(user.id)
+        })
+        .await?;
+    versions.forget(user_id);
+
+    Ok(())
 }
 
 #[derive(Deserialize)]
@@ -51,18 +248,147 @@ pub struct RefreshTokenRequest
     pub refresh_token: String,
 }
 
-#[post("/refresh", data = "<refresh_token_request>")]
+/// Reusing a refresh token revokes its owner's access tokens, which should take effect on this
+/// instance immediately.
+fn forget_on_reuse<T>(versions: &TokenVersions, res: RbResult<T>) -> RbResult<T>
+{
+    if let Err(RbError::AuthDuplicateRefreshToken(user_id)) = &res {
+        versions.forget(*user_id);
+    }
+
+    res
+}
+
+#[post("/refresh", data = "<refresh_token_request>", rank = 2)]
 pub async fn refresh_token(
     conn: RbDbConn,
     conf: &State<RbConfig>,
+    keys: &State<JwtKeys>,
+    versions: &State<TokenVersions>,
+    client: ClientInfo,
     refresh_token_request: Json<RefreshTokenRequest>,
 ) -> RbResult<Json<JWTResponse>>
 {
     let refresh_token = refresh_token_request.into_inner().refresh_token;
     let jwt = conf.jwt.clone();
+    let keys = keys.inner().clone();
+
+    let res = conn
+        .run(move |c| crate::auth::jwt::refresh_token(c, &jwt, &keys, &refresh_token, &client))
+        .await;
+
+    forget_on_reuse(versions, res).map(Json)
+}
+
+/// Refreshes the token pair stored in cookies, rotating the CSRF token as well.
+///
+/// # Arguments
+///
+/// * `conn` - guard providing a connection to the database
+/// * `conf` - application configuration
+/// * `keys` - keys used to sign tokens
+/// * `versions` - cached token versions
+/// * `client` - information about the client making the request
+/// * `jar` - the request's cookies
+/// * `refresh_cookie` - the refresh token stored in the cookie
+#[post("/refresh")]
+pub async fn refresh_token_cookie(
+    conn: RbDbConn,
+    conf: &State<RbConfig>,
+    keys: &State<JwtKeys>,
+    versions: &State<TokenVersions>,
+    client: ClientInfo,
+    jar: &CookieJar<'_>,
+    refresh_cookie: RefreshCookie,
+) -> RbResult<Json<CookieSession>>
+{
+    let jwt = conf.jwt.clone();
+    let keys = keys.inner().clone();
+
+    let res = conn
+        .run(move |c| crate::auth::jwt::refresh_token(c, &jwt, &keys, &refresh_cookie.0, &client))
+        .await;
+    let tokens = forget_on_reuse(versions, res)?;
+
+    Ok(Json(cookies::set(jar, conf, tokens)))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogoutRequest
+{
+    pub refresh_token: String,
+    /// Revoke all of the user's refresh tokens instead of only the provided one
+    #[serde(default)]
+    pub everywhere: bool,
+}
+
+#[post("/logout", data = "<logout_request>", rank = 2)]
+pub async fn logout(
+    conn: RbDbConn,
+    versions: &State<TokenVersions>,
+    logout_request: Json<LogoutRequest>,
+) -> RbResult<()>
+{
+    let logout_request = logout_request.into_inner();
+
+    let user_id = conn
+        .run(move |c| {
+            crate::auth::jwt::revoke_refresh_token(
+                c,
+                &logout_request.refresh_token,
+                logout_request.everywhere,
+            )
+        })
+        .await?;
+    versions.forget(user_id);
+
+    Ok(())
+}
+
+/// Publishes the public keys used to sign JWTs, allowing other services to verify tokens without
+/// knowing any secrets.
+#[get("/.well-known/jwks.json")]
+pub async fn jwks(keys: &State<JwtKeys>) -> Json<&Jwks>
+{
+    Json(keys.jwks())
+}
+
+#[derive(Deserialize)]
+pub struct CookieLogoutRequest
+{
+    /// Revoke all of the user's refresh tokens instead of only the one stored in the cookie
+    #[serde(default)]
+    pub everywhere: bool,
+}
+
+/// Revokes the refresh token stored in the cookie & removes all authentication cookies. The body is
+/// optional.
+///
+/// # Arguments
+///
+/// * `conn` - guard providing a connection to the database
+/// * `versions` - cached token versions
+/// * `jar` - the request's cookies
+/// * `refresh_cookie` - the refresh token stored in the cookie
+/// * `logout_request` - whether to log out everywhere
+#[post("/logout", data = "<logout_request>")]
+pub async fn logout_cookie(
+    conn: RbDbConn,
+    versions: &State<TokenVersions>,
+    jar: &CookieJar<'_>,
+    refresh_cookie: RefreshCookie,
+    logout_request: Option<Json<CookieLogoutRequest>>,
+) -> RbResult<()>
+{
+    let everywhere = matches!(logout_request, Some(req) if req.everywhere);
+
+    cookies::clear(jar);
+
+    let user_id = conn
+        .run(move |c| crate::auth::jwt::revoke_refresh_token(c, &refresh_cookie.0, everywhere))
+        .await?;
+    versions.forget(user_id);
 
-    Ok(Json(
-        conn.run(move |c| crate::auth::jwt::refresh_token(c, &jwt, &refresh_token))
-            .await?,
-    ))
+    Ok(())
 }
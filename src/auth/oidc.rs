@@ -0,0 +1,534 @@
+//! Single sign-on using an OpenID Connect provider, through the authorization code flow with PKCE.
+//! The state, nonce & code verifier of a login in progress are stored in a signed, HttpOnly cookie,
+//! which also ties the login to the browser that started it. Identities are linked to local users
+//! by the provider's subject identifier, & users logging in for the first time can optionally be
+//! created on the spot.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use diesel::{Connection, PgConnection};
+use jsonwebtoken::{jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use rand::{thread_rng, Rng};
+use rocket::{
+    http::{Cookie, CookieJar, SameSite},
+    response::Redirect,
+    serde::json::{Json, Value},
+    State,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{
+    cookies::TokenResponse, jwt::generate_jwt_token, keys::JwtKeys, pass, revocation::TokenVersions,
+};
+use crate::{
+    db,
+    errors::{RbError, RbResult},
+    guards::ClientInfo,
+    RbArgon2Conf, RbConfig, RbDbConn, RbJwtConf, RbOidcConf,
+};
+
+/// Cookie containing the signed state of a login in progress
+const STATE_COOKIE: &str = "rb_oidc";
+/// Path the state cookie is sent to
+const STATE_PATH: &str = "/api/auth/oidc";
+/// Time users get to log in at the provider, in seconds
+const LOGIN_EXPIRE: i64 = 600;
+/// Amount of random bytes in the state, nonce & code verifier
+const RANDOM_SIZE: usize = 32;
+/// Time after which a request to the provider is given up on
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long the provider's configuration & keys are cached
+const CACHE_EXPIRE: Duration = Duration::from_secs(3600);
+
+/// The parts of the provider's configuration we need
+#[derive(Deserialize)]
+struct ProviderMetadata
+{
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+/// Claims of the state cookie
+#[derive(Serialize, Deserialize)]
+struct LoginState
+{
+    exp: i64,
+    iss: String,
+    aud: String,
+    state: String,
+    nonce: String,
+    code_verifier: String,
+}
+
+#[derive(Deserialize)]
+struct TokenEndpointResponse
+{
+    id_token: String,
+}
+
+/// Claims of the ID token returned by the provider
+#[derive(Deserialize)]
+struct IdTokenClaims
+{
+    sub: String,
+    nonce: Option<String>,
+    preferred_username: Option<String>,
+    email: Option<String>,
+    email_verified: Option<bool>,
+    /// Remaining claims, which might include the configured groups claim
+    #[serde(flatten)]
+    other: HashMap<String, Value>,
+}
+
+impl IdTokenClaims
+{
+    /// Returns the groups listed in the given claim.
+    fn groups(&self, claim: &str) -> Vec<&str>
+    {
+        match self.other.get(claim) {
+            Some(Value::Array(groups)) => groups.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+fn random_string() -> String
+{
+    let mut bytes = [0u8; RANDOM_SIZE];
+    thread_rng().fill(&mut bytes[..]);
+
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn state_audience(jwt: &RbJwtConf) -> String
+{
+    format!("{}:oidc", jwt.audience)
+}
+
+/// Fetches a JSON document from the provider.
+async fn fetch_json<T: serde::de::DeserializeOwned>(
+    http: &reqwest::Client,
+    url: &str,
+) -> RbResult<T>
+{
+    http.get(url)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(|_| RbError::AuthOidcFailed)?
+        .json()
+        .await
+        .map_err(|_| RbError::AuthOidcFailed)
+}
+
+/// Client used to talk to the provider. Its configuration & keys are cached for a while, so they
+/// don't have to be fetched on every login.
+pub struct OidcProvider
+{
+    http: reqwest::Client,
+    metadata: RwLock<Option<(Arc<ProviderMetadata>, Instant)>>,
+    jwks: RwLock<Option<(Arc<JwkSet>, Instant)>>,
+}
+
+impl OidcProvider
+{
+    pub fn new() -> RbResult<Self>
+    {
+        let http = reqwest::Client::builder()
+            .timeout(HTTP_TIMEOUT)
+            .build()
+            .map_err(|_| RbError::Custom("Couldn't create HTTP client."))?;
+
+        Ok(OidcProvider {
+            http,
+            metadata: RwLock::new(None),
+            jwks: RwLock::new(None),
+        })
+    }
+
+    /// Returns the provider's configuration, fetching it if it isn't cached.
+    ///
+    /// # Arguments
+    ///
+    /// * `oidc` - OIDC configuration
+    async fn metadata(&self, oidc: &RbOidcConf) -> RbResult<Arc<ProviderMetadata>>
+    {
+        if let Some(metadata) = cached(&self.metadata) {
+            return Ok(metadata);
+        }
+
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            oidc.issuer.trim_end_matches('/')
+        );
+        let metadata: ProviderMetadata = fetch_json(&self.http, &url).await?;
+
+        // Prevents a compromised discovery document from impersonating another issuer
+        if metadata.issuer != oidc.issuer {
+            return Err(RbError::AuthOidcFailed);
+        }
+
+        Ok(store(&self.metadata, metadata))
+    }
+
+    /// Returns the provider's signing keys, fetching them if they aren't cached.
+    ///
+    /// # Arguments
+    ///
+    /// * `metadata` - the provider's configuration
+    /// * `refresh` - fetch the keys even if they're cached, e.g. because the provider started
+    ///   using a new key
+    async fn jwks(&self, metadata: &ProviderMetadata, refresh: bool) -> RbResult<Arc<JwkSet>>
+    {
+        if !refresh {
+            if let Some(jwks) = cached(&self.jwks) {
+                return Ok(jwks);
+            }
+        }
+
+        let jwks: JwkSet = fetch_json(&self.http, &metadata.jwks_uri).await?;
+
+        Ok(store(&self.jwks, jwks))
+    }
+}
+
+/// Returns the cached value, if it was fetched less than `CACHE_EXPIRE` ago.
+fn cached<T>(cache: &RwLock<Option<(Arc<T>, Instant)>>) -> Option<Arc<T>>
+{
+    match &*cache.read().ok()? {
+        Some((value, fetched_at)) if fetched_at.elapsed() < CACHE_EXPIRE => Some(Arc::clone(value)),
+        _ => None,
+    }
+}
+
+/// Caches a value that was just fetched.
+fn store<T>(cache: &RwLock<Option<(Arc<T>, Instant)>>, value: T) -> Arc<T>
+{
+    let value = Arc::new(value);
+
+    if let Ok(mut cache) = cache.write() {
+        *cache = Some((Arc::clone(&value), Instant::now()));
+    }
+
+    value
+}
+
+/// Exchanges the authorization code for an ID token & verifies the token.
+async fn fetch_id_token(
+    provider: &OidcProvider,
+    metadata: &ProviderMetadata,
+    oidc: &RbOidcConf,
+    jwt: &RbJwtConf,
+    code: &str,
+    login_state: &LoginState,
+) -> RbResult<IdTokenClaims>
+{
+    let mut params = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", &oidc.redirect_uri),
+        ("client_id", &oidc.client_id),
+        ("code_verifier", &login_state.code_verifier),
+    ];
+
+    if let Some(client_secret) = &oidc.client_secret {
+        params.push(("client_secret", client_secret));
+    }
+
+    let response: TokenEndpointResponse = provider
+        .http
+        .post(&metadata.token_endpoint)
+        .form(&params)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(|_| RbError::AuthOidcFailed)?
+        .json()
+        .await
+        .map_err(|_| RbError::AuthOidcFailed)?;
+
+    let header =
+        jsonwebtoken::decode_header(&response.id_token).map_err(|_| RbError::AuthOidcFailed)?;
+
+    // ID tokens have to be signed using one of the provider's published keys
+    if matches!(
+        header.alg,
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+    ) {
+        return Err(RbError::AuthOidcFailed);
+    }
+
+    let find_key = |jwks: &JwkSet| {
+        match &header.kid {
+            Some(kid) => jwks.find(kid),
+            None => jwks.keys.first(),
+        }
+        .and_then(|jwk| DecodingKey::from_jwk(jwk).ok())
+    };
+
+    // An unknown key might've been added since the keys were cached
+    let key = match find_key(&*provider.jwks(metadata, false).await?) {
+        Some(key) => key,
+        None => find_key(&*provider.jwks(metadata, true).await?).ok_or(RbError::AuthOidcFailed)?,
+    };
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_audience(&[&oidc.client_id]);
+    validation.set_issuer(&[&metadata.issuer]);
+    validation.leeway = jwt.leeway as u64;
+
+    let claims = jsonwebtoken::decode::<IdTokenClaims>(&response.id_token, &key, &validation)
+        .map_err(|_| RbError::AuthOidcFailed)?
+        .claims;
+
+    // Prevents a token issued for another login from being replayed
+    if claims.nonce.as_deref() != Some(login_state.nonce.as_str()) {
+        return Err(RbError::AuthOidcFailed);
+    }
+
+    Ok(claims)
+}
+
+/// Returns the user linked to the identity, creating one if allowed. Members of the configured
+/// admin groups are made admins, & lose the role again once they leave those groups. Admins
+/// appointed locally are never demoted. Also returns whether the user's role was changed, which
+/// revokes their access tokens.
+fn find_or_provision(
+    conn: &PgConnection,
+    oidc: &RbOidcConf,
+    argon2: &RbArgon2Conf,
+    issuer: &str,
+    claims: &IdTokenClaims,
+) -> RbResult<(db::User, bool)>
+{
+    let (mut user, role_from_groups) =
+        match db::oidc_identities::find_user(conn, issuer, &claims.sub)? {
+            Some(found) => found,
+            None if oidc.provision => {
+                let username = claims
+                    .preferred_username
+                    .clone()
+                    .unwrap_or_else(|| claims.sub.clone());
+                db::users::check_username(&username)?;
+
+                let new_user = db::NewUser {
+                    username,
+                    // Provisioned users log in using the provider, so they get a password nobody knows
+                    password: pass::hash_password(argon2, &pass::generate_password())?,
+                    role: oidc.default_role.clone(),
+                    // Unverified addresses could be used to take over password resets
+                    email: match claims.email_verified {
+                        Some(true) => claims.email.as_ref().map(|email| email.to_lowercase()),
+                        _ => None,
+                    },
+                };
+
+                (
+                    db::oidc_identities::provision(conn, issuer, &claims.sub, &new_user)?,
+                    false,
+                )
+            },
+            None => return Err(RbError::AuthUnknownOidcUser),
+        };
+
+    let is_admin = claims
+        .groups(&oidc.groups_claim)
+        .iter()
+        .any(|group| oidc.admin_groups.iter().any(|g| g == group));
+
+    let mut role_changed = false;
+
+    conn.transaction::<_, RbError, _>(|| {
+        if is_admin && user.role != db::roles::ADMIN {
+            user = db::users::set_role(conn, user.id, db::roles::ADMIN)?;
+            role_changed = true;
+            db::oidc_identities::set_role_from_groups(conn, issuer, &claims.sub, true)?;
+        } else if !is_admin && role_from_groups {
+            // The role might've been changed locally in the meantime, which is left alone
+            if user.role == db::roles::ADMIN {
+                let role = oidc.default_role.as_deref().unwrap_or(db::roles::READER);
+                user = db::users::set_role(conn, user.id, role)?;
+                role_changed = true;
+            }
+
+            db::oidc_identities::set_role_from_groups(conn, issuer, &claims.sub, false)?;
+        }
+
+        Ok(())
+    })?;
+
+    Ok((user, role_changed))
+}
+
+/// Starts a login by redirecting the browser to the provider.
+///
+/// # Arguments
+///
+/// * `conf` - application configuration
+/// * `provider` - client used to talk to the provider
+/// * `keys` - keys used to sign the state cookie
+/// * `jar` - the request's cookies
+#[get("/oidc/login")]
+pub async fn login(
+    conf: &State<RbConfig>,
+    provider: &State<OidcProvider>,
+    keys: &State<JwtKeys>,
+    jar: &CookieJar<'_>,
+) -> RbResult<Redirect>
+{
+    let oidc = conf.oidc.as_ref().ok_or(RbError::AuthOidcDisabled)?;
+    let metadata = provider.metadata(oidc).await?;
+
+    let login_state = LoginState {
+        exp: Utc::now().timestamp() + LOGIN_EXPIRE,
+        iss: conf.jwt.issuer.clone(),
+        aud: state_audience(&conf.jwt),
+        state: random_string(),
+        nonce: random_string(),
+        code_verifier: random_string(),
+    };
+    let code_challenge = base64::encode_config(
+        Sha256::digest(login_state.code_verifier.as_bytes()),
+        base64::URL_SAFE_NO_PAD,
+    );
+
+    let url = reqwest::Url::parse_with_params(
+        &metadata.authorization_endpoint,
+        &[
+            ("response_type", "code"),
+            ("client_id", &oidc.client_id),
+            ("redirect_uri", &oidc.redirect_uri),
+            ("scope", &oidc.scopes.join(" ")),
+            ("state", &login_state.state),
+            ("nonce", &login_state.nonce),
+            ("code_challenge", &code_challenge),
+            ("code_challenge_method", "S256"),
+        ],
+    )
+    .map_err(|_| RbError::AuthOidcFailed)?;
+
+    // Lax, as the user arrives back from the provider through a cross-site navigation
+    jar.add(
+        Cookie::build(STATE_COOKIE, keys.sign(&login_state)?)
+            .path(STATE_PATH)
+            .secure(conf.cookies.secure)
+            .http_only(true)
+            .same_site(SameSite::Lax)
+            .max_age(time::Duration::seconds(LOGIN_EXPIRE))
+            .finish(),
+    );
+
+    Ok(Redirect::to(url.to_string()))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Callback
+{
+    code: String,
+    state: String,
+    /// Optional name for the new session, e.g. "work laptop"
+    device_label: Option<String>,
+    /// Whether to set the tokens as cookies instead of returning them
+    #[serde(default)]
+    cookies: bool,
+}
+
+/// Completes a login using the code & state the provider redirected the browser back with,
+/// returning a new JWT & refresh token pair.
+///
+/// # Arguments
+///
+/// * `conn` - guard providing a connection to the database
+/// * `conf` - application configuration
+/// * `provider` - client used to talk to the provider
+/// * `keys` - keys used to verify the state cookie & sign the new JWT
+/// * `versions` - cached token versions
+/// * `client` - information about the client completing the login
+/// * `jar` - the request's cookies
+/// * `callback` - the code & state returned by the provider
+// Every argument is a request guard
+#[allow(clippy::too_many_arguments)]
+#[post("/oidc/callback", data = "<callback>")]
+pub async fn callback(
+    conn: RbDbConn,
+    conf: &State<RbConfig>,
+    provider: &State<OidcProvider>,
+    keys: &State<JwtKeys>,
+    versions: &State<TokenVersions>,
+    client: ClientInfo,
+    jar: &CookieJar<'_>,
+    callback: Json<Callback>,
+) -> RbResult<Json<TokenResponse>>
+{
+    let oidc = conf.oidc.clone().ok_or(RbError::AuthOidcDisabled)?;
+    let callback = callback.into_inner();
+    db::tokens::check_device_label(callback.device_label.as_deref())?;
+
+    // Each login attempt can only be completed once
+    let state_cookie = jar
+        .get(STATE_COOKIE)
+        .map(|cookie| cookie.value().to_string())
+        .ok_or(RbError::AuthInvalidOidcState)?;
+    jar.remove(Cookie::build(STATE_COOKIE, "").path(STATE_PATH).finish());
+
+    let login_state: LoginState = keys
+        .verify(&state_cookie)
+        .map_err(|_| RbError::AuthInvalidOidcState)?;
+
+    if login_state.iss != conf.jwt.issuer
+        || login_state.aud != state_audience(&conf.jwt)
+        || login_state.exp < Utc::now().timestamp()
+        || login_state.state != callback.state
+    {
+        return Err(RbError::AuthInvalidOidcState);
+    }
+
+    let metadata = provider.metadata(&oidc).await?;
+    let claims = fetch_id_token(
+        provider,
+        &metadata,
+        &oidc,
+        &conf.jwt,
+        &callback.code,
+        &login_state,
+    )
+    .await?;
+
+    let jwt = conf.jwt.clone();
+    let argon2 = conf.argon2.clone();
+    let keys = keys.inner().clone();
+    let device_label = callback.device_label;
+
+    let (user, role_changed) = conn
+        .run(move |c| find_or_provision(c, &oidc, &argon2, &metadata.issuer, &claims))
+        .await?;
+
+    // Otherwise, tokens issued for the old role would keep working on this instance for a while
+    if role_changed {
+        versions.forget(user.id);
+    }
+
+    if user.blocked {
+        return Err(RbError::AuthBlockedUser(user.blocked_reason.clone()));
+    }
+
+    let tokens = conn
+        .run(move |c| generate_jwt_token(c, &jwt, &keys, &user, &client, device_label))
+        .await?;
+
+    Ok(Json(TokenResponse::new(
+        tokens,
+        jar,
+        conf,
+        callback.cookies,
+    )))
+}
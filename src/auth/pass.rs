@@ -1,36 +1,360 @@
-use argon2::verify_encoded;
+use argon2::{verify_encoded, verify_encoded_ext, ThreadMode, Variant, Version};
 use diesel::PgConnection;
-use rand::{thread_rng, Rng};
+use once_cell::sync::OnceCell;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use uuid::Uuid;
 
 use crate::{
     db,
     errors::{RbError, RbResult},
+    RbArgon2Conf,
 };
 
-pub fn verify_user(conn: &PgConnection, username: &str, password: &str) -> RbResult<db::User>
+/// Minimum length of new passwords
+const MIN_PASSWORD_LENGTH: usize = 8;
+/// Length of the temporary passwords generated when an admin resets a password
+const TEMPORARY_PASSWORD_LENGTH: usize = 16;
+/// Length of the random salt used for each hash, in bytes
+const SALT_LENGTH: usize = 64;
+/// Length of the resulting hashes, in bytes
+const HASH_LENGTH: u32 = 32;
+
+/// Hash that's verified when logging in as an unknown user
+static DUMMY_HASH: OnceCell<String> = OnceCell::new();
+
+/// Checks a user's credentials. Unknown users, wrong passwords & blocked users all result in the
+/// same error, & take about as long to check, so the response doesn't reveal which users exist.
+/// Only a user who provides the correct password finds out they're blocked.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `argon2` - password hashing configuration
+/// * `username` - username provided by the client
+/// * `password` - password provided by the client
+pub fn verify_user(
+    conn: &PgConnection,
+    argon2: &RbArgon2Conf,
+    username: &str,
+    password: &str,
+) -> RbResult<db::User>
 {
-    // TODO handle non-"NotFound" Diesel errors accordingely
-    let user = db::users::find_by_username(conn, username).map_err(|_| RbError::AuthUnknownUser)?;
+    let user = match db::users::find_by_username(conn, username)? {
+        Some(user) => user,
+        None => {
+            verify_dummy(argon2, password)?;
+
+            return Err(RbError::AuthInvalidCredentials);
+        },
+    };
+
+    let needs_rehash =
+        verify_password(argon2, &user.password, password).ok_or(RbError::AuthInvalidCredentials)?;
 
-    // Check if a user is blocked
     if user.blocked {
-        return Err(RbError::AuthBlockedUser);
+        return Err(RbError::AuthBlockedUser(user.blocked_reason.clone()));
     }
 
-    match verify_encoded(user.password.as_str(), password.as_bytes()) {
-        Ok(true) => Ok(user),
-        _ => Err(RbError::AuthInvalidPassword),
+    // The user can still log in if this fails; we'll just try again next time
+    if needs_rehash {
+        if let Err(err) = hash_password(argon2, password)
+            .and_then(|hash| db::users::set_password_hash(conn, user.id, &user.password, &hash))
+        {
+            warn!("Couldn't rehash password: {}", err.message());
+        }
     }
+
+    Ok(user)
+}
+
+/// Verifies the password against a dummy hash, so attempts for unknown users take as long as
+/// attempts for known ones.
+///
+/// # Arguments
+///
+/// * `argon2` - password hashing configuration
+/// * `password` - password provided by the client
+pub fn verify_dummy(argon2: &RbArgon2Conf, password: &str) -> RbResult<()>
+{
+    let dummy_hash = DUMMY_HASH.get_or_try_init(|| hash_password(argon2, "dummy"))?;
+    verify_password(argon2, dummy_hash, password);
+
+    Ok(())
 }
 
-pub fn hash_password(password: &str) -> RbResult<String>
+fn pepper(argon2: &RbArgon2Conf) -> &[u8]
+{
+    argon2.pepper.as_deref().unwrap_or_default().as_bytes()
+}
+
+pub fn hash_password(argon2: &RbArgon2Conf, password: &str) -> RbResult<String>
 {
     // Generate a random salt
-    let mut salt = [0u8; 64];
+    let mut salt = [0u8; SALT_LENGTH];
     thread_rng().fill(&mut salt[..]);
 
     // Encode the actual password
-    let config = argon2::Config::default();
+    let config = argon2::Config {
+        variant: Variant::Argon2id,
+        version: Version::Version13,
+        mem_cost: argon2.mem_cost,
+        time_cost: argon2.time_cost,
+        lanes: argon2.lanes,
+        thread_mode: ThreadMode::Sequential,
+        secret: pepper(argon2),
+        ad: &[],
+        hash_length: HASH_LENGTH,
+    };
     argon2::hash_encoded(password.as_bytes(), &salt, &config)
         .map_err(|_| RbError::Custom("Couldn't hash password."))
 }
+
+/// Checks a password against an encoded hash.
+///
+/// Returns `None` if the password is wrong, & otherwise whether the hash should be replaced by one
+/// using the current parameters.
+fn verify_password(argon2: &RbArgon2Conf, hash: &str, password: &str) -> Option<bool>
+{
+    let pepper = pepper(argon2);
+
+    if matches!(
+        verify_encoded_ext(hash, password.as_bytes(), pepper, &[]),
+        Ok(true)
+    ) {
+        return Some(is_outdated(argon2, hash));
+    }
+
+    // Hashes created before a pepper was configured don't use one
+    if !pepper.is_empty() && matches!(verify_encoded(hash, password.as_bytes()), Ok(true)) {
+        return Some(true);
+    }
+
+    None
+}
+
+/// Checks whether an encoded hash uses a different variant or weaker parameters than the current
+/// configuration. Hashes look like `$argon2id$v=19$m=4096,t=3,p=1$<salt>$<hash>`, where the
+/// version is missing for hashes created using version 1.0.
+fn is_outdated(argon2: &RbArgon2Conf, hash: &str) -> bool
+{
+    let parts: Vec<&str> = hash.split('$').collect();
+
+    if parts.get(1) != Some(&"argon2id") || parts.get(2) != Some(&"v=19") {
+        return true;
+    }
+
+    let mut mem_cost = 0;
+    let mut time_cost = 0;
+    let mut lanes = 0;
+
+    for param in parts.get(3).unwrap_or(&"").split(',') {
+        match param.split_once('=') {
+            Some(("m", val)) => mem_cost = val.parse().unwrap_or(0),
+            Some(("t", val)) => time_cost = val.parse().unwrap_or(0),
+            Some(("p", val)) => lanes = val.parse().unwrap_or(0),
+            _ => {},
+        }
+    }
+
+    mem_cost < argon2.mem_cost || time_cost < argon2.time_cost || lanes < argon2.lanes
+}
+
+/// Checks whether a string looks like an encoded Argon2 hash, e.g. one provided in the
+/// configuration. Hashes created using version 1.0 lack the version part.
+pub fn is_encoded_hash(hash: &str) -> bool
+{
+    let parts: Vec<&str> = hash.split('$').collect();
+
+    matches!(
+        parts.get(1),
+        Some(&"argon2i") | Some(&"argon2d") | Some(&"argon2id")
+    ) && (parts.len() == 5 || parts.len() == 6)
+        && parts.iter().skip(1).all(|part| !part.is_empty())
+}
+
+/// Makes sure a new password meets the minimum requirements.
+pub fn check_password(password: &str) -> RbResult<()>
+{
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(RbError::AuthWeakPassword);
+    }
+
+    Ok(())
+}
+
+/// Generates a random password, to be used as a temporary password.
+pub fn generate_password() -> String
+{
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TEMPORARY_PASSWORD_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+/// Replaces a user's password & revokes all of their refresh tokens, logging them out everywhere.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `argon2` - parameters to hash the new password with
+/// * `user_id` - ID of the user whose password should be changed
+/// * `password` - the new password
+/// * `must_change` - whether the user has to change the password before being able to log in
+pub fn change_password(
+    conn: &PgConnection,
+    argon2: &RbArgon2Conf,
+    user_id: Uuid,
+    password: &str,
+    must_change: bool,
+) -> RbResult<()>
+{
+    db::users::set_password(
+        conn,
+        user_id,
+        &hash_password(argon2, password)?,
+        must_change,
+    )
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// Salt & hash parts of an encoded hash; their contents don't matter for parsing
+    const TAIL: &str = "c2FsdHNhbHQ$aGFzaGhhc2hoYXNo";
+
+    fn conf() -> RbArgon2Conf
+    {
+        RbArgon2Conf {
+            mem_cost: 4096,
+            time_cost: 3,
+            lanes: 1,
+            pepper: None,
+        }
+    }
+
+    #[test]
+    fn is_outdated_accepts_current_params()
+    {
+        let conf = conf();
+
+        assert!(!is_outdated(
+            &conf,
+            &format!("$argon2id$v=19$m=4096,t=3,p=1${}", TAIL)
+        ));
+        assert!(!is_outdated(
+            &conf,
+            &format!("$argon2id$v=19$m=8192,t=4,p=2${}", TAIL)
+        ));
+    }
+
+    #[test]
+    fn is_outdated_rejects_other_variants_and_versions()
+    {
+        let conf = conf();
+
+        assert!(is_outdated(
+            &conf,
+            &format!("$argon2i$v=19$m=4096,t=3,p=1${}", TAIL)
+        ));
+        assert!(is_outdated(
+            &conf,
+            &format!("$argon2d$v=19$m=4096,t=3,p=1${}", TAIL)
+        ));
+        assert!(is_outdated(
+            &conf,
+            &format!("$argon2id$v=16$m=4096,t=3,p=1${}", TAIL)
+        ));
+        assert!(is_outdated(
+            &conf,
+            &format!("$argon2id$m=4096,t=3,p=1${}", TAIL)
+        ));
+    }
+
+    #[test]
+    fn is_outdated_rejects_weaker_params()
+    {
+        let conf = conf();
+
+        assert!(is_outdated(
+            &conf,
+            &format!("$argon2id$v=19$m=2048,t=3,p=1${}", TAIL)
+        ));
+        assert!(is_outdated(
+            &conf,
+            &format!("$argon2id$v=19$m=4096,t=2,p=1${}", TAIL)
+        ));
+        assert!(is_outdated(
+            &conf,
+            &format!("$argon2id$v=19$m=4096,t=3,p=0${}", TAIL)
+        ));
+        assert!(is_outdated(
+            &conf,
+            &format!("$argon2id$v=19$m=4096,t=3${}", TAIL)
+        ));
+        assert!(is_outdated(
+            &conf,
+            &format!("$argon2id$v=19$m=lots,t=3,p=1${}", TAIL)
+        ));
+    }
+
+    #[test]
+    fn is_outdated_accepts_own_hashes()
+    {
+        let conf = RbArgon2Conf {
+            mem_cost: 64,
+            time_cost: 1,
+            ..conf()
+        };
+        let hash = hash_password(&conf, "password").unwrap();
+
+        assert!(!is_outdated(&conf, &hash));
+        assert!(is_outdated(
+            &RbArgon2Conf {
+                time_cost: 2,
+                ..conf
+            },
+            &hash
+        ));
+    }
+
+    #[test]
+    fn is_encoded_hash_accepts_argon2_hashes()
+    {
+        assert!(is_encoded_hash(&format!(
+            "$argon2id$v=19$m=4096,t=3,p=1${}",
+            TAIL
+        )));
+        assert!(is_encoded_hash(&format!(
+            "$argon2i$v=16$m=4096,t=3,p=1${}",
+            TAIL
+        )));
+        assert!(is_encoded_hash(&format!(
+            "$argon2d$m=4096,t=3,p=1${}",
+            TAIL
+        )));
+    }
+
+    #[test]
+    fn is_encoded_hash_rejects_other_strings()
+    {
+        assert!(!is_encoded_hash("admin_pass"));
+        assert!(!is_encoded_hash(""));
+        assert!(!is_encoded_hash("$argon2id$"));
+        assert!(!is_encoded_hash("$argon2id$v=19$m=4096,t=3,p=1$$"));
+        assert!(!is_encoded_hash(&format!(
+            "$scrypt$v=19$m=4096,t=3,p=1${}",
+            TAIL
+        )));
+        assert!(!is_encoded_hash(&format!(
+            "$2b$v=19$m=4096,t=3,p=1${}",
+            TAIL
+        )));
+        assert!(!is_encoded_hash(&format!(
+            "$argon2id$v=19$m=4096,t=3,p=1${}$extra",
+            TAIL
+        )));
+    }
+}
@@ -0,0 +1,156 @@
+//! Allows users who forgot their password to reset it. A single-use, short-lived token is emailed
+//! to the user, which can then be exchanged for a new password.
+
+use std::sync::Arc;
+
+use chrono::Utc;
+use rocket::{serde::json::Json, State};
+use serde::Deserialize;
+
+use super::{hash_token, pass, random_token, revocation::TokenVersions, throttle};
+use crate::{
+    db,
+    errors::{RbError, RbOption, RbResult},
+    guards::ClientInfo,
+    mail::{self, Email, Mailer},
+    RbConfig, RbDbConn,
+};
+
+/// Amount of random bytes in a token
+const TOKEN_SIZE: usize = 32;
+
+#[derive(Deserialize)]
+pub struct ForgotPasswordRequest
+{
+    email: String,
+}
+
+/// Emails a password reset link to the user with the given email address. The response is the same
+/// whether or not such a user exists, so it can't be used to find out which addresses are in use.
+/// Requests are throttled per IP address, & no new link is sent while the previous one is still
+/// recent, so this can't be used to flood someone's inbox.
+///
+/// # Arguments
+///
+/// * `conn` - guard providing a connection to the database
+/// * `conf` - application configuration
+/// * `mailer` - mailer used to send the email
+/// * `client` - information about the client making the request
+/// * `request` - email address of the user
+#[post("/password/forgot", data = "<request>")]
+pub async fn forgot_password(
+    conn: RbDbConn,
+    conf: &State<RbConfig>,
+    mailer: &State<Arc<dyn Mailer>>,
+    client: ClientInfo,
+    request: Json<ForgotPasswordRequest>,
+) -> RbResult<()>
+{
+    let email = request.into_inner().email.trim().to_lowercase();
+    let token_expire = conf.password_reset.token_expire;
+    let resend_after = chrono::Duration::seconds(conf.password_reset.resend_after);
+    let throttle = conf.throttle.clone();
+
+    let (token, token_hash) = random_token(TOKEN_SIZE);
+
+    let user = conn
+        .run(move |c| -> RbOption<db::User> {
+            throttle::register_reset_request(c, &throttle, &client)?;
+
+            let user = match db::users::find_by_email(c, &email)? {
+                Some(user) if !user.blocked => user,
+                _ => return Ok(None),
+            };
+
+            // The previous email is probably still on its way
+            if let Some(previous) = db::password_resets::find_by_user(c, user.id)? {
+                if previous.created_at + resend_after > Utc::now().naive_utc() {
+                    return Ok(None);
+                }
+            }
+
+            db::password_resets::replace(
+                c,
+                &db::NewPasswordResetToken {
+                    token_hash,
+                    user_id: user.id,
+                    expires_at: (Utc::now() + chrono::Duration::seconds(token_expire)).naive_utc(),
+                },
+            )?;
+
+            Ok(Some(user))
+        })
+        .await?;
+
+    if let Some(user) = user {
+        let link = conf.password_reset.link.replace("{token}", &token);
+
+        mail::send_in_background(
+            Arc::clone(mailer),
+            Email {
+                // The user was found using their email address, so this is always set
+                to: user.email.unwrap_or_default(),
+                subject: String::from("Reset your password"),
+                body: format!(
+                    "Hi {},\n\nSomeone requested to reset your password. If this was you, you \
+                     can choose a new password using the following link, which is valid for {} \
+                     minutes:\n\n{}\n\nIf you didn't request this, you can safely ignore this \
+                     email.",
+                    user.username,
+                    token_expire / 60,
+                    link
+                ),
+            },
+        );
+    }
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResetPasswordRequest
+{
+    token: String,
+    new_password: String,
+}
+
+/// Sets a new password using a token received by email. This logs the user out everywhere.
+///
+/// # Arguments
+///
+/// * `conn` - guard providing a connection to the database
+/// * `conf` - application configuration
+/// * `versions` - cached token versions
+/// * `request` - the reset token & new password
+#[post("/password/reset", data = "<request>")]
+pub async fn reset_password(
+    conn: RbDbConn,
+    conf: &State<RbConfig>,
+    versions: &State<TokenVersions>,
+    request: Json<ResetPasswordRequest>,
+) -> RbResult<()>
+{
+    let request = request.into_inner();
+    let argon2 = conf.argon2.clone();
+
+    pass::check_password(&request.new_password)?;
+
+    let user_id = conn
+        .run(move |c| {
+            let token = db::password_resets::consume(c, &hash_token(&request.token))?
+                .ok_or(RbError::AuthInvalidResetToken)?;
+
+            if token.expires_at < Utc::now().naive_utc() {
+                return Err(RbError::AuthInvalidResetToken);
+            }
+
+            pass::change_password(c, &argon2, token.user_id, &request.new_password, false)?;
+
+            Ok(token.user_id)
+        })
+        .await?;
+    versions.forget(user_id);
+
+    Ok(())
+}
@@ -0,0 +1,89 @@
+//! Permissions define what a user is allowed to do. They're granted to users through their role,
+//! & are included in the user's JWTs. Routes declare the permission they require using the
+//! [`Authorized`](crate::guards::Authorized) guard.
+//!
+//! Personal access tokens can be limited to a subset of their user's permissions, called the
+//! token's scopes.
+//!
+//! Users can also be granted access to a single section, without their role granting them the
+//! matching permission for every section.
+
+use serde::Deserialize;
+
+/// A permission as used by the `Authorized` guard.
+pub trait Permission
+{
+    /// Name of the permission, as stored in the `permissions` table
+    const NAME: &'static str;
+}
+
+macro_rules! permissions {
+    ($($permission:ident => $name:literal,)*) => {
+        $(
+            pub struct $permission;
+
+            impl Permission for $permission
+            {
+                const NAME: &'static str = $name;
+            }
+        )*
+
+        /// Names of all existing permissions
+        pub const ALL: &[&str] = &[$($name,)*];
+    };
+}
+
+permissions! {
+    PostsWrite => "posts:write",
+    PostsEditAny => "posts:edit-any",
+    SectionsWrite => "sections:write",
+    UsersRead => "users:read",
+    UsersWrite => "users:write",
+}
+
+/// Level of access a user can be granted to a single section. Each level includes the ones before
+/// it.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SectionLevel
+{
+    /// Write posts & edit or delete your own posts
+    Write,
+    /// Edit or delete any post
+    EditAny,
+    /// Manage who has access to the section
+    Manage,
+}
+
+impl SectionLevel
+{
+    /// Name of the level, as stored in the database
+    pub fn name(&self) -> &'static str
+    {
+        match self {
+            SectionLevel::Write => "write",
+            SectionLevel::EditAny => "edit-any",
+            SectionLevel::Manage => "manage",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self>
+    {
+        match name {
+            "write" => Some(SectionLevel::Write),
+            "edit-any" => Some(SectionLevel::EditAny),
+            "manage" => Some(SectionLevel::Manage),
+            _ => None,
+        }
+    }
+
+    /// Permission granting this level of access to every section
+    pub fn permission(&self) -> &'static str
+    {
+        match self {
+            SectionLevel::Write => PostsWrite::NAME,
+            SectionLevel::EditAny => PostsEditAny::NAME,
+            SectionLevel::Manage => SectionsWrite::NAME,
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Personal access tokens are long-lived, revocable tokens for scripts & other non-interactive
+//! clients. They're sent as a regular bearer token, but can only be used for the scopes they were
+//! created with. Scopes are permissions, so a token can never do more than its user's role allows.
+
+use chrono::Utc;
+use diesel::PgConnection;
+use rocket::serde::json::Json;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{
+    hash_token,
+    permissions::{self, Permission, UsersWrite},
+    random_string,
+};
+use crate::{
+    db,
+    errors::{RbError, RbResult},
+    guards::User,
+    RbDbConn,
+};
+
+/// Prefix used to tell personal access tokens apart from JWTs
+pub const PREFIX: &str = "rbp_";
+/// Amount of random bytes in a token
+const TOKEN_SIZE: usize = 32;
+
+/// Checks whether the given personal access token is valid, returning it along with its user &
+/// the permissions granted by the user's role.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `token` - the token as provided by the client
+pub fn verify(
+    conn: &PgConnection,
+    token: &str,
+) -> RbResult<(db::PersonalToken, db::User, Vec<String>)>
+{
+    let (token, user) = db::personal_tokens::find_with_user(conn, &hash_token(token))?
+        .ok_or(RbError::AuthUnauthorized)?;
+
+    if matches!(token.expires_at, Some(expires_at) if expires_at < Utc::now().naive_utc()) {
+        return Err(RbError::AuthTokenExpired);
+    }
+
+    if user.blocked {
+        return Err(RbError::AuthBlockedUser(user.blocked_reason.clone()));
+    }
+
+    db::personal_tokens::update_last_used_at(conn, token.id)?;
+    let permissions = db::roles::permissions(conn, &user.role)?;
+
+    Ok((token, user, permissions))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewTokenRequest
+{
+    name: String,
+    scopes: Vec<String>,
+    expires_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewTokenResponse
+{
+    #[serde(flatten)]
+    info: db::PersonalToken,
+    /// The actual token; this is the only time it's returned
+    token: String,
+}
+
+/// Lists the logged-in user's personal access tokens.
+///
+/// # Arguments
+///
+/// * `user` - guard ensuring the user is logged in
+/// * `conn` - guard providing a connection to the database
+#[get("/")]
+pub async fn list(user: User, conn: RbDbConn) -> RbResult<Json<Vec<db::PersonalToken>>>
+{
+    Ok(Json(
+        conn.run(move |c| db::personal_tokens::find_by_user(c, user.0.id))
+            .await?,
+    ))
+}
+
+/// Creates a new personal access token for the logged-in user.
+///
+/// # Arguments
+///
+/// * `user` - guard ensuring the user is logged in
+/// * `conn` - guard providing a connection to the database
+/// * `new_token` - name, scopes & optional expiry date of the token
+#[post("/", data = "<new_token>")]
+pub async fn create(
+    user: User,
+    conn: RbDbConn,
+    new_token: Json<NewTokenRequest>,
+) -> RbResult<Json<NewTokenResponse>>
+{
+    let mut new_token = new_token.into_inner();
+
+    new_token.name = new_token.name.trim().to_string();
+    db::personal_tokens::check_name(&new_token.name)?;
+
+    if matches!(new_token.expires_at, Some(expires_at) if expires_at <= Utc::now().naive_utc()) {
+        return Err(RbError::AuthInvalidTokenExpiry);
+    }
+
+    if new_token.scopes.is_empty()
+        || new_token
+            .scopes
+            .iter()
+            .any(|scope| !permissions::ALL.contains(&scope.as_str()))
+    {
+        return Err(RbError::AuthInvalidScope);
+    }
+
+    // The prefix is part of the hashed token
+    let token = format!("{}{}", PREFIX, random_string(TOKEN_SIZE));
+    let token_hash = hash_token(&token);
+
+    let info = conn
+        .run(move |c| {
+            db::personal_tokens::create(
+                c,
+                &db::NewPersonalToken {
+                    user_id: user.0.id,
+                    name: new_token.name,
+                    token_hash,
+                    scopes: new_token.scopes,
+                    expires_at: new_token.expires_at,
+                },
+            )
+        })
+        .await?;
+
+    Ok(Json(NewTokenResponse { info, token }))
+}
+
+/// Revokes a personal access token. Users can only revoke their own tokens, while admins can
+/// revoke any token.
+///
+/// # Arguments
+///
+/// * `user` - guard ensuring the user is logged in
+/// * `conn` - guard providing a connection to the database
+/// * `id` - ID of the token to revoke
+#[delete("/<id>")]
+pub async fn revoke(user: User, conn: RbDbConn, id: Uuid) -> RbResult<()>
+{
+    let claims = user.0;
+
+    conn.run(move |c| {
+        let token = db::personal_tokens::find(c, id)?.ok_or(RbError::AuthUnknownToken)?;
+
+        if token.user_id != claims.id && !claims.has_permission(UsersWrite::NAME) {
+            return Err(RbError::AuthUnknownToken);
+        }
+
+        db::personal_tokens::delete(c, token.id)
+    })
+    .await
+}
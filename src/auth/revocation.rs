@@ -0,0 +1,69 @@
+//! Allows revoking access tokens before they expire. Every user has a token version, which is
+//! embedded into their access tokens & incremented whenever their tokens should stop being
+//! accepted, e.g. after being blocked, changing roles or logging out. To avoid querying the database
+//! on every request, versions are cached for a short while; changes made by this instance are
+//! applied to the cache immediately, while other instances pick them up once their cached entry
+//! expires.
+
+use std::{
+    collections::HashMap,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use uuid::Uuid;
+
+/// Cache of the users' current token versions
+#[derive(Default)]
+pub struct TokenVersions
+{
+    cache: RwLock<HashMap<Uuid, (i32, Instant)>>,
+}
+
+impl TokenVersions
+{
+    /// Returns the cached token version of a user, if it was fetched less than `ttl` ago.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - ID of the user
+    /// * `ttl` - how long a cached version can be used
+    pub fn get(&self, user_id: Uuid, ttl: Duration) -> Option<i32>
+    {
+        let cache = self.cache.read().ok()?;
+
+        cache
+            .get(&user_id)
+            .filter(|(_, fetched_at)| fetched_at.elapsed() < ttl)
+            .map(|(version, _)| *version)
+    }
+
+    /// Stores a token version that was just fetched from the database. Expired entries are
+    /// removed along the way, so the cache only grows with the amount of active users.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - ID of the user
+    /// * `version` - the user's current token version
+    /// * `ttl` - how long a cached version can be used
+    pub fn insert(&self, user_id: Uuid, version: i32, ttl: Duration)
+    {
+        if let Ok(mut cache) = self.cache.write() {
+            cache.retain(|_, (_, fetched_at)| fetched_at.elapsed() < ttl);
+            cache.insert(user_id, (version, Instant::now()));
+        }
+    }
+
+    /// Removes a user's cached version after it's been changed, so the next request fetches the
+    /// new one.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - ID of the user
+    pub fn forget(&self, user_id: Uuid)
+    {
+        if let Ok(mut cache) = self.cache.write() {
+            cache.remove(&user_id);
+        }
+    }
+}
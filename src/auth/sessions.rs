@@ -0,0 +1,89 @@
+//! Routes for listing & revoking a user's active sessions. A session corresponds to a family of
+//! refresh tokens, and is referred to using the family's ID, which stays the same when the refresh
+//! token is rotated.
+
+use diesel::Connection;
+use rocket::{serde::json::Json, State};
+use uuid::Uuid;
+
+use crate::{
+    auth::{
+        permissions::{Permission, UsersRead, UsersWrite},
+        revocation::TokenVersions,
+    },
+    db,
+    errors::{RbError, RbResult},
+    guards::User,
+    RbDbConn,
+};
+
+/// Lists the active sessions of the logged-in user. Admins can list another user's sessions by
+/// providing their ID.
+///
+/// # Arguments
+///
+/// * `user` - guard ensuring the user is logged in
+/// * `conn` - guard providing a connection to the database
+/// * `user_id` - optional ID of the user whose sessions should be listed
+#[get("/?<user_id>")]
+pub async fn list(
+    user: User,
+    conn: RbDbConn,
+    user_id: Option<Uuid>,
+) -> RbResult<Json<Vec<db::RefreshToken>>>
+{
+    let claims = user.0;
+    let user_id = user_id.unwrap_or(claims.id);
+
+    if user_id != claims.id && !claims.has_permission(UsersRead::NAME) {
+        return Err(RbError::AuthUnauthorized);
+    }
+
+    Ok(Json(
+        conn.run(move |c| db::tokens::find_active_by_user(c, user_id))
+            .await?,
+    ))
+}
+
+/// Revokes the session with the given ID. Users can only revoke their own sessions, while admins
+/// can revoke any session. The session's owner has to refresh their other sessions' access tokens,
+/// as these can't be traced back to a single session.
+///
+/// # Arguments
+///
+/// * `user` - guard ensuring the user is logged in
+/// * `conn` - guard providing a connection to the database
+/// * `versions` - cached token versions
+/// * `id` - ID of the session to revoke
+#[delete("/<id>")]
+pub async fn revoke(
+    user: User,
+    conn: RbDbConn,
+    versions: &State<TokenVersions>,
+    id: Uuid,
+) -> RbResult<()>
+{
+    let claims = user.0;
+
+    let user_id = conn
+        .run(move |c| {
+            let session =
+                db::tokens::find_active_by_family(c, id)?.ok_or(RbError::AuthUnknownSession)?;
+
+            // Other users' sessions are reported as unknown to avoid leaking their existence
+            if session.user_id != claims.id && !claims.has_permission(UsersWrite::NAME) {
+                return Err(RbError::AuthUnknownSession);
+            }
+
+            c.transaction::<_, RbError, _>(|| {
+                db::tokens::delete_family(c, session.family_id)?;
+                db::users::revoke_access_tokens(c, session.user_id)
+            })?;
+
+            Ok(session.user_id)
+        })
+        .await?;
+    versions.forget(user_id);
+
+    Ok(())
+}
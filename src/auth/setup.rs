@@ -0,0 +1,124 @@
+//! Allows creating the first admin user without putting their password in the configuration. If no
+//! admin exists & no password is configured, a one-time setup token is printed on startup, which
+//! can be exchanged for an admin account. Its hash is only kept in memory.
+
+use std::sync::Mutex;
+
+use rocket::{serde::json::Json, State};
+use serde::Deserialize;
+
+use super::{hash_token, pass, random_token};
+use crate::{
+    admin, db,
+    errors::{RbError, RbResult},
+    guards::ClientInfo,
+    RbConfig, RbDbConn,
+};
+
+/// Amount of random bytes in a setup token
+const TOKEN_SIZE: usize = 24;
+
+/// Hash of the current setup token, if there is one
+#[derive(Default)]
+pub struct SetupToken(Mutex<Option<Vec<u8>>>);
+
+impl SetupToken
+{
+    /// Generates a new setup token, replacing the previous one.
+    pub fn generate(&self) -> String
+    {
+        let (token, token_hash) = random_token(TOKEN_SIZE);
+
+        if let Ok(mut hash) = self.0.lock() {
+            *hash = Some(token_hash);
+        }
+
+        token
+    }
+
+    /// Takes out the current setup token if it's the given one, so no other request can use it in
+    /// the meantime. Returns its hash, which can be put back using `restore`.
+    fn take(&self, token: &str) -> Option<Vec<u8>>
+    {
+        let mut hash = self.0.lock().ok()?;
+
+        if hash.as_deref() == Some(&hash_token(token)[..]) {
+            hash.take()
+        } else {
+            None
+        }
+    }
+
+    /// Puts back a token taken out using `take`, unless a new one was generated in the meantime.
+    fn restore(&self, token_hash: Vec<u8>)
+    {
+        if let Ok(mut hash) = self.0.lock() {
+            hash.get_or_insert(token_hash);
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SetupRequest
+{
+    token: String,
+    username: String,
+    password: String,
+}
+
+/// Creates the first admin user using the setup token printed on startup. The token can only be
+/// used once, & stops working as soon as an admin exists.
+///
+/// # Arguments
+///
+/// * `conn` - guard providing a connection to the database
+/// * `conf` - application configuration
+/// * `setup_token` - the current setup token
+/// * `client` - information about the client making the request
+/// * `request` - setup token, username & password of the new admin
+#[post("/setup", data = "<request>")]
+pub async fn setup(
+    conn: RbDbConn,
+    conf: &State<RbConfig>,
+    setup_token: &State<SetupToken>,
+    client: ClientInfo,
+    request: Json<SetupRequest>,
+) -> RbResult<()>
+{
+    let request = request.into_inner();
+
+    // Otherwise, concurrent requests could each create an admin
+    let token_hash = setup_token
+        .take(&request.token)
+        .ok_or(RbError::AuthInvalidSetupToken)?;
+
+    match create_admin(conn, conf, client, request).await {
+        Ok(true) => Ok(()),
+        // An admin exists, so the token is of no use anymore
+        Ok(false) => Err(RbError::AuthInvalidSetupToken),
+        // Allows trying again, e.g. with a stronger password
+        Err(err) => {
+            setup_token.restore(token_hash);
+            Err(err)
+        },
+    }
+}
+
+/// Creates the admin requested using the setup token, returning whether one was created.
+async fn create_admin(
+    conn: RbDbConn,
+    conf: &RbConfig,
+    client: ClientInfo,
+    request: SetupRequest,
+) -> RbResult<bool>
+{
+    db::users::check_username(&request.username)?;
+    pass::check_password(&request.password)?;
+
+    let password_hash = pass::hash_password(&conf.argon2, &request.password)?;
+
+    conn.run(move |c| {
+        admin::create_admin_user(c, &request.username, password_hash, &client.actor(None))
+    })
+    .await
+}
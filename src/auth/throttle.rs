@@ -0,0 +1,376 @@
+//! Slows down password guessing by tracking failed login attempts per username & per client IP.
+//! After a few free attempts, each failure delays the next attempt exponentially longer, until the
+//! username or IP address gets locked out for a while. Two-factor codes are throttled the same way,
+//! per user & per client IP, as are password reset requests per client IP.
+
+use chrono::{Duration, Utc};
+use diesel::PgConnection;
+use uuid::Uuid;
+
+use super::pass;
+use crate::{
+    db,
+    errors::{RbError, RbResult},
+    guards::ClientInfo,
+    RbArgon2Conf, RbThrottleConf,
+};
+
+/// Throttles failed attempts for a username
+pub const KIND_USER: &str = "user";
+/// Throttles failed attempts from an IP address
+pub const KIND_IP: &str = "ip";
+/// Throttles failed two-factor codes for a user, identified by their ID. This is kept separate from
+/// the username's throttle, as that one is cleared by logging in with the right password.
+pub const KIND_TOTP: &str = "totp";
+/// Throttles password reset requests from an IP address, as each one sends an email
+pub const KIND_RESET: &str = "reset";
+
+/// Returns the pairs of kinds & subjects an attempt counts towards.
+fn subjects<'a>(
+    kind: &'static str,
+    subject: &'a str,
+    client: &'a ClientInfo,
+) -> Vec<(&'static str, &'a str)>
+{
+    let mut subjects = vec![(kind, subject)];
+
+    if let Some(ip_address) = &client.ip_address {
+        subjects.push((KIND_IP, ip_address.as_str()));
+    }
+
+    subjects
+}
+
+/// Calculates how long attempts should be delayed after the given amount of failures, in seconds.
+fn delay(throttle: &RbThrottleConf, kind: &str, failures: i32) -> i64
+{
+    // Many users can share an IP address, so these usually get more leeway
+    let (free_attempts, lockout_threshold) = match kind {
+        KIND_IP => (throttle.ip_free_attempts, throttle.ip_lockout_threshold),
+        _ => (throttle.free_attempts, throttle.lockout_threshold),
+    };
+
+    if failures >= lockout_threshold {
+        throttle.lockout_duration
+    } else if failures <= free_attempts {
+        0
+    } else {
+        // Capping the exponent prevents overflows; the delay is capped anyways
+        let exponent = (failures - free_attempts - 1).min(32) as u32;
+
+        throttle
+            .base_delay
+            .saturating_mul(2i64.pow(exponent))
+            .min(throttle.max_delay)
+    }
+}
+
+/// Checks whether a login attempt is currently allowed for the given username & client.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `username` - username the attempt is made for
+/// * `client` - client making the attempt
+pub fn check(conn: &PgConnection, username: &str, client: &ClientInfo) -> RbResult<()>
+{
+    check_subjects(conn, &subjects(KIND_USER, username, client))
+}
+
+/// Checks whether a two-factor code may currently be tried for the given user & client.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `user_id` - ID of the user the code is provided for
+/// * `client` - client providing the code
+pub fn check_code(conn: &PgConnection, user_id: Uuid, client: &ClientInfo) -> RbResult<()>
+{
+    check_subjects(conn, &subjects(KIND_TOTP, &user_id.to_string(), client))
+}
+
+fn check_subjects(conn: &PgConnection, subjects: &[(&str, &str)]) -> RbResult<()>
+{
+    let now = Utc::now().naive_utc();
+    let retry_after = db::login_throttles::find_locked(conn, subjects)?
+        .iter()
+        .filter_map(|throttle| throttle.locked_until)
+        .map(|locked_until| (locked_until - now).num_seconds() + 1)
+        .max();
+
+    match retry_after {
+        Some(retry_after) => Err(RbError::AuthTooManyAttempts(retry_after)),
+        None => Ok(()),
+    }
+}
+
+/// Registers a failed login attempt for the given username & client, delaying further attempts if
+/// needed.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `throttle` - throttling configuration
+/// * `username` - username the attempt was made for
+/// * `client` - client that made the attempt
+pub fn register_failure(
+    conn: &PgConnection,
+    throttle: &RbThrottleConf,
+    username: &str,
+    client: &ClientInfo,
+) -> RbResult<()>
+{
+    register_failures(conn, throttle, &subjects(KIND_USER, username, client))
+}
+
+/// Registers a wrong two-factor code for the given user & client, delaying further attempts if
+/// needed.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `throttle` - throttling configuration
+/// * `user_id` - ID of the user the code was provided for
+/// * `client` - client that provided the code
+pub fn register_code_failure(
+    conn: &PgConnection,
+    throttle: &RbThrottleConf,
+    user_id: Uuid,
+    client: &ClientInfo,
+) -> RbResult<()>
+{
+    register_failures(
+        conn,
+        throttle,
+        &subjects(KIND_TOTP, &user_id.to_string(), client),
+    )
+}
+
+/// Counts a password reset request towards the client's IP address, rejecting it if the client has
+/// made too many requests recently.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `throttle` - throttling configuration
+/// * `client` - client requesting the reset
+pub fn register_reset_request(
+    conn: &PgConnection,
+    throttle: &RbThrottleConf,
+    client: &ClientInfo,
+) -> RbResult<()>
+{
+    let subjects: Vec<_> = client
+        .ip_address
+        .iter()
+        .map(|ip_address| (KIND_RESET, ip_address.as_str()))
+        .collect();
+
+    check_subjects(conn, &subjects)?;
+    register_failures(conn, throttle, &subjects)
+}
+
+fn register_failures(
+    conn: &PgConnection,
+    throttle: &RbThrottleConf,
+    subjects: &[(&'static str, &str)],
+) -> RbResult<()>
+{
+    let now = Utc::now().naive_utc();
+
+    // Failures are forgotten after a while
+    db::login_throttles::delete_stale(conn, now - Duration::seconds(throttle.reset_after))?;
+
+    for &(kind, subject) in subjects {
+        let failures = db::login_throttles::add_failure(conn, kind, subject)?.failures;
+        let delay = delay(throttle, kind, failures);
+
+        if delay > 0 {
+            db::login_throttles::lock(conn, kind, subject, now + Duration::seconds(delay))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Clears the failed attempts for a username after a successful login. Failures from the client's
+/// IP address are kept, as a single valid account shouldn't allow guessing other passwords.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `username` - username that successfully logged in
+pub fn register_success(conn: &PgConnection, username: &str) -> RbResult<()>
+{
+    db::login_throttles::delete(conn, KIND_USER, username).map(|_| ())
+}
+
+/// Clears the wrong two-factor codes of a user after a valid one was provided.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `user_id` - ID of the user that provided a valid code
+pub fn register_code_success(conn: &PgConnection, user_id: Uuid) -> RbResult<()>
+{
+    db::login_throttles::delete(conn, KIND_TOTP, &user_id.to_string()).map(|_| ())
+}
+
+/// Verifies a user's credentials, rejecting the attempt if the client is being throttled &
+/// registering the outcome otherwise.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `throttle` - throttling configuration
+/// * `argon2` - password hashing configuration
+/// * `username` - username the attempt is made for
+/// * `password` - password provided by the client
+/// * `client` - client making the attempt
+pub fn verify_user(
+    conn: &PgConnection,
+    throttle: &RbThrottleConf,
+    argon2: &RbArgon2Conf,
+    username: &str,
+    password: &str,
+    client: &ClientInfo,
+) -> RbResult<db::User>
+{
+    let user = verify_credentials(conn, throttle, argon2, username, password, client)?;
+    register_success(conn, username)?;
+
+    Ok(user)
+}
+
+/// Verifies a user's credentials like [`verify_user`], but only registers failures. This allows
+/// the caller to require more proof, e.g. a two-factor code, before the failures are cleared using
+/// [`register_success`].
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `throttle` - throttling configuration
+/// * `argon2` - password hashing configuration
+/// * `username` - username the attempt is made for
+/// * `password` - password provided by the client
+/// * `client` - client making the attempt
+pub fn verify_credentials(
+    conn: &PgConnection,
+    throttle: &RbThrottleConf,
+    argon2: &RbArgon2Conf,
+    username: &str,
+    password: &str,
+    client: &ClientInfo,
+) -> RbResult<db::User>
+{
+    // No user can have this username, & it wouldn't fit in the throttles table; the attempt still
+    // counts towards the client's IP address
+    if username.chars().count() > db::users::MAX_USERNAME_LENGTH {
+        let ip_subjects: Vec<_> = client
+            .ip_address
+            .iter()
+            .map(|ip_address| (KIND_IP, ip_address.as_str()))
+            .collect();
+
+        check_subjects(conn, &ip_subjects)?;
+        pass::verify_dummy(argon2, password)?;
+        register_failures(conn, throttle, &ip_subjects)?;
+
+        return Err(RbError::AuthInvalidCredentials);
+    }
+
+    check(conn, username, client)?;
+
+    match pass::verify_user(conn, argon2, username, password) {
+        Err(err @ RbError::AuthInvalidCredentials) => {
+            register_failure(conn, throttle, username, client)?;
+
+            Err(err)
+        },
+        res => res,
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn conf() -> RbThrottleConf
+    {
+        RbThrottleConf {
+            free_attempts: 3,
+            ip_free_attempts: 20,
+            base_delay: 1,
+            max_delay: 60,
+            lockout_threshold: 10,
+            ip_lockout_threshold: 100,
+            lockout_duration: 900,
+            reset_after: 3600,
+        }
+    }
+
+    #[test]
+    fn delay_allows_free_attempts()
+    {
+        let conf = conf();
+
+        for failures in 0..=3 {
+            assert_eq!(delay(&conf, KIND_USER, failures), 0);
+        }
+    }
+
+    #[test]
+    fn delay_doubles_up_to_max()
+    {
+        let conf = conf();
+        let delays: Vec<i64> = (4..10)
+            .map(|failures| delay(&conf, KIND_USER, failures))
+            .collect();
+
+        assert_eq!(delays, vec![1, 2, 4, 8, 16, 32]);
+        assert_eq!(delay(&conf, KIND_IP, 27), 60);
+        assert_eq!(delay(&conf, KIND_IP, 99), 60);
+    }
+
+    #[test]
+    fn delay_locks_out_at_threshold()
+    {
+        let conf = conf();
+
+        assert_eq!(delay(&conf, KIND_USER, 9), 32);
+        assert_eq!(delay(&conf, KIND_USER, 10), 900);
+        assert_eq!(delay(&conf, KIND_USER, 1000), 900);
+        assert_eq!(delay(&conf, KIND_IP, 100), 900);
+    }
+
+    #[test]
+    fn delay_uses_ip_limits_for_ips_only()
+    {
+        let conf = conf();
+
+        assert_eq!(delay(&conf, KIND_IP, 20), 0);
+        assert_eq!(delay(&conf, KIND_IP, 21), 1);
+        assert_eq!(delay(&conf, KIND_TOTP, 20), 900);
+        assert_eq!(delay(&conf, KIND_RESET, 4), 1);
+    }
+
+    #[test]
+    fn delay_does_not_overflow()
+    {
+        let conf = RbThrottleConf {
+            max_delay: i64::MAX,
+            lockout_threshold: i32::MAX,
+            ..conf()
+        };
+
+        assert_eq!(delay(&conf, KIND_USER, i32::MAX - 1), 1 << 32);
+
+        let conf = RbThrottleConf {
+            base_delay: 1 << 40,
+            ..conf
+        };
+
+        assert_eq!(delay(&conf, KIND_USER, i32::MAX - 1), i64::MAX);
+    }
+}
@@ -0,0 +1,385 @@
+//! Implements two-factor authentication using time-based one-time passwords (RFC 6238), along with
+//! single-use recovery codes. Users with 2FA enabled receive a short-lived challenge token when
+//! logging in, which is exchanged for a regular JWT & refresh token pair once a valid code is
+//! provided.
+
+use chrono::Utc;
+use diesel::PgConnection;
+use hmac::{Hmac, Mac, NewMac};
+use rand::{thread_rng, Rng};
+use rocket::http::RawStr;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use super::{
+    jwt::{generate_jwt_token, JWTResponse},
+    keys::JwtKeys,
+    throttle,
+};
+use crate::{
+    db,
+    errors::{RbError, RbResult},
+    guards::ClientInfo,
+    RbJwtConf, RbThrottleConf, RbTotpConf,
+};
+
+/// Length of a time step, in seconds
+const STEP: i64 = 30;
+/// Amount of digits in a code
+const DIGITS: u32 = 6;
+/// Amount of steps before & after the current one that are also accepted, to account for clock
+/// drift
+const ALLOWED_DRIFT: i64 = 1;
+/// Size of the generated secrets, in bytes
+const SECRET_SIZE: usize = 20;
+/// Amount of recovery codes generated for each user
+const RECOVERY_CODES: usize = 10;
+
+/// Claims of a challenge token, which proves the user has provided valid credentials & only needs
+/// to provide a TOTP code.
+#[derive(Serialize, Deserialize)]
+pub struct ChallengeClaims
+{
+    pub sub: Uuid,
+    pub exp: i64,
+    pub iat: i64,
+    pub iss: String,
+    pub aud: String,
+    /// Label to use for the session that's created once the challenge is completed
+    pub device_label: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TotpSetup
+{
+    /// Base32-encoded secret
+    secret: String,
+    /// `otpauth://` URI that can be rendered as a QR code for authenticator apps
+    provisioning_uri: String,
+}
+
+/// Challenge tokens use a separate audience, so they can never be mistaken for access tokens.
+fn challenge_audience(jwt: &RbJwtConf) -> String
+{
+    format!("{}:2fa", jwt.audience)
+}
+
+/// Calculates the code for the given time step.
+fn code_at(secret: &[u8], step: i64) -> u32
+{
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    // Dynamic truncation as described in RFC 4226
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+
+    binary % 10u32.pow(DIGITS)
+}
+
+/// Checks the code against the steps surrounding the given time.
+///
+/// Returns the matching time step, if any.
+fn find_step(secret: &[u8], code: &str, time: i64) -> Option<i64>
+{
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let code: u32 = code.parse().ok()?;
+    let current = time / STEP;
+
+    (current - ALLOWED_DRIFT..=current + ALLOWED_DRIFT).find(|&step| code_at(secret, step) == code)
+}
+
+/// Recovery codes are random, so a plain hash is enough to store them safely.
+fn hash_recovery_code(code: &str) -> Vec<u8>
+{
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+
+    Sha256::digest(normalized.as_bytes()).to_vec()
+}
+
+fn generate_recovery_code() -> String
+{
+    let mut bytes = [0u8; 7];
+    thread_rng().fill(&mut bytes[..]);
+
+    let encoded =
+        base32::encode(base32::Alphabet::RFC4648 { padding: false }, &bytes).to_lowercase();
+
+    format!("{}-{}", &encoded[..5], &encoded[5..10])
+}
+
+/// Verifies a TOTP or recovery code for the given user. A valid code can't be used again. Wrong
+/// codes are throttled, as the small amount of possible codes could otherwise be guessed.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `throttle` - throttling configuration
+/// * `user` - user to verify the code for
+/// * `code` - either a TOTP code or one of the user's recovery codes
+/// * `client` - client providing the code
+pub fn verify_code(
+    conn: &PgConnection,
+    throttle: &RbThrottleConf,
+    user: &db::User,
+    code: &str,
+    client: &ClientInfo,
+) -> RbResult<()>
+{
+    let secret = match (&user.totp_secret, user.totp_enabled) {
+        (Some(secret), true) => secret,
+        _ => return Err(RbError::AuthTotpNotSetUp),
+    };
+
+    throttle::check_code(conn, user.id, client)?;
+
+    let valid = match find_step(secret, code.trim(), Utc::now().timestamp()) {
+        Some(step) => db::users::claim_totp_step(conn, user.id, step)?,
+        None => db::recovery_codes::consume(conn, user.id, &hash_recovery_code(code))?,
+    };
+
+    if valid {
+        throttle::register_code_success(conn, user.id)
+    } else {
+        throttle::register_code_failure(conn, throttle, user.id, client)?;
+
+        Err(RbError::AuthInvalidTotpCode)
+    }
+}
+
+/// Generates a new TOTP secret for the user. 2FA only becomes active once the secret has been
+/// confirmed using [`confirm`].
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `totp` - TOTP configuration
+/// * `user` - user to set up 2FA for
+pub fn setup(conn: &PgConnection, totp: &RbTotpConf, user: &db::User) -> RbResult<TotpSetup>
+{
+    if user.totp_enabled {
+        return Err(RbError::AuthTotpAlreadyEnabled);
+    }
+
+    let mut secret = [0u8; SECRET_SIZE];
+    thread_rng().fill(&mut secret[..]);
+
+    db::users::set_totp(conn, user.id, Some(&secret), false)?;
+
+    let secret = base32::encode(base32::Alphabet::RFC4648 { padding: false }, &secret);
+    let issuer = RawStr::new(&totp.issuer).percent_encode();
+    let provisioning_uri = format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        issuer,
+        RawStr::new(&user.username).percent_encode(),
+        secret,
+        issuer,
+        DIGITS,
+        STEP
+    );
+
+    Ok(TotpSetup {
+        secret,
+        provisioning_uri,
+    })
+}
+
+/// Enables 2FA once the user has proven their authenticator works, returning a fresh set of
+/// recovery codes. The codes themselves are only stored as hashes, so this is the only time
+/// they're visible.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `user` - user confirming their 2FA setup
+/// * `code` - current TOTP code
+pub fn confirm(conn: &PgConnection, user: &db::User, code: &str) -> RbResult<Vec<String>>
+{
+    if user.totp_enabled {
+        return Err(RbError::AuthTotpAlreadyEnabled);
+    }
+
+    let secret = user.totp_secret.as_ref().ok_or(RbError::AuthTotpNotSetUp)?;
+    let step = find_step(secret, code.trim(), Utc::now().timestamp())
+        .ok_or(RbError::AuthInvalidTotpCode)?;
+
+    db::users::set_totp(conn, user.id, Some(secret), true)?;
+    db::users::claim_totp_step(conn, user.id, step)?;
+
+    let codes: Vec<String> = (0..RECOVERY_CODES)
+        .map(|_| generate_recovery_code())
+        .collect();
+    let hashes: Vec<Vec<u8>> = codes.iter().map(|c| hash_recovery_code(c)).collect();
+
+    db::recovery_codes::replace(conn, user.id, &hashes)?;
+
+    Ok(codes)
+}
+
+/// Disables 2FA, removing the user's secret & recovery codes.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `throttle` - throttling configuration
+/// * `user` - user disabling 2FA
+/// * `code` - TOTP or recovery code, proving the user still has access to their second factor
+/// * `client` - client disabling 2FA
+pub fn disable(
+    conn: &PgConnection,
+    throttle: &RbThrottleConf,
+    user: &db::User,
+    code: &str,
+    client: &ClientInfo,
+) -> RbResult<()>
+{
+    verify_code(conn, throttle, user, code, client)?;
+
+    db::users::set_totp(conn, user.id, None, false)?;
+    db::recovery_codes::delete_by_user(conn, user.id)
+}
+
+/// Creates a challenge token for a user that has provided valid credentials but still needs to
+/// provide a TOTP code.
+///
+/// # Arguments
+///
+/// * `jwt` - JWT configuration
+/// * `totp` - TOTP configuration
+/// * `keys` - keys to sign the token with
+/// * `user` - user that's logging in
+/// * `device_label` - label for the session that will be created
+pub fn create_challenge(
+    jwt: &RbJwtConf,
+    totp: &RbTotpConf,
+    keys: &JwtKeys,
+    user: &db::User,
+    device_label: Option<String>,
+) -> RbResult<String>
+{
+    let now = Utc::now().timestamp();
+
+    keys.sign(&ChallengeClaims {
+        sub: user.id,
+        exp: now + totp.challenge_expire,
+        iat: now,
+        iss: jwt.issuer.clone(),
+        aud: challenge_audience(jwt),
+        device_label,
+    })
+}
+
+/// Completes a login challenge, generating a JWT & refresh token pair if the code is valid.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `jwt` - JWT configuration
+/// * `throttle` - throttling configuration
+/// * `keys` - keys used to verify the challenge & sign the new JWT
+/// * `challenge_token` - token returned when logging in
+/// * `code` - TOTP or recovery code
+/// * `client` - information about the client completing the challenge
+pub fn complete_challenge(
+    conn: &PgConnection,
+    jwt: &RbJwtConf,
+    throttle: &RbThrottleConf,
+    keys: &JwtKeys,
+    challenge_token: &str,
+    code: &str,
+    client: &ClientInfo,
+) -> RbResult<JWTResponse>
+{
+    let claims: ChallengeClaims = keys
+        .verify(challenge_token)
+        .map_err(|_| RbError::AuthInvalidChallenge)?;
+
+    if claims.iss != jwt.issuer
+        || claims.aud != challenge_audience(jwt)
+        || claims.exp < Utc::now().timestamp()
+    {
+        return Err(RbError::AuthInvalidChallenge);
+    }
+
+    let user = db::users::find(conn, claims.sub).ok_or(RbError::AuthInvalidChallenge)?;
+
+    if user.blocked {
+        return Err(RbError::AuthBlockedUser(user.blocked_reason.clone()));
+    }
+
+    verify_code(conn, throttle, &user, code, client)?;
+
+    generate_jwt_token(conn, jwt, keys, &user, client, claims.device_label)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// Secret used by the SHA-1 test vectors in appendix B of RFC 6238
+    const SECRET: &[u8] = b"12345678901234567890";
+
+    /// The RFC's 8-digit codes, truncated to our 6 digits, along with their times
+    const VECTORS: [(i64, &str); 6] = [
+        (59, "287082"),
+        (1111111109, "081804"),
+        (1111111111, "050471"),
+        (1234567890, "005924"),
+        (2000000000, "279037"),
+        (20000000000, "353130"),
+    ];
+
+    #[test]
+    fn code_at_matches_rfc_vectors()
+    {
+        for (time, code) in VECTORS.iter() {
+            assert_eq!(format!("{:06}", code_at(SECRET, time / STEP)), *code);
+        }
+    }
+
+    #[test]
+    fn find_step_accepts_rfc_vectors()
+    {
+        for (time, code) in VECTORS.iter() {
+            assert_eq!(find_step(SECRET, code, *time), Some(time / STEP));
+        }
+    }
+
+    #[test]
+    fn find_step_allows_drift()
+    {
+        let (time, code) = VECTORS[1];
+
+        assert_eq!(find_step(SECRET, code, time - STEP), Some(time / STEP));
+        assert_eq!(find_step(SECRET, code, time + STEP), Some(time / STEP));
+        assert_eq!(find_step(SECRET, code, time + 2 * STEP), None);
+    }
+
+    #[test]
+    fn find_step_rejects_malformed_codes()
+    {
+        let (time, code) = VECTORS[0];
+
+        assert_eq!(find_step(SECRET, "94287082", time), None);
+        assert_eq!(find_step(SECRET, &code[1..], time), None);
+        assert_eq!(find_step(SECRET, "28708a", time), None);
+        assert_eq!(find_step(SECRET, "+28708", time), None);
+    }
+}
@@ -0,0 +1,160 @@
+//! Routes for managing two-factor authentication & completing two-factor login challenges.
+
+use rocket::{http::CookieJar, serde::json::Json, State};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    cookies::TokenResponse,
+    keys::JwtKeys,
+    totp::{self, TotpSetup},
+};
+use crate::{
+    db,
+    errors::{RbError, RbResult},
+    guards::{ClientInfo, User},
+    RbConfig, RbDbConn,
+};
+
+#[derive(Deserialize)]
+pub struct CodeRequest
+{
+    /// Either a TOTP code or, where accepted, a recovery code
+    code: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecoveryCodes
+{
+    recovery_codes: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChallengeResponse
+{
+    challenge_token: String,
+    code: String,
+    /// Whether to set the tokens as cookies instead of returning them
+    #[serde(default)]
+    cookies: bool,
+}
+
+/// Generates a new TOTP secret for the logged-in user. 2FA isn't enforced until the secret has
+/// been confirmed.
+///
+/// # Arguments
+///
+/// * `user` - guard ensuring the user is logged in
+/// * `conn` - guard providing a connection to the database
+/// * `conf` - application configuration
+#[post("/setup")]
+pub async fn setup(user: User, conn: RbDbConn, conf: &State<RbConfig>)
+    -> RbResult<Json<TotpSetup>>
+{
+    let totp = conf.totp.clone();
+
+    Ok(Json(
+        conn.run(move |c| {
+            let user = db::users::find(c, user.0.id).ok_or(RbError::UMUnknownUser)?;
+
+            totp::setup(c, &totp, &user)
+        })
+        .await?,
+    ))
+}
+
+/// Enables 2FA using the first code generated by the user's authenticator, returning their
+/// recovery codes.
+///
+/// # Arguments
+///
+/// * `user` - guard ensuring the user is logged in
+/// * `conn` - guard providing a connection to the database
+/// * `code_request` - current TOTP code
+#[post("/confirm", data = "<code_request>")]
+pub async fn confirm(
+    user: User,
+    conn: RbDbConn,
+    code_request: Json<CodeRequest>,
+) -> RbResult<Json<RecoveryCodes>>
+{
+    let code = code_request.into_inner().code;
+
+    let recovery_codes = conn
+        .run(move |c| {
+            let user = db::users::find(c, user.0.id).ok_or(RbError::UMUnknownUser)?;
+
+            totp::confirm(c, &user, &code)
+        })
+        .await?;
+
+    Ok(Json(RecoveryCodes { recovery_codes }))
+}
+
+/// Disables 2FA for the logged-in user.
+///
+/// # Arguments
+///
+/// * `user` - guard ensuring the user is logged in
+/// * `conn` - guard providing a connection to the database
+/// * `conf` - application configuration
+/// * `client` - information about the client
+/// * `code_request` - TOTP or recovery code
+#[post("/disable", data = "<code_request>")]
+pub async fn disable(
+    user: User,
+    conn: RbDbConn,
+    conf: &State<RbConfig>,
+    client: ClientInfo,
+    code_request: Json<CodeRequest>,
+) -> RbResult<()>
+{
+    let code = code_request.into_inner().code;
+    let throttle = conf.throttle.clone();
+
+    conn.run(move |c| {
+        let user = db::users::find(c, user.0.id).ok_or(RbError::UMUnknownUser)?;
+
+        totp::disable(c, &throttle, &user, &code, &client)
+    })
+    .await
+}
+
+/// Exchanges a challenge token returned by the login route for a JWT & refresh token pair, given a
+/// valid TOTP or recovery code.
+///
+/// # Arguments
+///
+/// * `conn` - guard providing a connection to the database
+/// * `conf` - application configuration
+/// * `keys` - JWT keys
+/// * `client` - information about the client
+/// * `challenge_response` - challenge token & code
+#[post("/verify", data = "<challenge_response>")]
+pub async fn verify(
+    conn: RbDbConn,
+    conf: &State<RbConfig>,
+    keys: &State<JwtKeys>,
+    client: ClientInfo,
+    jar: &CookieJar<'_>,
+    challenge_response: Json<ChallengeResponse>,
+) -> RbResult<Json<TokenResponse>>
+{
+    let ChallengeResponse {
+        challenge_token,
+        code,
+        cookies,
+    } = challenge_response.into_inner();
+    let jwt = conf.jwt.clone();
+    let throttle = conf.throttle.clone();
+    let keys = keys.inner().clone();
+
+    let tokens = conn
+        .run(move |c| {
+            totp::complete_challenge(c, &jwt, &throttle, &keys, &challenge_token, &code, &client)
+        })
+        .await?;
+
+    Ok(Json(TokenResponse::new(tokens, jar, conf, cookies)))
+}
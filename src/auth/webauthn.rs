@@ -0,0 +1,424 @@
+//! Passwordless login using security keys & passkeys, through the Web Authentication API. Logged-in
+//! users can register any number of credentials, which can then be used to log in instead of a
+//! password. Authenticators have to verify the user themselves, e.g. using a PIN or fingerprint, so
+//! such a login already provides two factors & skips the TOTP challenge. The state of a ceremony in
+//! progress is stored in the database, so every challenge can only be answered once.
+
+use chrono::Utc;
+use rocket::{
+    http::CookieJar,
+    serde::json::{self, Json},
+    State,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use webauthn_rs::{
+    proto::{
+        CreationChallengeResponse, Credential, PublicKeyCredential, RegisterPublicKeyCredential,
+        RequestChallengeResponse, UserVerificationPolicy,
+    },
+    AuthenticationState, RegistrationState, Webauthn, WebauthnConfig,
+};
+
+use super::{
+    cookies::TokenResponse,
+    jwt::generate_jwt_token,
+    keys::JwtKeys,
+    permissions::{Permission, UsersWrite},
+};
+use crate::{
+    db,
+    errors::{RbError, RbResult},
+    guards::{ClientInfo, User},
+    RbConfig, RbDbConn, RbWebauthnConf,
+};
+
+/// Time users get to complete a ceremony, in seconds
+const CHALLENGE_EXPIRE: i64 = 300;
+/// Kind of a registration challenge
+const REGISTER: &str = "register";
+/// Kind of a login challenge
+const LOGIN: &str = "login";
+
+impl WebauthnConfig for RbWebauthnConf
+{
+    fn get_relying_party_name(&self) -> &str
+    {
+        &self.rp_name
+    }
+
+    fn get_origin(&self) -> &url::Url
+    {
+        &self.origin
+    }
+
+    fn get_relying_party_id(&self) -> &str
+    {
+        &self.rp_id
+    }
+}
+
+fn webauthn(conf: &RbConfig) -> RbResult<Webauthn<RbWebauthnConf>>
+{
+    conf.webauthn
+        .clone()
+        .map(Webauthn::new)
+        .ok_or(RbError::AuthWebauthnDisabled)
+}
+
+/// Stores the state of a new ceremony, returning the challenge's ID.
+fn store_challenge<S: Serialize>(
+    conn: &diesel::PgConnection,
+    user_id: Uuid,
+    kind: &str,
+    state: &S,
+) -> RbResult<Uuid>
+{
+    db::webauthn_challenges::create(
+        conn,
+        &db::NewWebauthnChallenge {
+            user_id,
+            kind,
+            state: json::serde_json::to_string(state)
+                .map_err(|_| RbError::Custom("Couldn't serialize WebAuthn state."))?,
+            expires_at: (Utc::now() + chrono::Duration::seconds(CHALLENGE_EXPIRE)).naive_utc(),
+        },
+    )
+}
+
+/// Consumes a challenge, returning the user it was created for & the state of its ceremony.
+fn consume_challenge<S: serde::de::DeserializeOwned>(
+    conn: &diesel::PgConnection,
+    challenge_id: Uuid,
+    kind: &str,
+) -> RbResult<(Uuid, S)>
+{
+    let challenge = db::webauthn_challenges::consume(conn, challenge_id, kind)?
+        .ok_or(RbError::AuthInvalidWebauthnChallenge)?;
+
+    if challenge.expires_at < Utc::now().naive_utc() {
+        return Err(RbError::AuthInvalidWebauthnChallenge);
+    }
+
+    let state = json::from_str(&challenge.state)
+        .map_err(|_| RbError::Custom("Couldn't deserialize WebAuthn state."))?;
+
+    Ok((challenge.user_id, state))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegistrationChallenge
+{
+    challenge_id: Uuid,
+    /// Options to pass to `navigator.credentials.create()`
+    #[serde(flatten)]
+    options: CreationChallengeResponse,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegistrationResponse
+{
+    challenge_id: Uuid,
+    /// Name for the credential, e.g. "YubiKey"
+    name: String,
+    /// Credential returned by `navigator.credentials.create()`
+    credential: RegisterPublicKeyCredential,
+}
+
+/// Starts registering a new security key or passkey for the logged-in user.
+///
+/// # Arguments
+///
+/// * `user` - guard ensuring the user is logged in
+/// * `conn` - guard providing a connection to the database
+/// * `conf` - application configuration
+#[post("/register/begin")]
+pub async fn register_begin(
+    user: User,
+    conn: RbDbConn,
+    conf: &State<RbConfig>,
+) -> RbResult<Json<RegistrationChallenge>>
+{
+    let webauthn = webauthn(conf)?;
+
+    Ok(Json(
+        conn.run(move |c| -> RbResult<RegistrationChallenge> {
+            let user = db::users::find(c, user.0.id).ok_or(RbError::UMUnknownUser)?;
+
+            // Authenticators refuse to register a second credential for the same account
+            let exclude = db::webauthn_credentials::find_by_user(c, user.id)?
+                .into_iter()
+                .map(|credential| credential.credential_id)
+                .collect();
+
+            let (options, state) = webauthn
+                .generate_challenge_register_options(
+                    user.id.as_bytes().to_vec(),
+                    user.username.clone(),
+                    user.username.clone(),
+                    Some(exclude),
+                    Some(UserVerificationPolicy::Required),
+                    None,
+                )
+                .map_err(|_| RbError::Custom("Couldn't generate WebAuthn challenge."))?;
+
+            Ok(RegistrationChallenge {
+                challenge_id: store_challenge(c, user.id, REGISTER, &state)?,
+                options,
+            })
+        })
+        .await?,
+    ))
+}
+
+/// Completes the registration of a security key or passkey given the authenticator's response.
+///
+/// # Arguments
+///
+/// * `user` - guard ensuring the user is logged in
+/// * `conn` - guard providing a connection to the database
+/// * `conf` - application configuration
+/// * `response` - challenge ID, name & the new credential
+#[post("/register/finish", data = "<response>")]
+pub async fn register_finish(
+    user: User,
+    conn: RbDbConn,
+    conf: &State<RbConfig>,
+    response: Json<RegistrationResponse>,
+) -> RbResult<Json<db::WebauthnCredential>>
+{
+    let webauthn = webauthn(conf)?;
+    let mut response = response.into_inner();
+
+    response.name = response.name.trim().to_string();
+    db::webauthn_credentials::check_name(&response.name)?;
+
+    Ok(Json(
+        conn.run(move |c| {
+            let (user_id, state): (_, RegistrationState) =
+                consume_challenge(c, response.challenge_id, REGISTER)?;
+
+            // The challenge has to be completed by the user that started it
+            if user_id != user.0.id {
+                return Err(RbError::AuthInvalidWebauthnChallenge);
+            }
+
+            let (credential, _) = webauthn
+                .register_credential(&response.credential, &state, |raw_id| {
+                    db::webauthn_credentials::exists(c, raw_id).map_err(|_| ())
+                })
+                .map_err(|err| match err {
+                    webauthn_rs::error::WebauthnError::CredentialAlreadyExists => {
+                        RbError::AuthDuplicateWebauthnCredential
+                    },
+                    _ => RbError::AuthInvalidWebauthnResponse,
+                })?;
+
+            db::webauthn_credentials::create(
+                c,
+                &db::NewWebauthnCredential {
+                    user_id,
+                    name: response.name,
+                    credential_id: credential.cred_id.clone(),
+                    credential: json::serde_json::to_string(&credential)
+                        .map_err(|_| RbError::Custom("Couldn't serialize WebAuthn credential."))?,
+                    sign_count: credential.counter.into(),
+                },
+            )
+        })
+        .await?,
+    ))
+}
+
+/// Lists the logged-in user's security keys & passkeys.
+///
+/// # Arguments
+///
+/// * `user` - guard ensuring the user is logged in
+/// * `conn` - guard providing a connection to the database
+#[get("/credentials")]
+pub async fn list(user: User, conn: RbDbConn) -> RbResult<Json<Vec<db::WebauthnCredential>>>
+{
+    Ok(Json(
+        conn.run(move |c| db::webauthn_credentials::find_by_user(c, user.0.id))
+            .await?,
+    ))
+}
+
+/// Removes a security key or passkey. Users can only remove their own credentials, while admins
+/// can remove any credential.
+///
+/// # Arguments
+///
+/// * `user` - guard ensuring the user is logged in
+/// * `conn` - guard providing a connection to the database
+/// * `id` - ID of the credential to remove
+#[delete("/credentials/<id>")]
+pub async fn delete(user: User, conn: RbDbConn, id: Uuid) -> RbResult<()>
+{
+    let claims = user.0;
+
+    conn.run(move |c| {
+        let credential =
+            db::webauthn_credentials::find(c, id)?.ok_or(RbError::AuthUnknownWebauthnCredential)?;
+
+        if credential.user_id != claims.id && !claims.has_permission(UsersWrite::NAME) {
+            return Err(RbError::AuthUnknownWebauthnCredential);
+        }
+
+        db::webauthn_credentials::delete(c, credential.id)
+    })
+    .await
+}
+
+#[derive(Deserialize)]
+pub struct LoginRequest
+{
+    username: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoginChallenge
+{
+    challenge_id: Uuid,
+    /// Options to pass to `navigator.credentials.get()`
+    #[serde(flatten)]
+    options: RequestChallengeResponse,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoginResponse
+{
+    challenge_id: Uuid,
+    /// Assertion returned by `navigator.credentials.get()`
+    credential: PublicKeyCredential,
+    /// Optional name for the new session, e.g. "work laptop"
+    device_label: Option<String>,
+    /// Whether to set the tokens as cookies instead of returning them
+    #[serde(default)]
+    cookies: bool,
+}
+
+/// Starts a login using one of the given user's security keys or passkeys. Unknown users & users
+/// without any credentials get the same response.
+///
+/// # Arguments
+///
+/// * `conn` - guard providing a connection to the database
+/// * `conf` - application configuration
+/// * `request` - username of the user logging in
+#[post("/login/begin", data = "<request>")]
+pub async fn login_begin(
+    conn: RbDbConn,
+    conf: &State<RbConfig>,
+    request: Json<LoginRequest>,
+) -> RbResult<Json<LoginChallenge>>
+{
+    let webauthn = webauthn(conf)?;
+    let username = request.into_inner().username;
+
+    Ok(Json(
+        conn.run(move |c| {
+            let user = db::users::find_by_username(c, &username)?
+                .ok_or(RbError::AuthInvalidCredentials)?;
+
+            let credentials = db::webauthn_credentials::find_by_user(c, user.id)?
+                .into_iter()
+                .map(|stored| {
+                    let mut credential: Credential =
+                        json::from_str(&stored.credential).map_err(|_| {
+                            RbError::Custom("Couldn't deserialize WebAuthn credential.")
+                        })?;
+                    // The serialized counter is the one from when the credential was registered
+                    credential.counter = stored.sign_count as u32;
+
+                    Ok(credential)
+                })
+                .collect::<RbResult<Vec<_>>>()?;
+
+            if credentials.is_empty() {
+                return Err(RbError::AuthInvalidCredentials);
+            }
+
+            let (options, state) = webauthn
+                .generate_challenge_authenticate(credentials)
+                .map_err(|_| RbError::Custom("Couldn't generate WebAuthn challenge."))?;
+
+            Ok(LoginChallenge {
+                challenge_id: store_challenge(c, user.id, LOGIN, &state)?,
+                options,
+            })
+        })
+        .await?,
+    ))
+}
+
+/// Completes a login given the authenticator's assertion, returning a new JWT & refresh token pair.
+/// Assertions whose signature counter didn't increase are rejected, as the authenticator might
+/// have been cloned.
+///
+/// # Arguments
+///
+/// * `conn` - guard providing a connection to the database
+/// * `conf` - application configuration
+/// * `keys` - JWT keys
+/// * `client` - information about the client
+/// * `jar` - the request's cookies
+/// * `response` - challenge ID & assertion
+#[post("/login/finish", data = "<response>")]
+pub async fn login_finish(
+    conn: RbDbConn,
+    conf: &State<RbConfig>,
+    keys: &State<JwtKeys>,
+    client: ClientInfo,
+    jar: &CookieJar<'_>,
+    response: Json<LoginResponse>,
+) -> RbResult<Json<TokenResponse>>
+{
+    let webauthn = webauthn(conf)?;
+    let LoginResponse {
+        challenge_id,
+        credential,
+        device_label,
+        cookies,
+    } = response.into_inner();
+    db::tokens::check_device_label(device_label.as_deref())?;
+
+    let jwt = conf.jwt.clone();
+    let keys = keys.inner().clone();
+
+    let tokens = conn
+        .run(move |c| {
+            let (user_id, state): (_, AuthenticationState) =
+                consume_challenge(c, challenge_id, LOGIN)?;
+
+            let (raw_id, auth_data) = webauthn
+                .authenticate_credential(&credential, &state)
+                .map_err(|_| RbError::AuthInvalidCredentials)?;
+
+            // The credential might also have been removed since the login was started
+            if !db::webauthn_credentials::update_sign_count(c, raw_id, auth_data.counter.into())? {
+                warn!(
+                    "Rejected WebAuthn login for user {} with an outdated signature counter",
+                    user_id
+                );
+
+                return Err(RbError::AuthInvalidCredentials);
+            }
+
+            let user = db::users::find(c, user_id).ok_or(RbError::AuthInvalidCredentials)?;
+
+            if user.blocked {
+                return Err(RbError::AuthBlockedUser(user.blocked_reason.clone()));
+            }
+
+            generate_jwt_token(c, &jwt, &keys, &user, &client, device_label)
+        })
+        .await?;
+
+    Ok(Json(TokenResponse::new(tokens, jar, conf, cookies)))
+}
@@ -0,0 +1,426 @@
+//! Administrative subcommands of the `rbd` binary, allowing an instance to be managed without going
+//! through the API, e.g. over SSH. They use the same configuration as the server, & talk to the
+//! database directly. Changes are recorded in the audit log without an actor ID.
+
+use std::{io::BufRead, path::PathBuf};
+
+use diesel::{Connection, PgConnection};
+use figment::Figment;
+use structopt::StructOpt;
+
+use crate::{
+    admin,
+    auth::pass,
+    db::{
+        self,
+        audit_events::{Actor, Target},
+    },
+    errors::{RbError, RbResult},
+    RbConfig,
+};
+
+#[derive(StructOpt)]
+#[structopt(name = "rbd", about = "Rusty Bever server & administration tool")]
+pub struct Opts
+{
+    /// Runs the server if not provided
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(StructOpt)]
+pub enum Command
+{
+    /// Run the server
+    Serve,
+    /// Manage database migrations; shows their status if no action is given
+    Migrate
+    {
+        #[structopt(subcommand)]
+        action: Option<MigrateCommand>,
+    },
+    /// Manage users
+    User(UserCommand),
+    /// Manage sections
+    Section(SectionCommand),
+    /// Manage refresh tokens
+    Token(TokenCommand),
+}
+
+#[derive(StructOpt)]
+pub enum MigrateCommand
+{
+    /// Show which migrations have been applied
+    Status
+    {
+        /// Directory containing the migrations
+        #[structopt(long, default_value = "migrations")]
+        dir: PathBuf,
+    },
+    /// Apply all pending migrations
+    Run,
+    /// Revert the latest applied migration
+    Revert
+    {
+        /// Directory containing the migrations
+        #[structopt(long, default_value = "migrations")]
+        dir: PathBuf,
+    },
+}
+
+#[derive(StructOpt)]
+pub enum UserCommand
+{
+    /// Create a new user; the password is read from standard input
+    Create
+    {
+        username: String,
+        /// Role of the new user; defaults to the database's default role
+        #[structopt(long)]
+        role: Option<String>,
+        /// Address to send password reset emails to
+        #[structopt(long)]
+        email: Option<String>,
+    },
+    /// List users, ordered by username
+    List
+    {
+        /// Only list users whose username contains this, ignoring case
+        #[structopt(long)]
+        search: Option<String>,
+        #[structopt(long, default_value = "0")]
+        offset: u32,
+        #[structopt(long, default_value = "50")]
+        limit: u32,
+    },
+    /// Block a user, revoking all of their tokens
+    Block
+    {
+        /// ID or username of the user
+        user: String,
+        /// Reason shown to the user when they try to log in
+        #[structopt(long)]
+        reason: String,
+    },
+    /// Unblock a user
+    Unblock
+    {
+        /// ID or username of the user
+        user: String,
+    },
+    /// Replace a user's password, revoking all of their tokens; the password is read from standard
+    /// input
+    SetPassword
+    {
+        /// ID or username of the user
+        user: String,
+        /// Require the user to change the password before logging in
+        #[structopt(long)]
+        must_change: bool,
+    },
+    /// Change a user's role
+    Promote
+    {
+        /// ID or username of the user
+        user: String,
+        /// The new role
+        #[structopt(long, default_value = db::roles::ADMIN)]
+        role: String,
+    },
+}
+
+#[derive(StructOpt)]
+pub enum SectionCommand
+{
+    /// Create a new section
+    Create
+    {
+        title: String,
+        shortname: String,
+        #[structopt(long)]
+        description: Option<String>,
+        /// Show the section's posts in the default feed
+        #[structopt(long)]
+        default: bool,
+        /// Don't give the section's posts titles
+        #[structopt(long)]
+        no_titles: bool,
+    },
+    /// List sections
+    List
+    {
+        #[structopt(long, default_value = "0")]
+        offset: u32,
+        #[structopt(long, default_value = "50")]
+        limit: u32,
+    },
+}
+
+#[derive(StructOpt)]
+pub enum TokenCommand
+{
+    /// Remove expired refresh tokens
+    Prune,
+}
+
+/// Reads a password from the first line of standard input, so it doesn't end up in the shell's
+/// history.
+fn read_password() -> RbResult<String>
+{
+    eprintln!("Password:");
+
+    let mut line = String::new();
+    std::io::stdin()
+        .lock()
+        .read_line(&mut line)
+        .map_err(|_| RbError::Custom("Couldn't read password."))?;
+    let password = line.trim_end_matches(&['\r', '\n'][..]).to_string();
+
+    pass::check_password(&password)?;
+
+    Ok(password)
+}
+
+/// Commands aren't run by a user, & don't come in over the network.
+fn actor() -> Actor
+{
+    Actor {
+        id: None,
+        ip_address: None,
+        user_agent: None,
+    }
+}
+
+/// Runs an administrative command.
+///
+/// # Arguments
+///
+/// * `figment` - the configuration, as used by the server
+/// * `command` - the command to run; `Command::Serve` is handled by the caller
+pub fn run(figment: &Figment, command: Command) -> Result<(), String>
+{
+    let config: RbConfig = figment
+        .extract()
+        .map_err(|err| format!("Invalid configuration: {}", err))?;
+    let url: String = figment
+        .extract_inner("databases.postgres_rb.url")
+        .map_err(|err| format!("Invalid configuration: {}", err))?;
+
+    let conn = PgConnection::establish(&url)
+        .map_err(|err| format!("Couldn't connect to the database: {}", err))?;
+
+    let res = match command {
+        Command::Serve => Ok(()),
+        Command::Migrate { action } => return migrate(&conn, action),
+        Command::User(command) => user(&conn, &config, command),
+        Command::Section(command) => section(&conn, command),
+        Command::Token(TokenCommand::Prune) => db::tokens::delete_expired(&conn)
+            .map(|removed| println!("Removed {} expired refresh tokens", removed)),
+    };
+
+    res.map_err(|err| err.message().to_string())
+}
+
+fn migrate(conn: &PgConnection, action: Option<MigrateCommand>) -> Result<(), String>
+{
+    let action = action.unwrap_or(MigrateCommand::Status {
+        dir: PathBuf::from("migrations"),
+    });
+
+    // The embedded migrations can only be run, so the others need the migrations on disk
+    if let MigrateCommand::Status { dir } | MigrateCommand::Revert { dir } = &action {
+        if !dir.is_dir() {
+            return Err(format!(
+                "Migrations directory {} doesn't exist.",
+                dir.display()
+            ));
+        }
+    }
+
+    match action {
+        MigrateCommand::Status { dir } => {
+            let migrations = diesel_migrations::mark_migrations_in_directory(conn, &dir)
+                .map_err(|err| format!("Couldn't read migrations: {}", err))?;
+
+            let mut migrations: Vec<_> = migrations
+                .iter()
+                .map(|(migration, applied)| (migration.version().to_string(), *applied))
+                .collect();
+            migrations.sort();
+
+            for (version, applied) in migrations {
+                println!("[{}] {}", if applied { "X" } else { " " }, version);
+            }
+
+            Ok(())
+        },
+        MigrateCommand::Run => {
+            crate::embedded_migrations::run_with_output(conn, &mut std::io::stdout())
+                .map_err(|err| format!("Couldn't run migrations: {}", err))
+        },
+        MigrateCommand::Revert { dir } => {
+            diesel_migrations::revert_latest_migration_in_directory(conn, &dir)
+                .map(|version| println!("Reverted migration {}", version))
+                .map_err(|err| format!("Couldn't revert migration: {}", err))
+        },
+    }
+}
+
+fn user(conn: &PgConnection, config: &RbConfig, command: UserCommand) -> RbResult<()>
+{
+    let actor = actor();
+
+    match command {
+        UserCommand::Create {
+            username,
+            role,
+            email,
+        } => {
+            db::users::check_username(&username)?;
+
+            if let Some(role) = &role {
+                admin::check_role_exists(conn, role)?;
+            }
+
+            let password = read_password()?;
+
+            let new_user = db::NewUser {
+                username,
+                password: pass::hash_password(&config.argon2, &password)?,
+                email: email.map(|email| email.trim().to_lowercase()),
+                role,
+            };
+
+            let user = conn.transaction::<_, RbError, _>(|| {
+                let user = db::users::create(conn, &new_user)?;
+                db::audit_events::record(
+                    conn,
+                    &actor,
+                    "user.create",
+                    Some(Target::User(user.id)),
+                    None,
+                )?;
+
+                Ok(user)
+            })?;
+
+            println!("Created user {} ({})", user.username, user.id);
+        },
+        UserCommand::List {
+            search,
+            offset,
+            limit,
+        } => {
+            for user in db::users::get(conn, search.as_deref(), offset, limit)? {
+                println!(
+                    "{}\t{}\t{}{}",
+                    user.id,
+                    user.username,
+                    user.role,
+                    if user.blocked { "\tblocked" } else { "" }
+                );
+            }
+        },
+        UserCommand::Block { user, reason } => {
+            let user = admin::resolve_user(conn, &user)?;
+            db::users::block(conn, user.id, &reason, &actor)?;
+
+            println!("Blocked user {}", user.username);
+        },
+        UserCommand::Unblock { user } => {
+            let user = admin::resolve_user(conn, &user)?;
+            conn.transaction::<_, RbError, _>(|| {
+                db::users::unblock(conn, user.id)?;
+                db::audit_events::record(
+                    conn,
+                    &actor,
+                    "user.unblock",
+                    Some(Target::User(user.id)),
+                    None,
+                )
+            })?;
+
+            println!("Unblocked user {}", user.username);
+        },
+        UserCommand::SetPassword { user, must_change } => {
+            let user = admin::resolve_user(conn, &user)?;
+
+            let password = read_password()?;
+
+            conn.transaction::<_, RbError, _>(|| {
+                pass::change_password(conn, &config.argon2, user.id, &password, must_change)?;
+                db::audit_events::record(
+                    conn,
+                    &actor,
+                    "user.password_reset",
+                    Some(Target::User(user.id)),
+                    None,
+                )
+            })?;
+
+            println!("Changed password of user {}", user.username);
+        },
+        UserCommand::Promote { user, role } => {
+            let user = admin::resolve_user(conn, &user)?;
+            admin::check_role_exists(conn, &role)?;
+
+            conn.transaction::<_, RbError, _>(|| {
+                db::users::set_role(conn, user.id, &role)?;
+                db::audit_events::record(
+                    conn,
+                    &actor,
+                    "user.role",
+                    Some(Target::User(user.id)),
+                    Some(role.clone()),
+                )
+            })?;
+
+            println!("Gave user {} the role {}", user.username, role);
+        },
+    }
+
+    Ok(())
+}
+
+fn section(conn: &PgConnection, command: SectionCommand) -> RbResult<()>
+{
+    match command {
+        SectionCommand::Create {
+            title,
+            shortname,
+            description,
+            default,
+            no_titles,
+        } => {
+            let new_section = db::NewSection {
+                title,
+                shortname,
+                description,
+                is_default: Some(default),
+                has_titles: Some(!no_titles),
+            };
+
+            let section = conn.transaction::<_, RbError, _>(|| {
+                let section = db::sections::create(conn, &new_section)?;
+                db::audit_events::record(
+                    conn,
+                    &actor(),
+                    "section.create",
+                    Some(Target::Section(section.id)),
+                    None,
+                )?;
+
+                Ok(section)
+            })?;
+
+            println!("Created section {} ({})", section.shortname, section.id);
+        },
+        SectionCommand::List { offset, limit } => {
+            for section in db::sections::get(conn, offset, limit)? {
+                println!("{}\t{}\t{}", section.id, section.shortname, section.title);
+            }
+        },
+    }
+
+    Ok(())
+}
@@ -0,0 +1,220 @@
+//! Handles audit log-related database operations. Events are only ever inserted & eventually
+//! removed once they're older than the retention period; the database rejects any updates.
+
+use diesel::{insert_into, prelude::*, Insertable, PgConnection, Queryable};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    errors::{RbError, RbResult},
+    schema::{audit_events, audit_events::dsl::*},
+};
+
+/// Maximum length of a target's identifier, as enforced by the database
+const MAX_TARGET_ID_LENGTH: usize = 255;
+
+/// An audit event as stored in the database
+#[derive(Queryable, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEvent
+{
+    pub id: Uuid,
+    pub actor_id: Option<Uuid>,
+    pub action: String,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    pub details: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+/// A new audit event to be added into the database
+#[derive(Insertable)]
+#[table_name = "audit_events"]
+pub struct NewAuditEvent
+{
+    pub actor_id: Option<Uuid>,
+    pub action: &'static str,
+    pub target_type: Option<&'static str>,
+    pub target_id: Option<String>,
+    pub details: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// Who performed an action & from where
+#[derive(Clone)]
+pub struct Actor
+{
+    /// `None` if the action wasn't performed by a logged-in user
+    pub id: Option<Uuid>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// The object an action was performed on
+pub enum Target
+{
+    User(Uuid),
+    /// A username that doesn't necessarily belong to a user, e.g. after a failed login
+    Username(String),
+    Post(Uuid),
+    Section(Uuid),
+    Invite(Uuid),
+    /// Kind & subject of a login throttle
+    Lockout(String, String),
+}
+
+impl Target
+{
+    fn into_parts(self) -> (&'static str, String)
+    {
+        match self {
+            Target::User(user_id) => ("user", user_id.to_string()),
+            // Failed logins can be made for arbitrarily long usernames
+            Target::Username(name) => (
+                "username",
+                name.chars().take(MAX_TARGET_ID_LENGTH).collect(),
+            ),
+            Target::Post(post_id) => ("post", post_id.to_string()),
+            Target::Section(section_id) => ("section", section_id.to_string()),
+            Target::Invite(invite_id) => ("invite", invite_id.to_string()),
+            Target::Lockout(kind, subject) => ("lockout", format!("{}:{}", kind, subject)),
+        }
+    }
+}
+
+impl Actor
+{
+    /// Describes an action performed by this actor.
+    ///
+    /// # Arguments
+    ///
+    /// * `action_` - what happened, e.g. "user.block"
+    /// * `target` - object the action was performed on
+    /// * `details_` - additional information
+    pub fn event(
+        &self,
+        action_: &'static str,
+        target: Option<Target>,
+        details_: Option<String>,
+    ) -> NewAuditEvent
+    {
+        let (type_, id_) = match target.map(Target::into_parts) {
+            Some((type_, id_)) => (Some(type_), Some(id_)),
+            None => (None, None),
+        };
+
+        NewAuditEvent {
+            actor_id: self.id,
+            action: action_,
+            target_type: type_,
+            target_id: id_,
+            details: details_,
+            ip_address: self.ip_address.clone(),
+            user_agent: self.user_agent.clone(),
+        }
+    }
+}
+
+/// Criteria events have to match to be returned; unset fields match any event.
+#[derive(Default)]
+pub struct AuditFilter
+{
+    pub actor_id: Option<Uuid>,
+    pub action: Option<String>,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    /// Only return events created at or after this time
+    pub since: Option<chrono::NaiveDateTime>,
+    /// Only return events created before this time
+    pub until: Option<chrono::NaiveDateTime>,
+}
+
+/// Records an action performed by the given actor.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `actor` - who performed the action
+/// * `action_` - what happened, e.g. "user.block"
+/// * `target` - object the action was performed on
+/// * `details_` - additional information
+pub fn record(
+    conn: &PgConnection,
+    actor: &Actor,
+    action_: &'static str,
+    target: Option<Target>,
+    details_: Option<String>,
+) -> RbResult<()>
+{
+    insert_into(audit_events)
+        .values(&actor.event(action_, target, details_))
+        .execute(conn)
+        .map_err(|_| RbError::DbError("Couldn't insert audit event."))?;
+
+    Ok(())
+}
+
+/// Returns the events matching the filter, newest first.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `filter` - criteria the events have to match
+/// * `offset_` - amount of events to skip
+/// * `limit_` - maximum amount of events to return
+pub fn find(
+    conn: &PgConnection,
+    filter: &AuditFilter,
+    offset_: u32,
+    limit_: u32,
+) -> RbResult<Vec<AuditEvent>>
+{
+    let mut query = audit_events.into_boxed();
+
+    if let Some(actor_id_) = filter.actor_id {
+        query = query.filter(actor_id.eq(actor_id_));
+    }
+
+    if let Some(action_) = &filter.action {
+        query = query.filter(action.eq(action_));
+    }
+
+    if let Some(target_type_) = &filter.target_type {
+        query = query.filter(target_type.eq(target_type_));
+    }
+
+    if let Some(target_id_) = &filter.target_id {
+        query = query.filter(target_id.eq(target_id_));
+    }
+
+    if let Some(since) = filter.since {
+        query = query.filter(created_at.ge(since));
+    }
+
+    if let Some(until) = filter.until {
+        query = query.filter(created_at.lt(until));
+    }
+
+    query
+        .order(created_at.desc())
+        .offset(offset_.into())
+        .limit(limit_.into())
+        .load(conn)
+        .map_err(|_| RbError::DbError("Couldn't query audit events."))
+}
+
+/// Removes all events created before the given time, returning how many were removed.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `cutoff` - events created before this time are removed
+pub fn delete_older_than(conn: &PgConnection, cutoff: chrono::NaiveDateTime) -> RbResult<usize>
+{
+    diesel::delete(audit_events.filter(created_at.lt(cutoff)))
+        .execute(conn)
+        .map_err(|_| RbError::DbError("Couldn't remove old audit events."))
+}
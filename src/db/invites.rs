@@ -0,0 +1,116 @@
+//! Handles invite-related database operations.
+
+use diesel::{
+    insert_into,
+    prelude::*,
+    result::{DatabaseErrorKind, Error::DatabaseError},
+    Insertable, PgConnection, Queryable,
+};
+use serde::Serialize;
+use uuid::Uuid;
+
+use super::users::NewUser;
+use crate::{
+    errors::{RbError, RbResult},
+    schema::{invites, invites::dsl::*, users},
+};
+
+/// An invite as stored in the database
+#[derive(Queryable, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Invite
+{
+    pub id: Uuid,
+    #[serde(skip_serializing)]
+    pub code_hash: Vec<u8>,
+    pub role: Option<String>,
+    pub max_uses: i32,
+    pub uses: i32,
+    pub created_by: Option<Uuid>,
+    pub created_at: chrono::NaiveDateTime,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+}
+
+/// A new invite to be added into the database
+#[derive(Insertable)]
+#[table_name = "invites"]
+pub struct NewInvite
+{
+    pub code_hash: Vec<u8>,
+    pub role: Option<String>,
+    /// Defaults to a single use if not provided
+    pub max_uses: Option<i32>,
+    pub created_by: Uuid,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+}
+
+pub fn create(conn: &PgConnection, new_invite: &NewInvite) -> RbResult<Invite>
+{
+    insert_into(invites)
+        .values(new_invite)
+        .get_result(conn)
+        .map_err(|_| RbError::DbError("Couldn't insert invite."))
+}
+
+/// Returns all invites, newest first.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+pub fn all(conn: &PgConnection) -> RbResult<Vec<Invite>>
+{
+    invites
+        .order(created_at.desc())
+        .load(conn)
+        .map_err(|_| RbError::DbError("Couldn't query invites."))
+}
+
+/// Removes an invite.
+///
+/// Returns whether the invite existed.
+pub fn delete(conn: &PgConnection, invite_id: Uuid) -> RbResult<bool>
+{
+    diesel::delete(invites.find(invite_id))
+        .execute(conn)
+        .map(|count| count > 0)
+        .map_err(|_| RbError::DbError("Couldn't delete invite."))
+}
+
+/// Uses up one of the invite's uses to create a new user, who gets the invite's role. Both happen
+/// in a single transaction, so a failed registration doesn't use up the invite, & an invite can't
+/// be used more often than allowed.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `hash` - SHA-256 hash of the invite code
+/// * `new_user` - user to create; its role is replaced by the invite's
+pub fn redeem(conn: &PgConnection, hash: &[u8], new_user: NewUser) -> RbResult<()>
+{
+    let now = chrono::Utc::now().naive_utc();
+
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        let invite: Invite = diesel::update(
+            invites
+                .filter(code_hash.eq(hash))
+                .filter(uses.lt(max_uses))
+                .filter(expires_at.is_null().or(expires_at.gt(now))),
+        )
+        .set(uses.eq(uses + 1))
+        .get_result(conn)?;
+
+        insert_into(users::table)
+            .values(&NewUser {
+                role: invite.role,
+                ..new_user
+            })
+            .execute(conn)?;
+
+        Ok(())
+    })
+    .map_err(|err| match err {
+        diesel::NotFound => RbError::AuthInvalidInvite,
+        DatabaseError(DatabaseErrorKind::UniqueViolation, _) => RbError::UMDuplicateUser,
+        _ => RbError::DbError("Couldn't redeem invite."),
+    })
+}
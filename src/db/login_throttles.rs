@@ -0,0 +1,145 @@
+//! Handles storing failed login attempts.
+
+use chrono::NaiveDateTime;
+use diesel::{insert_into, prelude::*, Insertable, PgConnection, Queryable};
+use serde::Serialize;
+
+use crate::{
+    errors::{RbError, RbResult},
+    schema::{login_throttles, login_throttles::dsl::*},
+};
+
+/// Failed login attempts for a single username or IP address
+#[derive(Queryable, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoginThrottle
+{
+    pub kind: String,
+    pub subject: String,
+    pub failures: i32,
+    pub last_failure_at: NaiveDateTime,
+    pub locked_until: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "login_throttles"]
+struct NewLoginThrottle<'a>
+{
+    kind: &'a str,
+    subject: &'a str,
+}
+
+/// Returns the throttles for the given subjects that are currently delaying login attempts.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `subjects` - pairs of kinds & subjects to check
+pub fn find_locked(conn: &PgConnection, subjects: &[(&str, &str)]) -> RbResult<Vec<LoginThrottle>>
+{
+    let now = chrono::Utc::now().naive_utc();
+    let mut throttles = Vec::new();
+
+    for (kind_, subject_) in subjects {
+        let mut found = login_throttles
+            .filter(kind.eq(kind_))
+            .filter(subject.eq(subject_))
+            .filter(locked_until.gt(now))
+            .load(conn)
+            .map_err(|_| RbError::DbError("Couldn't query login throttles."))?;
+
+        throttles.append(&mut found);
+    }
+
+    Ok(throttles)
+}
+
+/// Returns all throttles that are currently delaying login attempts, ending soonest first.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+pub fn all_locked(conn: &PgConnection) -> RbResult<Vec<LoginThrottle>>
+{
+    login_throttles
+        .filter(locked_until.gt(chrono::Utc::now().naive_utc()))
+        .order(locked_until)
+        .load(conn)
+        .map_err(|_| RbError::DbError("Couldn't query login throttles."))
+}
+
+/// Registers a failed login attempt, returning the updated throttle.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `kind_` - either "user" or "ip"
+/// * `subject_` - username or IP address the attempt was made for
+pub fn add_failure(conn: &PgConnection, kind_: &str, subject_: &str) -> RbResult<LoginThrottle>
+{
+    insert_into(login_throttles)
+        .values(&NewLoginThrottle {
+            kind: kind_,
+            subject: subject_,
+        })
+        .on_conflict((kind, subject))
+        .do_update()
+        .set((
+            failures.eq(failures + 1),
+            last_failure_at.eq(chrono::Utc::now().naive_utc()),
+        ))
+        .get_result(conn)
+        .map_err(|_| RbError::DbError("Couldn't save failed login attempt."))
+}
+
+/// Sets the time before which login attempts are rejected.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `kind_` - either "user" or "ip"
+/// * `subject_` - username or IP address to delay attempts for
+/// * `until` - end of the delay
+pub fn lock(conn: &PgConnection, kind_: &str, subject_: &str, until: NaiveDateTime)
+    -> RbResult<()>
+{
+    diesel::update(login_throttles.find((kind_, subject_)))
+        .set(locked_until.eq(until))
+        .execute(conn)
+        .map_err(|_| RbError::DbError("Couldn't lock login attempts."))?;
+
+    Ok(())
+}
+
+/// Removes a throttle, clearing its failed attempts.
+///
+/// Returns whether the throttle existed.
+pub fn delete(conn: &PgConnection, kind_: &str, subject_: &str) -> RbResult<bool>
+{
+    diesel::delete(login_throttles.find((kind_, subject_)))
+        .execute(conn)
+        .map(|count| count > 0)
+        .map_err(|_| RbError::DbError("Couldn't delete login throttle."))
+}
+
+/// Removes all throttles whose last failure happened before the given time, unless they're still
+/// delaying login attempts.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `before` - throttles with failures before this time are removed
+pub fn delete_stale(conn: &PgConnection, before: NaiveDateTime) -> RbResult<()>
+{
+    let now = chrono::Utc::now().naive_utc();
+
+    diesel::delete(
+        login_throttles
+            .filter(last_failure_at.lt(before))
+            .filter(locked_until.is_null().or(locked_until.le(now))),
+    )
+    .execute(conn)
+    .map_err(|_| RbError::DbError("Couldn't delete stale login throttles."))?;
+
+    Ok(())
+}
@@ -1,12 +1,36 @@
 //! The db module contains all Diesel-related logic. This is to prevent the various Diesel imports
 //! from poluting other modules' namespaces.
 
+pub mod audit_events;
+pub mod invites;
+pub mod login_throttles;
+pub mod oidc_identities;
+pub mod password_resets;
+pub mod personal_tokens;
 pub mod posts;
+pub mod recovery_codes;
+pub mod roles;
+pub mod section_grants;
 pub mod sections;
+pub mod security_events;
 pub mod tokens;
+pub mod user_profiles;
 pub mod users;
+pub mod webauthn_challenges;
+pub mod webauthn_credentials;
 
+pub use audit_events::{AuditEvent, NewAuditEvent};
+pub use invites::{Invite, NewInvite};
+pub use login_throttles::LoginThrottle;
+pub use password_resets::{NewPasswordResetToken, PasswordResetToken};
+pub use personal_tokens::{NewPersonalToken, PersonalToken};
 pub use posts::{NewPost, PatchPost, Post};
+pub use roles::Role;
+pub use section_grants::{NewSectionGrant, SectionGrant};
 pub use sections::{NewSection, Section};
+pub use security_events::{NewSecurityEvent, SecurityEvent};
 pub use tokens::{NewRefreshToken, RefreshToken};
-pub use users::{NewUser, User};
+pub use user_profiles::{NewUserProfile, UserProfile};
+pub use users::{NewUser, PatchUser, User};
+pub use webauthn_challenges::{NewWebauthnChallenge, WebauthnChallenge};
+pub use webauthn_credentials::{NewWebauthnCredential, WebauthnCredential};
@@ -0,0 +1,108 @@
+//! Handles OpenID Connect identity-related database operations.
+
+use diesel::{
+    insert_into,
+    prelude::*,
+    result::{DatabaseErrorKind, Error::DatabaseError},
+    Insertable, PgConnection,
+};
+
+use super::users::{NewUser, User};
+use crate::{
+    errors::{RbError, RbOption, RbResult},
+    schema::{oidc_identities, oidc_identities::dsl::*, users},
+};
+
+#[derive(Insertable)]
+#[table_name = "oidc_identities"]
+struct NewOidcIdentity<'a>
+{
+    issuer: &'a str,
+    subject: &'a str,
+    user_id: uuid::Uuid,
+}
+
+/// Returns the user the given identity is linked to, along with whether they were made an admin
+/// because of their groups at the provider.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `issuer_` - issuer URL of the provider
+/// * `subject_` - identifier of the user at the provider
+pub fn find_user(conn: &PgConnection, issuer_: &str, subject_: &str) -> RbOption<(User, bool)>
+{
+    match oidc_identities
+        .inner_join(users::table)
+        .filter(issuer.eq(issuer_))
+        .filter(subject.eq(subject_))
+        .select((users::all_columns, role_from_groups))
+        .first(conn)
+    {
+        Ok(val) => Ok(Some(val)),
+        Err(diesel::NotFound) => Ok(None),
+        _ => Err(RbError::DbError("Couldn't find user by OIDC identity.")),
+    }
+}
+
+/// Creates a new user & links the given identity to it, in a single transaction.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `issuer_` - issuer URL of the provider
+/// * `subject_` - identifier of the user at the provider
+/// * `new_user` - user to create
+pub fn provision(
+    conn: &PgConnection,
+    issuer_: &str,
+    subject_: &str,
+    new_user: &NewUser,
+) -> RbResult<User>
+{
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        let user: User = insert_into(users::table)
+            .values(new_user)
+            .get_result(conn)?;
+
+        insert_into(oidc_identities)
+            .values(&NewOidcIdentity {
+                issuer: issuer_,
+                subject: subject_,
+                user_id: user.id,
+            })
+            .execute(conn)?;
+
+        Ok(user)
+    })
+    .map_err(|err| match err {
+        DatabaseError(DatabaseErrorKind::UniqueViolation, _) => RbError::UMDuplicateUser,
+        _ => RbError::DbError("Couldn't provision user."),
+    })
+}
+
+/// Records whether the identity's user was made an admin because of their groups at the provider.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `issuer_` - issuer URL of the provider
+/// * `subject_` - identifier of the user at the provider
+/// * `from_groups` - whether the role came from the user's groups
+pub fn set_role_from_groups(
+    conn: &PgConnection,
+    issuer_: &str,
+    subject_: &str,
+    from_groups: bool,
+) -> RbResult<()>
+{
+    diesel::update(
+        oidc_identities
+            .filter(issuer.eq(issuer_))
+            .filter(subject.eq(subject_)),
+    )
+    .set(role_from_groups.eq(from_groups))
+    .execute(conn)
+    .map(|_| ())
+    .map_err(|_| RbError::DbError("Couldn't update OIDC identity."))
+}
@@ -0,0 +1,81 @@
+//! Handles password reset token-related database operations.
+
+use diesel::{insert_into, prelude::*, Insertable, PgConnection, Queryable};
+use uuid::Uuid;
+
+use crate::{
+    errors::{RbError, RbOption, RbResult},
+    schema::{password_reset_tokens, password_reset_tokens::dsl::*},
+};
+
+#[derive(Queryable)]
+pub struct PasswordResetToken
+{
+    pub token_hash: Vec<u8>,
+    pub user_id: Uuid,
+    pub created_at: chrono::NaiveDateTime,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "password_reset_tokens"]
+pub struct NewPasswordResetToken
+{
+    pub token_hash: Vec<u8>,
+    pub user_id: Uuid,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+/// Stores a new reset token, replacing any tokens the user already had.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `new_token` - token to insert
+pub fn replace(conn: &PgConnection, new_token: &NewPasswordResetToken) -> RbResult<()>
+{
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        diesel::delete(password_reset_tokens.filter(user_id.eq(new_token.user_id)))
+            .execute(conn)?;
+        insert_into(password_reset_tokens)
+            .values(new_token)
+            .execute(conn)?;
+
+        Ok(())
+    })
+    .map_err(|_| RbError::DbError("Couldn't store password reset token."))
+}
+
+/// Returns the user's current reset token, if they have one.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `user_id_` - ID of the user
+pub fn find_by_user(conn: &PgConnection, user_id_: Uuid) -> RbOption<PasswordResetToken>
+{
+    match password_reset_tokens
+        .filter(user_id.eq(user_id_))
+        .first(conn)
+    {
+        Ok(val) => Ok(Some(val)),
+        Err(diesel::NotFound) => Ok(None),
+        _ => Err(RbError::DbError("Couldn't find password reset token.")),
+    }
+}
+
+/// Removes the token with the given hash, returning it if it existed. As this happens in a single
+/// query, a token can only be consumed once.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `hash` - SHA-256 hash of the token
+pub fn consume(conn: &PgConnection, hash: &[u8]) -> RbOption<PasswordResetToken>
+{
+    match diesel::delete(password_reset_tokens.find(hash)).get_result(conn) {
+        Ok(val) => Ok(Some(val)),
+        Err(diesel::NotFound) => Ok(None),
+        _ => Err(RbError::DbError("Couldn't consume password reset token.")),
+    }
+}
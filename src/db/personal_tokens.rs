@@ -0,0 +1,139 @@
+//! Handles personal access token-related database operations.
+
+use diesel::{insert_into, prelude::*, Insertable, PgConnection, Queryable};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    errors::{RbError, RbOption, RbResult},
+    schema::{personal_access_tokens, personal_access_tokens::dsl::*},
+};
+
+/// Maximum length of a token's name, as enforced by the database
+pub const MAX_NAME_LENGTH: usize = 255;
+
+/// A personal access token as stored in the database
+#[derive(Queryable, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PersonalToken
+{
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub token_hash: Vec<u8>,
+    pub scopes: Vec<String>,
+    pub created_at: chrono::NaiveDateTime,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    pub last_used_at: Option<chrono::NaiveDateTime>,
+}
+
+/// A new personal access token to be added into the database
+#[derive(Insertable)]
+#[table_name = "personal_access_tokens"]
+pub struct NewPersonalToken
+{
+    pub user_id: Uuid,
+    pub name: String,
+    pub token_hash: Vec<u8>,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+}
+
+/// Checks whether the given token name is valid, so it can be rejected before the database does so
+/// with a generic error.
+///
+/// # Arguments
+///
+/// * `name_` - name to check
+pub fn check_name(name_: &str) -> RbResult<()>
+{
+    let length = name_.chars().count();
+
+    if length == 0 || length > MAX_NAME_LENGTH || name_.chars().any(|c| c.is_control()) {
+        return Err(RbError::AuthInvalidTokenName);
+    }
+
+    Ok(())
+}
+
+pub fn create(conn: &PgConnection, new_token: &NewPersonalToken) -> RbResult<PersonalToken>
+{
+    insert_into(personal_access_tokens)
+        .values(new_token)
+        .get_result(conn)
+        .map_err(|_| RbError::DbError("Couldn't insert personal access token."))
+}
+
+/// Returns all of a user's personal access tokens, newest first.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `user_id_` - ID of the user whose tokens should be returned
+pub fn find_by_user(conn: &PgConnection, user_id_: Uuid) -> RbResult<Vec<PersonalToken>>
+{
+    personal_access_tokens
+        .filter(user_id.eq(user_id_))
+        .order(created_at.desc())
+        .load(conn)
+        .map_err(|_| RbError::DbError("Couldn't query personal access tokens."))
+}
+
+pub fn find(conn: &PgConnection, token_id: Uuid) -> RbOption<PersonalToken>
+{
+    match personal_access_tokens.find(token_id).first(conn) {
+        Ok(val) => Ok(Some(val)),
+        Err(diesel::NotFound) => Ok(None),
+        _ => Err(RbError::DbError("Couldn't find personal access token.")),
+    }
+}
+
+/// Returns the token with the given hash, along with the user it belongs to.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `hash` - SHA-256 hash of the token
+pub fn find_with_user(
+    conn: &PgConnection,
+    hash: &[u8],
+) -> RbOption<(PersonalToken, super::users::User)>
+{
+    match personal_access_tokens
+        .inner_join(crate::schema::users::dsl::users)
+        .filter(token_hash.eq(hash))
+        .first(conn)
+    {
+        Ok(val) => Ok(Some(val)),
+        Err(diesel::NotFound) => Ok(None),
+        _ => Err(RbError::DbError(
+            "Couldn't get personal access token & user.",
+        )),
+    }
+}
+
+/// Updates a token's `last_used_at` column value to the current time.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `token_id` - ID of the token that was used
+pub fn update_last_used_at(conn: &PgConnection, token_id: Uuid) -> RbResult<()>
+{
+    diesel::update(personal_access_tokens.find(token_id))
+        .set(last_used_at.eq(chrono::Utc::now().naive_utc()))
+        .execute(conn)
+        .map_err(|_| RbError::DbError("Couldn't update last_used_at."))?;
+
+    Ok(())
+}
+
+pub fn delete(conn: &PgConnection, token_id: Uuid) -> RbResult<()>
+{
+    diesel::delete(personal_access_tokens.find(token_id))
+        .execute(conn)
+        .map_err(|_| RbError::DbError("Couldn't delete personal access token."))?;
+
+    Ok(())
+}
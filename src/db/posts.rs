@@ -16,6 +16,7 @@ pub struct Post
     pub title: Option<String>,
     pub publish_date: NaiveDate,
     pub content: String,
+    pub author_id: Option<Uuid>,
 }
 
 #[derive(Deserialize, Insertable)]
@@ -27,6 +28,9 @@ pub struct NewPost
     pub title: Option<String>,
     pub publish_date: NaiveDate,
     pub content: String,
+    /// Set to the user creating the post
+    #[serde(skip)]
+    pub author_id: Option<Uuid>,
 }
 
 #[derive(Deserialize, AsChangeset)]
@@ -0,0 +1,82 @@
+//! Handles the single-use recovery codes that can replace a TOTP code.
+
+use diesel::{insert_into, prelude::*, Insertable, PgConnection};
+use uuid::Uuid;
+
+use crate::{
+    errors::{RbError, RbResult},
+    schema::{recovery_codes, recovery_codes::dsl::*},
+};
+
+#[derive(Insertable)]
+#[table_name = "recovery_codes"]
+struct NewRecoveryCode<'a>
+{
+    user_id: Uuid,
+    code_hash: &'a [u8],
+}
+
+/// Replaces all of a user's recovery codes with a new set.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `user_id_` - ID of the user to generate codes for
+/// * `hashes` - hashes of the new codes
+pub fn replace(conn: &PgConnection, user_id_: Uuid, hashes: &[Vec<u8>]) -> RbResult<()>
+{
+    let new_codes: Vec<_> = hashes
+        .iter()
+        .map(|hash| NewRecoveryCode {
+            user_id: user_id_,
+            code_hash: hash,
+        })
+        .collect();
+
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        diesel::delete(recovery_codes.filter(user_id.eq(user_id_))).execute(conn)?;
+        insert_into(recovery_codes)
+            .values(&new_codes)
+            .execute(conn)?;
+
+        Ok(())
+    })
+    .map_err(|_| RbError::DbError("Couldn't replace recovery codes."))
+}
+
+/// Uses up a recovery code, if it exists.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `user_id_` - ID of the user the code belongs to
+/// * `hash` - hash of the code
+///
+/// Returns whether the code was valid.
+pub fn consume(conn: &PgConnection, user_id_: Uuid, hash: &[u8]) -> RbResult<bool>
+{
+    let count = diesel::delete(
+        recovery_codes
+            .filter(user_id.eq(user_id_))
+            .filter(code_hash.eq(hash)),
+    )
+    .execute(conn)
+    .map_err(|_| RbError::DbError("Couldn't use recovery code."))?;
+
+    Ok(count == 1)
+}
+
+/// Removes all of a user's recovery codes.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `user_id_` - ID of the user whose codes should be removed
+pub fn delete_by_user(conn: &PgConnection, user_id_: Uuid) -> RbResult<()>
+{
+    diesel::delete(recovery_codes.filter(user_id.eq(user_id_)))
+        .execute(conn)
+        .map_err(|_| RbError::DbError("Couldn't delete recovery codes."))?;
+
+    Ok(())
+}
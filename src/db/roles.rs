@@ -0,0 +1,60 @@
+//! Handles role & permission-related database operations.
+
+use diesel::{prelude::*, PgConnection, Queryable};
+use serde::Serialize;
+
+use crate::{
+    errors::{RbError, RbOption, RbResult},
+    schema::{role_permissions, roles},
+};
+
+/// Role granting every permission, as created by the migrations
+pub const ADMIN: &str = "admin";
+/// Role new users get if none is given, as created by the migrations
+pub const READER: &str = "reader";
+
+/// A role that can be assigned to users
+#[derive(Queryable, Serialize)]
+pub struct Role
+{
+    pub name: String,
+    pub description: String,
+}
+
+/// Returns all existing roles.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+pub fn all(conn: &PgConnection) -> RbResult<Vec<Role>>
+{
+    roles::table
+        .order(roles::name)
+        .load(conn)
+        .map_err(|_| RbError::DbError("Couldn't query roles."))
+}
+
+pub fn find(conn: &PgConnection, role_name: &str) -> RbOption<Role>
+{
+    match roles::table.find(role_name).first(conn) {
+        Ok(val) => Ok(Some(val)),
+        Err(diesel::NotFound) => Ok(None),
+        _ => Err(RbError::DbError("Couldn't find role.")),
+    }
+}
+
+/// Returns the names of all permissions granted to the given role.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `role_name` - name of the role
+pub fn permissions(conn: &PgConnection, role_name: &str) -> RbResult<Vec<String>>
+{
+    role_permissions::table
+        .filter(role_permissions::role.eq(role_name))
+        .select(role_permissions::permission)
+        .order(role_permissions::permission)
+        .load(conn)
+        .map_err(|_| RbError::DbError("Couldn't query role permissions."))
+}
@@ -0,0 +1,80 @@
+//! Handles per-section access grants.
+
+use diesel::{insert_into, prelude::*, Insertable, PgConnection, Queryable};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    errors::{RbError, RbOption, RbResult},
+    schema::{section_grants, section_grants::dsl::*},
+};
+
+/// Access to a single section granted to a user
+#[derive(Queryable, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SectionGrant
+{
+    pub user_id: Uuid,
+    pub section_id: Uuid,
+    pub level: String,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "section_grants"]
+pub struct NewSectionGrant
+{
+    pub user_id: Uuid,
+    pub section_id: Uuid,
+    pub level: String,
+}
+
+pub fn find(conn: &PgConnection, user_id_: Uuid, section_id_: Uuid) -> RbOption<SectionGrant>
+{
+    match section_grants.find((user_id_, section_id_)).first(conn) {
+        Ok(val) => Ok(Some(val)),
+        Err(diesel::NotFound) => Ok(None),
+        _ => Err(RbError::DbError("Couldn't find section grant.")),
+    }
+}
+
+/// Returns all grants for the given section.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `section_id_` - ID of the section
+pub fn find_by_section(conn: &PgConnection, section_id_: Uuid) -> RbResult<Vec<SectionGrant>>
+{
+    section_grants
+        .filter(section_id.eq(section_id_))
+        .order(created_at)
+        .load(conn)
+        .map_err(|_| RbError::DbError("Couldn't query section grants."))
+}
+
+/// Grants a user access to a section, replacing the level of any existing grant.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `new_grant` - grant to insert or update
+pub fn upsert(conn: &PgConnection, new_grant: &NewSectionGrant) -> RbResult<SectionGrant>
+{
+    insert_into(section_grants)
+        .values(new_grant)
+        .on_conflict((user_id, section_id))
+        .do_update()
+        .set(level.eq(&new_grant.level))
+        .get_result(conn)
+        .map_err(|_| RbError::DbError("Couldn't save section grant."))
+}
+
+pub fn delete(conn: &PgConnection, user_id_: Uuid, section_id_: Uuid) -> RbResult<()>
+{
+    diesel::delete(section_grants.find((user_id_, section_id_)))
+        .execute(conn)
+        .map_err(|_| RbError::DbError("Couldn't delete section grant."))?;
+
+    Ok(())
+}
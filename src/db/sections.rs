@@ -3,7 +3,7 @@ use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::{
-    errors::{RbError, RbResult},
+    errors::{RbError, RbOption, RbResult},
     schema::{sections, sections::dsl::*},
 };
 
@@ -23,11 +23,11 @@ pub struct Section
 #[serde(rename_all = "camelCase")]
 pub struct NewSection
 {
-    title: String,
+    pub title: String,
     pub shortname: String,
-    description: Option<String>,
-    is_default: Option<bool>,
-    has_titles: Option<bool>,
+    pub description: Option<String>,
+    pub is_default: Option<bool>,
+    pub has_titles: Option<bool>,
 }
 
 #[derive(Deserialize, AsChangeset)]
@@ -51,6 +51,15 @@ pub fn get(conn: &PgConnection, offset_: u32, limit_: u32) -> RbResult<Vec<Secti
         .map_err(|_| RbError::DbError("Couldn't query sections."))?)
 }
 
+pub fn find(conn: &PgConnection, section_id: Uuid) -> RbOption<Section>
+{
+    match sections.find(section_id).first(conn) {
+        Ok(val) => Ok(Some(val)),
+        Err(diesel::NotFound) => Ok(None),
+        _ => Err(RbError::DbError("Couldn't find section.")),
+    }
+}
+
 pub fn create(conn: &PgConnection, new_post: &NewSection) -> RbResult<Section>
 {
     Ok(insert_into(sections)
@@ -0,0 +1,53 @@
+//! Handles storing security-relevant events, such as the reuse of a refresh token.
+
+use diesel::{insert_into, prelude::*, Insertable, PgConnection, Queryable};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    errors::{RbError, RbResult},
+    schema::{security_events, security_events::dsl::*},
+};
+
+/// A security event as stored in the database
+#[derive(Queryable, Serialize)]
+pub struct SecurityEvent
+{
+    pub id: Uuid,
+    pub user_id: Option<Uuid>,
+    pub kind: String,
+    pub details: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+/// A new security event to be added into the database
+#[derive(Insertable)]
+#[table_name = "security_events"]
+pub struct NewSecurityEvent
+{
+    pub user_id: Option<Uuid>,
+    pub kind: String,
+    pub details: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+pub fn get(conn: &PgConnection, offset_: u32, limit_: u32) -> RbResult<Vec<SecurityEvent>>
+{
+    security_events
+        .order(created_at.desc())
+        .offset(offset_.into())
+        .limit(limit_.into())
+        .load(conn)
+        .map_err(|_| RbError::DbError("Couldn't query security events."))
+}
+
+pub fn create(conn: &PgConnection, new_event: &NewSecurityEvent) -> RbResult<SecurityEvent>
+{
+    insert_into(security_events)
+        .values(new_event)
+        .get_result(conn)
+        .map_err(|_| RbError::DbError("Couldn't insert security event."))
+}
@@ -5,18 +5,35 @@ use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::{
-    errors::{RbError, RbResult},
+    errors::{RbError, RbOption, RbResult},
     schema::{refresh_tokens, refresh_tokens::dsl::*},
 };
 
-/// A refresh token as stored in the database
+/// Maximum length of a session's device label, as enforced by the database
+pub const MAX_DEVICE_LABEL_LENGTH: usize = 255;
+
+/// A refresh token as stored in the database. Refresh tokens are replaced on every use, so a
+/// session is a family of tokens, of which only the newest one is active. Sessions are referred to
+/// using their family's ID, which is serialized as `id`, so the token value itself never has to
+/// leave the server.
 #[derive(Queryable, Serialize)]
 pub struct RefreshToken
 {
+    #[serde(skip_serializing)]
     pub token: Vec<u8>,
     pub user_id: Uuid,
     pub expires_at: chrono::NaiveDateTime,
     pub last_used_at: Option<chrono::NaiveDateTime>,
+    #[serde(skip_serializing)]
+    pub id: Uuid,
+    pub created_at: chrono::NaiveDateTime,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub device_label: Option<String>,
+    #[serde(rename = "id")]
+    pub family_id: Uuid,
+    #[serde(skip_serializing)]
+    pub parent_id: Option<Uuid>,
 }
 
 /// A new refresh token to be added into the database
@@ -27,6 +44,12 @@ pub struct NewRefreshToken
     pub token: Vec<u8>,
     pub user_id: Uuid,
     pub expires_at: chrono::NaiveDateTime,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub device_label: Option<String>,
+    /// Family of the token; a new family is started if not provided
+    pub family_id: Option<Uuid>,
+    pub parent_id: Option<Uuid>,
 }
 
 #[derive(Deserialize, AsChangeset)]
@@ -37,6 +60,22 @@ pub struct PatchRefreshToken
     pub last_used_at: Option<chrono::NaiveDateTime>,
 }
 
+/// Checks whether the given device label fits in the database, so it can be rejected before the
+/// user has logged in.
+///
+/// # Arguments
+///
+/// * `label` - device label to check, if any
+pub fn check_device_label(label: Option<&str>) -> RbResult<()>
+{
+    match label {
+        Some(label) if label.chars().count() > MAX_DEVICE_LABEL_LENGTH => {
+            Err(RbError::AuthInvalidDeviceLabel)
+        },
+        _ => Ok(()),
+    }
+}
+
 pub fn get(conn: &PgConnection, offset_: u32, limit_: u32) -> RbResult<Vec<RefreshToken>>
 {
     Ok(refresh_tokens
@@ -56,6 +95,21 @@ pub fn create(conn: &PgConnection, new_token: &NewRefreshToken) -> RbResult<Refr
     // TODO check for conflict?
 }
 
+/// Returns the refresh token with the given value, if it exists.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `token_` - token value to search for
+pub fn find(conn: &PgConnection, token_: &[u8]) -> RbOption<RefreshToken>
+{
+    match refresh_tokens.filter(token.eq(token_)).first(conn) {
+        Ok(val) => Ok(Some(val)),
+        Err(diesel::NotFound) => Ok(None),
+        _ => Err(RbError::DbError("Couldn't find refresh token.")),
+    }
+}
+
 pub fn update(
     conn: &PgConnection,
     token_: &[u8],
@@ -77,6 +131,86 @@ pub fn delete(conn: &PgConnection, token_: &[u8]) -> RbResult<()>
     Ok(())
 }
 
+/// Returns the active token of the session with the given ID, if the session hasn't ended yet.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `session_id` - ID of the session, i.e. its token family
+pub fn find_active_by_family(conn: &PgConnection, session_id: Uuid) -> RbOption<RefreshToken>
+{
+    match refresh_tokens
+        .filter(family_id.eq(session_id))
+        .filter(last_used_at.is_null())
+        .filter(expires_at.gt(chrono::Utc::now().naive_utc()))
+        .first(conn)
+    {
+        Ok(val) => Ok(Some(val)),
+        Err(diesel::NotFound) => Ok(None),
+        _ => Err(RbError::DbError("Couldn't find session.")),
+    }
+}
+
+/// Returns a user's active sessions, meaning refresh tokens that haven't been used or expired yet,
+/// newest first. As only one token per family can be used, each session is listed once.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `user_id_` - ID of the user whose sessions should be returned
+pub fn find_active_by_user(conn: &PgConnection, user_id_: Uuid) -> RbResult<Vec<RefreshToken>>
+{
+    refresh_tokens
+        .filter(user_id.eq(user_id_))
+        .filter(last_used_at.is_null())
+        .filter(expires_at.gt(chrono::Utc::now().naive_utc()))
+        .order(created_at.desc())
+        .load(conn)
+        .map_err(|_| RbError::DbError("Couldn't query sessions."))
+}
+
+/// Deletes all refresh tokens belonging to the given user.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `user_id_` - ID of the user whose tokens should be removed
+pub fn delete_by_user(conn: &PgConnection, user_id_: Uuid) -> RbResult<()>
+{
+    diesel::delete(refresh_tokens.filter(user_id.eq(user_id_)))
+        .execute(conn)
+        .map_err(|_| RbError::DbError("Couldn't delete user's tokens."))?;
+
+    Ok(())
+}
+
+/// Deletes all refresh tokens in the given family, ending the session they belong to.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `family_id_` - ID of the token family to remove
+pub fn delete_family(conn: &PgConnection, family_id_: Uuid) -> RbResult<()>
+{
+    diesel::delete(refresh_tokens.filter(family_id.eq(family_id_)))
+        .execute(conn)
+        .map_err(|_| RbError::DbError("Couldn't delete token family."))?;
+
+    Ok(())
+}
+
+/// Deletes all refresh tokens that have expired, returning how many were removed.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+pub fn delete_expired(conn: &PgConnection) -> RbResult<usize>
+{
+    diesel::delete(refresh_tokens.filter(expires_at.lt(chrono::Utc::now().naive_utc())))
+        .execute(conn)
+        .map_err(|_| RbError::DbError("Couldn't delete expired tokens."))
+}
+
 /// Returns the token & user data associated with the given refresh token value.
 ///
 /// # Arguments
@@ -97,7 +231,9 @@ pub fn find_with_user(
         .ok()
 }
 
-/// Updates a token's `last_used_at` column value.
+/// Marks a token as used by setting its `last_used_at` column value. This only happens if the token
+/// hasn't been used yet, in the same query, so only one of several concurrent requests using the
+/// token can succeed. Returns whether the token was marked.
 ///
 /// # Arguments
 ///
@@ -111,12 +247,16 @@ pub fn update_last_used_at(
     conn: &PgConnection,
     token_: &[u8],
     last_used_at_: chrono::NaiveDateTime,
-) -> RbResult<()>
+) -> RbResult<bool>
 {
-    diesel::update(refresh_tokens.filter(token.eq(token_)))
-        .set(last_used_at.eq(last_used_at_))
-        .execute(conn)
-        .map_err(|_| RbError::DbError("Couldn't update last_used_at."))?;
+    let updated = diesel::update(
+        refresh_tokens
+            .filter(token.eq(token_))
+            .filter(last_used_at.is_null()),
+    )
+    .set(last_used_at.eq(last_used_at_))
+    .execute(conn)
+    .map_err(|_| RbError::DbError("Couldn't update last_used_at."))?;
 
-    Ok(())
+    Ok(updated == 1)
 }
@@ -0,0 +1,74 @@
+//! Handles user profile-related database operations. Users without a profile simply haven't filled
+//! it in yet.
+
+use diesel::{insert_into, prelude::*, AsChangeset, Insertable, PgConnection, Queryable};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    errors::{RbError, RbOption, RbResult},
+    schema::{user_profiles, user_profiles::dsl::*},
+};
+
+/// Public information about a user
+#[derive(Queryable, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserProfile
+{
+    #[serde(skip_serializing)]
+    pub user_id: Uuid,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub avatar: Option<String>,
+    pub website: Option<String>,
+    pub social_links: Vec<String>,
+    pub public_email: Option<String>,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+/// A profile to be stored; fields that aren't provided are cleared.
+#[derive(Insertable, AsChangeset)]
+#[table_name = "user_profiles"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct NewUserProfile
+{
+    pub user_id: Uuid,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub avatar: Option<String>,
+    pub website: Option<String>,
+    pub social_links: Vec<String>,
+    pub public_email: Option<String>,
+}
+
+/// Returns the profile of the given user, if they've created one.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `user_id_` - ID of the user
+pub fn find(conn: &PgConnection, user_id_: Uuid) -> RbOption<UserProfile>
+{
+    match user_profiles.find(user_id_).first(conn) {
+        Ok(val) => Ok(Some(val)),
+        Err(diesel::NotFound) => Ok(None),
+        _ => Err(RbError::DbError("Couldn't find user profile.")),
+    }
+}
+
+/// Creates or replaces a user's profile.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `profile` - the new profile
+pub fn upsert(conn: &PgConnection, profile: &NewUserProfile) -> RbResult<UserProfile>
+{
+    insert_into(user_profiles)
+        .values(profile)
+        .on_conflict(user_id)
+        .do_update()
+        .set((profile, updated_at.eq(chrono::Utc::now().naive_utc())))
+        .get_result(conn)
+        .map_err(|_| RbError::DbError("Couldn't save user profile."))
+}
@@ -1,12 +1,20 @@
-use diesel::{prelude::*, AsChangeset, Insertable, Queryable};
+use diesel::{
+    prelude::*,
+    result::{DatabaseErrorKind, Error::DatabaseError},
+    AsChangeset, Insertable, Queryable,
+};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use super::audit_events::{Actor, Target};
 use crate::{
-    errors::{RbError, RbResult},
-    schema::{users, users::dsl::*},
+    errors::{RbError, RbOption, RbResult},
+    schema::{audit_events, refresh_tokens, users, users::dsl::*},
 };
 
+/// Maximum length of a username, as enforced by the database
+pub const MAX_USERNAME_LENGTH: usize = 32;
+
 #[derive(Queryable, Serialize)]
 pub struct User
 {
@@ -15,7 +23,21 @@ pub struct User
     #[serde(skip_serializing)]
     pub password: String,
     pub blocked: bool,
-    pub admin: bool,
+    #[serde(skip_serializing)]
+    pub totp_secret: Option<Vec<u8>>,
+    pub totp_enabled: bool,
+    #[serde(skip_serializing)]
+    pub totp_last_step: Option<i64>,
+    pub role: String,
+    pub must_change_password: bool,
+    pub email: Option<String>,
+    pub blocked_reason: Option<String>,
+    pub blocked_at: Option<chrono::NaiveDateTime>,
+    /// Admin that blocked the user; `None` if the user was blocked automatically
+    pub blocked_by: Option<Uuid>,
+    /// Access tokens issued with an older version have been revoked
+    #[serde(skip_serializing)]
+    pub token_version: i32,
 }
 
 #[derive(Insertable, Deserialize)]
@@ -24,25 +46,55 @@ pub struct NewUser
 {
     pub username: String,
     pub password: String,
-    pub admin: bool,
+    /// Role of the new user; defaults to a reader if not provided
+    pub role: Option<String>,
+    /// Address to send password reset emails to
+    pub email: Option<String>,
 }
 
 #[derive(Deserialize, AsChangeset)]
 #[table_name = "users"]
 #[serde(rename_all = "camelCase")]
-pub struct PatchSection
+pub struct PatchUser
 {
-    username: Option<String>,
-    admin: Option<bool>,
+    pub username: Option<String>,
+    pub role: Option<String>,
+    /// Address to send password reset emails to
+    pub email: Option<String>,
 }
 
-pub fn get(conn: &PgConnection, offset_: u32, limit_: u32) -> RbResult<Vec<User>>
+/// Returns a page of users, ordered by username.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `search` - only return users whose username contains this, ignoring case
+/// * `offset_` - amount of users to skip
+/// * `limit_` - maximum amount of users to return
+pub fn get(
+    conn: &PgConnection,
+    search: Option<&str>,
+    offset_: u32,
+    limit_: u32,
+) -> RbResult<Vec<User>>
 {
-    Ok(users
+    let mut query = users.into_boxed();
+
+    if let Some(search) = search {
+        // Wildcards in the search term should be matched literally
+        let pattern = search
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        query = query.filter(username.ilike(format!("%{}%", pattern)));
+    }
+
+    query
+        .order(username.asc())
         .offset(offset_.into())
         .limit(limit_.into())
         .load(conn)
-        .map_err(|_| RbError::DbError("Couldn't query users."))?)
+        .map_err(|_| RbError::DbError("Couldn't query users."))
 }
 
 pub fn find(conn: &PgConnection, user_id: Uuid) -> Option<User>
@@ -50,12 +102,79 @@ pub fn find(conn: &PgConnection, user_id: Uuid) -> Option<User>
     users.find(user_id).first::<User>(conn).ok()
 }
 
-pub fn find_by_username(conn: &PgConnection, username_: &str) -> RbResult<User>
+pub fn find_by_username(conn: &PgConnection, username_: &str) -> RbOption<User>
+{
+    match users.filter(username.eq(username_)).first(conn) {
+        Ok(val) => Ok(Some(val)),
+        Err(diesel::NotFound) => Ok(None),
+        _ => Err(RbError::DbError("Couldn't find users by username.")),
+    }
+}
+
+/// Returns whether any user has the admin role.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+pub fn admin_exists(conn: &PgConnection) -> RbResult<bool>
+{
+    diesel::select(diesel::dsl::exists(
+        users.filter(role.eq(super::roles::ADMIN)),
+    ))
+    .get_result(conn)
+    .map_err(|_| RbError::DbError("Couldn't check for existing admins."))
+}
+
+/// Returns the current token version of a user, or `None` if the user doesn't exist.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `user_id` - ID of the user
+pub fn find_token_version(conn: &PgConnection, user_id: Uuid) -> RbOption<i32>
+{
+    match users.find(user_id).select(token_version).first(conn) {
+        Ok(val) => Ok(Some(val)),
+        Err(diesel::NotFound) => Ok(None),
+        _ => Err(RbError::DbError("Couldn't find token version.")),
+    }
+}
+
+/// Returns the user with the given email address.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `email_` - email address, in lowercase
+pub fn find_by_email(conn: &PgConnection, email_: &str) -> RbOption<User>
+{
+    match users.filter(email.eq(email_)).first(conn) {
+        Ok(val) => Ok(Some(val)),
+        Err(diesel::NotFound) => Ok(None),
+        _ => Err(RbError::DbError("Couldn't find user by email.")),
+    }
+}
+
+/// Checks whether the given username is valid, so it can be rejected before the database does so
+/// with a generic error. Usernames can't contain whitespace, as they're used to log in.
+///
+/// # Arguments
+///
+/// * `username_` - username to check
+pub fn check_username(username_: &str) -> RbResult<()>
 {
-    Ok(users
-        .filter(username.eq(username_))
-        .first::<User>(conn)
-        .map_err(|_| RbError::DbError("Couldn't find users by username."))?)
+    let length = username_.chars().count();
+
+    if length == 0
+        || length > MAX_USERNAME_LENGTH
+        || username_
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control())
+    {
+        return Err(RbError::UMInvalidUsername);
+    }
+
+    Ok(())
 }
 
 /// Insert a new user into the database
@@ -64,18 +183,41 @@ pub fn find_by_username(conn: &PgConnection, username_: &str) -> RbResult<User>
 ///
 /// * `conn` - database connection to use
 /// * `new_user` - user to insert
-pub fn create(conn: &PgConnection, new_user: &NewUser) -> RbResult<()>
+pub fn create(conn: &PgConnection, new_user: &NewUser) -> RbResult<User>
 {
-    let count = diesel::insert_into(users)
+    diesel::insert_into(users)
         .values(new_user)
-        .execute(conn)
-        .map_err(|_| RbError::DbError("Couldn't create user."))?;
+        .get_result(conn)
+        .map_err(|err| match err {
+            DatabaseError(DatabaseErrorKind::UniqueViolation, _) => RbError::UMDuplicateUser,
+            _ => RbError::DbError("Couldn't create user."),
+        })
+}
 
-    if count == 0 {
-        return Err(RbError::UMDuplicateUser);
-    }
+/// Applies the given changes to a user, returning the updated user. Changing the username or role
+/// revokes the user's access tokens, as these contain both.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `user_id` - ID of the user to update
+/// * `patch` - the changes to apply
+pub fn update(conn: &PgConnection, user_id: Uuid, patch: &PatchUser) -> RbResult<User>
+{
+    let query = diesel::update(users.filter(id.eq(user_id)));
 
-    Ok(())
+    let res = if patch.username.is_some() || patch.role.is_some() {
+        query
+            .set((patch, token_version.eq(token_version + 1)))
+            .get_result(conn)
+    } else {
+        query.set(patch).get_result(conn)
+    };
+
+    res.map_err(|err| match err {
+        DatabaseError(DatabaseErrorKind::UniqueViolation, _) => RbError::UMDuplicateUser,
+        _ => RbError::DbError("Couldn't update user."),
+    })
 }
 
 /// Either create a new user or update an existing one on conflict.
@@ -97,12 +239,13 @@ pub fn create(conn: &PgConnection, new_user: &NewUser) -> RbResult<()>
 //     Ok(())
 // }
 
-/// Delete the user with the given ID.
+/// Delete the user with the given ID. Their tokens, credentials & section grants are removed along
+/// with them, while their posts are kept without an author.
 ///
 /// # Arguments
 ///
-/// `conn` - database connection to use
-/// `user_id` - ID of user to delete
+/// * `conn` - database connection to use
+/// * `user_id` - ID of user to delete
 pub fn delete(conn: &PgConnection, user_id: Uuid) -> RbResult<()>
 {
     diesel::delete(users.filter(id.eq(user_id)))
@@ -114,18 +257,199 @@ pub fn delete(conn: &PgConnection, user_id: Uuid) -> RbResult<()>
 
 /// Block a user given an ID.
 /// In practice, this means updating the user's entry so that the `blocked` column is set to
-/// `true`.
+/// `true`. All of the user's tokens are revoked as well, & the block is recorded in the audit log.
 ///
 /// # Arguments
 ///
 /// `conn` - database connection to use
 /// `user_id` - ID of user to block
-pub fn block(conn: &PgConnection, user_id: Uuid) -> RbResult<()>
+/// `reason` - why the user is being blocked
+/// `actor` - who is blocking the user; the ID is `None` if the user is blocked automatically
+pub fn block(conn: &PgConnection, user_id: Uuid, reason: &str, actor: &Actor) -> RbResult<()>
+{
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        diesel::update(users.filter(id.eq(user_id)))
+            .set((
+                blocked.eq(true),
+                blocked_reason.eq(reason),
+                blocked_at.eq(chrono::Utc::now().naive_utc()),
+                blocked_by.eq(actor.id),
+                token_version.eq(token_version + 1),
+            ))
+            .execute(conn)?;
+        diesel::delete(refresh_tokens::table.filter(refresh_tokens::user_id.eq(user_id)))
+            .execute(conn)?;
+        diesel::insert_into(audit_events::table)
+            .values(&actor.event(
+                "user.block",
+                Some(Target::User(user_id)),
+                Some(reason.to_string()),
+            ))
+            .execute(conn)?;
+
+        Ok(())
+    })
+    .map_err(|_| RbError::DbError("Couldn't block user."))
+}
+
+/// Unblock a user given an ID, removing the recorded reason.
+///
+/// # Arguments
+///
+/// `conn` - database connection to use
+/// `user_id` - ID of user to unblock
+pub fn unblock(conn: &PgConnection, user_id: Uuid) -> RbResult<()>
 {
     diesel::update(users.filter(id.eq(user_id)))
-        .set(blocked.eq(true))
+        .set((
+            blocked.eq(false),
+            blocked_reason.eq(None::<String>),
+            blocked_at.eq(None::<chrono::NaiveDateTime>),
+            blocked_by.eq(None::<Uuid>),
+        ))
         .execute(conn)
-        .map_err(|_| RbError::DbError("Couldn't block user."))?;
+        .map_err(|_| RbError::DbError("Couldn't unblock user."))?;
 
     Ok(())
 }
+
+/// Replaces a user's password & revokes all of their tokens.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `user_id` - ID of the user to update
+/// * `password_hash` - hash of the new password
+/// * `must_change` - whether the user has to change the password before being able to log in
+pub fn set_password(
+    conn: &PgConnection,
+    user_id: Uuid,
+    password_hash: &str,
+    must_change: bool,
+) -> RbResult<()>
+{
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        diesel::update(users.filter(id.eq(user_id)))
+            .set((
+                password.eq(password_hash),
+                must_change_password.eq(must_change),
+                token_version.eq(token_version + 1),
+            ))
+            .execute(conn)?;
+        diesel::delete(refresh_tokens::table.filter(refresh_tokens::user_id.eq(user_id)))
+            .execute(conn)?;
+
+        Ok(())
+    })
+    .map_err(|_| RbError::DbError("Couldn't update password."))
+}
+
+/// Replaces the hash of a user's password without otherwise affecting the user, e.g. when upgrading
+/// the hash to stronger parameters. Nothing happens if the user's hash isn't `old_hash` anymore, so
+/// a password change made in the meantime isn't undone.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `user_id` - ID of the user to update
+/// * `old_hash` - the hash being replaced
+/// * `password_hash` - the new hash
+pub fn set_password_hash(
+    conn: &PgConnection,
+    user_id: Uuid,
+    old_hash: &str,
+    password_hash: &str,
+) -> RbResult<()>
+{
+    diesel::update(users.filter(id.eq(user_id)).filter(password.eq(old_hash)))
+        .set(password.eq(password_hash))
+        .execute(conn)
+        .map_err(|_| RbError::DbError("Couldn't update password hash."))?;
+
+    Ok(())
+}
+
+/// Changes the role of a user, returning the updated user. The user's access tokens are revoked, as
+/// they still contain the permissions of the old role.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `user_id` - ID of the user to update
+/// * `role_` - name of the new role
+pub fn set_role(conn: &PgConnection, user_id: Uuid, role_: &str) -> RbResult<User>
+{
+    diesel::update(users.filter(id.eq(user_id)))
+        .set((role.eq(role_), token_version.eq(token_version + 1)))
+        .get_result(conn)
+        .map_err(|_| RbError::DbError("Couldn't update role."))
+}
+
+/// Revokes all of a user's access tokens. Their refresh tokens stay valid, so the user's sessions
+/// can still obtain new access tokens.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `user_id` - ID of the user
+pub fn revoke_access_tokens(conn: &PgConnection, user_id: Uuid) -> RbResult<()>
+{
+    diesel::update(users.filter(id.eq(user_id)))
+        .set(token_version.eq(token_version + 1))
+        .execute(conn)
+        .map_err(|_| RbError::DbError("Couldn't revoke access tokens."))?;
+
+    Ok(())
+}
+
+/// Sets a user's TOTP secret & whether two-factor authentication is enabled. Passing `None` as the
+/// secret removes 2FA entirely.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `user_id` - ID of the user to update
+/// * `secret` - new TOTP secret
+/// * `enabled` - whether logging in should require a TOTP code
+pub fn set_totp(
+    conn: &PgConnection,
+    user_id: Uuid,
+    secret: Option<&[u8]>,
+    enabled: bool,
+) -> RbResult<()>
+{
+    diesel::update(users.filter(id.eq(user_id)))
+        .set((
+            totp_secret.eq(secret),
+            totp_enabled.eq(enabled),
+            totp_last_step.eq(None::<i64>),
+        ))
+        .execute(conn)
+        .map_err(|_| RbError::DbError("Couldn't update TOTP secret."))?;
+
+    Ok(())
+}
+
+/// Marks the given TOTP time step as used. This only succeeds if no later step has been used yet,
+/// which prevents a code from being accepted twice.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `user_id` - ID of the user whose code was used
+/// * `step` - time step of the used code
+///
+/// Returns whether the step could be claimed.
+pub fn claim_totp_step(conn: &PgConnection, user_id: Uuid, step: i64) -> RbResult<bool>
+{
+    let count = diesel::update(
+        users
+            .filter(id.eq(user_id))
+            .filter(totp_last_step.is_null().or(totp_last_step.lt(step))),
+    )
+    .set(totp_last_step.eq(step))
+    .execute(conn)
+    .map_err(|_| RbError::DbError("Couldn't update TOTP step."))?;
+
+    Ok(count == 1)
+}
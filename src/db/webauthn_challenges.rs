@@ -0,0 +1,71 @@
+//! Handles WebAuthn challenge-related database operations.
+
+use diesel::{insert_into, prelude::*, Insertable, PgConnection, Queryable};
+use uuid::Uuid;
+
+use crate::{
+    errors::{RbError, RbOption, RbResult},
+    schema::{webauthn_challenges, webauthn_challenges::dsl::*},
+};
+
+/// A registration or login ceremony in progress
+#[derive(Queryable)]
+pub struct WebauthnChallenge
+{
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub kind: String,
+    pub state: String,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "webauthn_challenges"]
+pub struct NewWebauthnChallenge<'a>
+{
+    pub user_id: Uuid,
+    pub kind: &'a str,
+    pub state: String,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+/// Stores a new challenge, returning its ID. Expired challenges that were never completed are
+/// removed as well.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `new_challenge` - challenge to insert
+pub fn create(conn: &PgConnection, new_challenge: &NewWebauthnChallenge) -> RbResult<Uuid>
+{
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        diesel::delete(webauthn_challenges.filter(expires_at.lt(chrono::Utc::now().naive_utc())))
+            .execute(conn)?;
+
+        insert_into(webauthn_challenges)
+            .values(new_challenge)
+            .returning(id)
+            .get_result(conn)
+    })
+    .map_err(|_| RbError::DbError("Couldn't store WebAuthn challenge."))
+}
+
+/// Removes the challenge with the given ID & kind, returning it if it existed. As this happens in a
+/// single query, a challenge can only be consumed once.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `challenge_id` - ID of the challenge
+/// * `kind_` - kind of ceremony the challenge was created for
+pub fn consume(conn: &PgConnection, challenge_id: Uuid, kind_: &str)
+    -> RbOption<WebauthnChallenge>
+{
+    match diesel::delete(webauthn_challenges.filter(id.eq(challenge_id).and(kind.eq(kind_))))
+        .get_result(conn)
+    {
+        Ok(val) => Ok(Some(val)),
+        Err(diesel::NotFound) => Ok(None),
+        _ => Err(RbError::DbError("Couldn't consume WebAuthn challenge.")),
+    }
+}
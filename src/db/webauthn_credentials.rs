@@ -0,0 +1,177 @@
+//! Handles WebAuthn credential-related database operations.
+
+use diesel::{
+    insert_into,
+    prelude::*,
+    result::{DatabaseErrorKind, Error::DatabaseError},
+    Insertable, PgConnection, Queryable,
+};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    errors::{RbError, RbOption, RbResult},
+    schema::{webauthn_credentials, webauthn_credentials::dsl::*},
+};
+
+/// Maximum length of a credential's name, as enforced by the database
+pub const MAX_NAME_LENGTH: usize = 255;
+
+/// A security key or passkey registered by a user
+#[derive(Queryable, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebauthnCredential
+{
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub credential_id: Vec<u8>,
+    #[serde(skip_serializing)]
+    pub credential: String,
+    #[serde(skip_serializing)]
+    pub sign_count: i64,
+    pub created_at: chrono::NaiveDateTime,
+    pub last_used_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "webauthn_credentials"]
+pub struct NewWebauthnCredential
+{
+    pub user_id: Uuid,
+    pub name: String,
+    pub credential_id: Vec<u8>,
+    pub credential: String,
+    pub sign_count: i64,
+}
+
+/// Checks whether the given credential name is valid, so it can be rejected before the database
+/// does so with a generic error.
+///
+/// # Arguments
+///
+/// * `name_` - name to check
+pub fn check_name(name_: &str) -> RbResult<()>
+{
+    let length = name_.chars().count();
+
+    if length == 0 || length > MAX_NAME_LENGTH || name_.chars().any(|c| c.is_control()) {
+        return Err(RbError::AuthInvalidWebauthnName);
+    }
+
+    Ok(())
+}
+
+/// Stores a newly registered credential.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `new_credential` - the credential to store
+pub fn create(
+    conn: &PgConnection,
+    new_credential: &NewWebauthnCredential,
+) -> RbResult<WebauthnCredential>
+{
+    insert_into(webauthn_credentials)
+        .values(new_credential)
+        .get_result(conn)
+        .map_err(|err| match err {
+            DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                RbError::AuthDuplicateWebauthnCredential
+            },
+            _ => RbError::DbError("Couldn't insert WebAuthn credential."),
+        })
+}
+
+/// Returns all of a user's credentials, oldest first.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `user_id_` - ID of the user whose credentials should be returned
+pub fn find_by_user(conn: &PgConnection, user_id_: Uuid) -> RbResult<Vec<WebauthnCredential>>
+{
+    webauthn_credentials
+        .filter(user_id.eq(user_id_))
+        .order(created_at.asc())
+        .load(conn)
+        .map_err(|_| RbError::DbError("Couldn't query WebAuthn credentials."))
+}
+
+/// Returns a credential given its ID.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `credential_id_` - ID of the credential
+pub fn find(conn: &PgConnection, credential_id_: Uuid) -> RbOption<WebauthnCredential>
+{
+    match webauthn_credentials.find(credential_id_).first(conn) {
+        Ok(val) => Ok(Some(val)),
+        Err(diesel::NotFound) => Ok(None),
+        _ => Err(RbError::DbError("Couldn't find WebAuthn credential.")),
+    }
+}
+
+/// Returns whether a credential with the given authenticator-assigned ID has been registered.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `raw_id` - ID the authenticator uses for the credential
+pub fn exists(conn: &PgConnection, raw_id: &[u8]) -> RbResult<bool>
+{
+    diesel::select(diesel::dsl::exists(
+        webauthn_credentials.filter(credential_id.eq(raw_id)),
+    ))
+    .get_result(conn)
+    .map_err(|_| RbError::DbError("Couldn't check for existing WebAuthn credential."))
+}
+
+/// Stores the signature counter reported by an authenticator during a login. The counter has to be
+/// larger than the stored one, unless the authenticator doesn't implement counters, in which case
+/// both are zero. This check happens in the same query as the update, so concurrent logins can't
+/// both pass it.
+///
+/// Returns whether the counter was valid & has been stored.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `raw_id` - ID the authenticator uses for the credential
+/// * `new_count` - signature counter reported by the authenticator
+pub fn update_sign_count(conn: &PgConnection, raw_id: &[u8], new_count: i64) -> RbResult<bool>
+{
+    let target = webauthn_credentials.filter(credential_id.eq(raw_id));
+    let now = chrono::Utc::now().naive_utc();
+
+    let updated = if new_count == 0 {
+        diesel::update(target.filter(sign_count.eq(0)))
+            .set(last_used_at.eq(now))
+            .execute(conn)
+    } else {
+        diesel::update(target.filter(sign_count.lt(new_count)))
+            .set((sign_count.eq(new_count), last_used_at.eq(now)))
+            .execute(conn)
+    }
+    .map_err(|_| RbError::DbError("Couldn't update WebAuthn signature counter."))?;
+
+    Ok(updated == 1)
+}
+
+/// Removes a credential, so it can't be used to log in anymore.
+///
+/// # Arguments
+///
+/// * `conn` - database connection to use
+/// * `credential_id_` - ID of the credential to remove
+pub fn delete(conn: &PgConnection, credential_id_: Uuid) -> RbResult<()>
+{
+    diesel::delete(webauthn_credentials.find(credential_id_))
+        .execute(conn)
+        .map_err(|_| RbError::DbError("Couldn't delete WebAuthn credential."))?;
+
+    Ok(())
+}
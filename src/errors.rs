@@ -8,19 +8,68 @@ use rocket::{
 #[derive(Debug)]
 pub enum RbError
 {
-    AuthUnknownUser,
-    AuthBlockedUser,
-    AuthInvalidPassword,
+    AuthInvalidCredentials,
+    /// Contains the reason the user was blocked, if any
+    AuthBlockedUser(Option<String>),
     AuthUnauthorized,
     AuthTokenExpired,
+    AuthTokenRevoked,
     AuthRefreshTokenExpired,
     AuthInvalidRefreshToken,
-    AuthDuplicateRefreshToken,
+    /// Contains the ID of the token's owner, whose access tokens have been revoked
+    AuthDuplicateRefreshToken(uuid::Uuid),
     AuthMissingHeader,
+    AuthUnknownSession,
+    AuthInvalidTotpCode,
+    AuthTotpNotSetUp,
+    AuthTotpAlreadyEnabled,
+    AuthInvalidChallenge,
+    AuthInvalidScope,
+    AuthMissingScope,
+    AuthInvalidTokenName,
+    AuthInvalidTokenExpiry,
+    AuthMissingPermission,
+    AuthUnknownToken,
+    /// Contains the amount of seconds after which a new attempt can be made
+    AuthTooManyAttempts(i64),
+    AuthUnknownLockout,
+    AuthPasswordChangeRequired,
+    AuthWeakPassword,
+    AuthInvalidResetToken,
+    AuthInvalidInvite,
+    AuthInvalidCsrfToken,
+    AuthOidcDisabled,
+    AuthInvalidOidcState,
+    AuthOidcFailed,
+    AuthUnknownOidcUser,
+    AuthWebauthnDisabled,
+    AuthInvalidWebauthnChallenge,
+    AuthInvalidWebauthnResponse,
+    AuthDuplicateWebauthnCredential,
+    AuthUnknownWebauthnCredential,
+    AuthInvalidWebauthnName,
+    AuthInvalidSetupToken,
+    AuthInvalidDeviceLabel,
 
     // UM = User Management
     UMDuplicateUser,
     UMUnknownUser,
+    UMUnknownRole,
+    UMInvalidUsername,
+    UMInvalidInvite,
+    UMUnknownInvite,
+    UMMissingBlockReason,
+    UMBlockSelf,
+    UMDeleteSelf,
+    UMChangeOwnRole,
+    UMInvalidProfile,
+
+    // SM = Section Management
+    SMUnknownSection,
+    SMUnknownGrant,
+
+    // AL = Audit Log
+    ALInvalidFilter,
 
     DbError(&'static str),
     Custom(&'static str),
@@ -32,17 +81,62 @@ impl RbError
     {
         // Every entry gets its own line for easy editing later when needed
         match self {
-            RbError::AuthUnknownUser => Status::NotFound,
-            RbError::AuthBlockedUser => Status::Forbidden,
-            RbError::AuthInvalidPassword => Status::Unauthorized,
+            RbError::AuthInvalidCredentials => Status::Unauthorized,
+            RbError::AuthBlockedUser(_) => Status::Forbidden,
             RbError::AuthUnauthorized => Status::Unauthorized,
             RbError::AuthTokenExpired => Status::Unauthorized,
+            RbError::AuthTokenRevoked => Status::Unauthorized,
             RbError::AuthRefreshTokenExpired => Status::Unauthorized,
             RbError::AuthInvalidRefreshToken => Status::Unauthorized,
-            RbError::AuthDuplicateRefreshToken => Status::Unauthorized,
+            RbError::AuthDuplicateRefreshToken(_) => Status::Unauthorized,
             RbError::AuthMissingHeader => Status::BadRequest,
+            RbError::AuthUnknownSession => Status::NotFound,
+            RbError::AuthInvalidTotpCode => Status::Unauthorized,
+            RbError::AuthTotpNotSetUp => Status::BadRequest,
+            RbError::AuthTotpAlreadyEnabled => Status::Conflict,
+            RbError::AuthInvalidChallenge => Status::Unauthorized,
+            RbError::AuthInvalidScope => Status::BadRequest,
+            RbError::AuthMissingScope => Status::Forbidden,
+            RbError::AuthInvalidTokenName => Status::BadRequest,
+            RbError::AuthInvalidTokenExpiry => Status::BadRequest,
+            RbError::AuthMissingPermission => Status::Forbidden,
+            RbError::AuthUnknownToken => Status::NotFound,
+            RbError::AuthTooManyAttempts(_) => Status::TooManyRequests,
+            RbError::AuthUnknownLockout => Status::NotFound,
+            RbError::AuthPasswordChangeRequired => Status::Forbidden,
+            RbError::AuthWeakPassword => Status::BadRequest,
+            RbError::AuthInvalidResetToken => Status::BadRequest,
+            RbError::AuthInvalidInvite => Status::BadRequest,
+            RbError::AuthInvalidCsrfToken => Status::Forbidden,
+            RbError::AuthOidcDisabled => Status::NotFound,
+            RbError::AuthInvalidOidcState => Status::BadRequest,
+            RbError::AuthOidcFailed => Status::BadGateway,
+            RbError::AuthUnknownOidcUser => Status::Forbidden,
+            RbError::AuthWebauthnDisabled => Status::NotFound,
+            RbError::AuthInvalidWebauthnChallenge => Status::BadRequest,
+            RbError::AuthInvalidWebauthnResponse => Status::BadRequest,
+            RbError::AuthDuplicateWebauthnCredential => Status::Conflict,
+            RbError::AuthUnknownWebauthnCredential => Status::NotFound,
+            RbError::AuthInvalidWebauthnName => Status::BadRequest,
+            RbError::AuthInvalidSetupToken => Status::Unauthorized,
+            RbError::AuthInvalidDeviceLabel => Status::BadRequest,
 
             RbError::UMDuplicateUser => Status::Conflict,
+            RbError::UMUnknownUser => Status::NotFound,
+            RbError::UMUnknownRole => Status::BadRequest,
+            RbError::UMInvalidUsername => Status::BadRequest,
+            RbError::UMInvalidInvite => Status::BadRequest,
+            RbError::UMUnknownInvite => Status::NotFound,
+            RbError::UMMissingBlockReason => Status::BadRequest,
+            RbError::UMBlockSelf => Status::BadRequest,
+            RbError::UMDeleteSelf => Status::BadRequest,
+            RbError::UMChangeOwnRole => Status::BadRequest,
+            RbError::UMInvalidProfile => Status::BadRequest,
+
+            RbError::SMUnknownSection => Status::NotFound,
+            RbError::SMUnknownGrant => Status::NotFound,
+
+            RbError::ALInvalidFilter => Status::BadRequest,
 
             RbError::Custom(_) => Status::InternalServerError,
             _ => Status::InternalServerError,
@@ -52,19 +146,80 @@ impl RbError
     pub fn message(&self) -> &'static str
     {
         match self {
-            RbError::AuthUnknownUser => "This user doesn't exist.",
-            RbError::AuthBlockedUser => "This user is blocked.",
-            RbError::AuthInvalidPassword => "Invalid credentials.",
+            RbError::AuthInvalidCredentials => "Invalid credentials.",
+            RbError::AuthBlockedUser(_) => "This user is blocked.",
             RbError::AuthUnauthorized => "You are not authorized to access this resource.",
             RbError::AuthTokenExpired => "This token is not valid anymore.",
+            RbError::AuthTokenRevoked => "This token has been revoked.",
             RbError::AuthRefreshTokenExpired => "This refresh token is not valid anymore.",
             RbError::AuthInvalidRefreshToken => "This refresh token is not valid.",
-            RbError::AuthDuplicateRefreshToken => {
-                "This refresh token has already been used. The user has been blocked."
+            RbError::AuthDuplicateRefreshToken(_) => {
+                "This refresh token has already been used. Its session has been revoked."
             },
             RbError::AuthMissingHeader => "Missing Authorization header.",
+            RbError::AuthUnknownSession => "This session doesn't exist.",
+            RbError::AuthInvalidTotpCode => "Invalid two-factor authentication code.",
+            RbError::AuthTotpNotSetUp => "Two-factor authentication hasn't been set up.",
+            RbError::AuthTotpAlreadyEnabled => "Two-factor authentication is already enabled.",
+            RbError::AuthInvalidChallenge => "This login challenge is invalid or has expired.",
+            RbError::AuthInvalidScope => "Unknown or missing scopes.",
+            RbError::AuthMissingScope => "This token doesn't grant access to this resource.",
+            RbError::AuthInvalidTokenName => "Token names need to be 1 to 255 characters long.",
+            RbError::AuthInvalidTokenExpiry => "Tokens can't expire in the past.",
+            RbError::AuthMissingPermission => "You don't have permission to do this.",
+            RbError::AuthUnknownToken => "This token doesn't exist.",
+            RbError::AuthTooManyAttempts(_) => "Too many failed login attempts. Try again later.",
+            RbError::AuthUnknownLockout => {
+                "There are no failed login attempts for this username or IP address."
+            },
+            RbError::AuthPasswordChangeRequired => {
+                "Your password has been reset. Change it before logging in."
+            },
+            RbError::AuthWeakPassword => "Passwords need to be at least 8 characters long.",
+            RbError::AuthInvalidResetToken => "This password reset link is invalid or has expired.",
+            RbError::AuthInvalidInvite => "This invite is invalid, used up or has expired.",
+            RbError::AuthInvalidCsrfToken => "Missing or invalid CSRF token.",
+            RbError::AuthOidcDisabled => "Single sign-on isn't enabled.",
+            RbError::AuthInvalidOidcState => "This login attempt is invalid or has expired.",
+            RbError::AuthOidcFailed => "Couldn't log in using the identity provider.",
+            RbError::AuthUnknownOidcUser => "No user is linked to this identity.",
+            RbError::AuthWebauthnDisabled => "Security key login isn't enabled.",
+            RbError::AuthInvalidWebauthnChallenge => {
+                "This security key challenge is invalid or has expired."
+            },
+            RbError::AuthInvalidWebauthnResponse => "Couldn't verify the security key's response.",
+            RbError::AuthDuplicateWebauthnCredential => {
+                "This security key has already been registered."
+            },
+            RbError::AuthUnknownWebauthnCredential => "This security key doesn't exist.",
+            RbError::AuthInvalidWebauthnName => {
+                "Security key names need to be 1 to 255 characters long."
+            },
+            RbError::AuthInvalidSetupToken => {
+                "This setup token is invalid or has already been used."
+            },
+            RbError::AuthInvalidDeviceLabel => "Device labels can be at most 255 characters long.",
 
             RbError::UMDuplicateUser => "This user already exists.",
+            RbError::UMUnknownUser => "This user doesn't exist.",
+            RbError::UMUnknownRole => "This role doesn't exist.",
+            RbError::UMInvalidUsername => {
+                "Usernames need to be 1 to 32 characters long & can't contain whitespace."
+            },
+            RbError::UMInvalidInvite => "Invites need to be usable at least once.",
+            RbError::UMUnknownInvite => "This invite doesn't exist.",
+            RbError::UMMissingBlockReason => "A reason is required to block a user.",
+            RbError::UMBlockSelf => "You can't block yourself.",
+            RbError::UMDeleteSelf => "You can't delete yourself.",
+            RbError::UMChangeOwnRole => "You can't change your own role.",
+            RbError::UMInvalidProfile => {
+                "Profile fields can't be too long, & links & email addresses need to be valid."
+            },
+
+            RbError::SMUnknownSection => "This section doesn't exist.",
+            RbError::SMUnknownGrant => "This user hasn't been granted access to this section.",
+
+            RbError::ALInvalidFilter => "Invalid audit log filter.",
 
             RbError::Custom(message) => message,
             _ => "",
@@ -77,13 +232,34 @@ impl<'r> Responder<'r, 'static> for RbError
     fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static>
     {
         let status = self.status();
-        let content = json!({
+        let mut content = json!({
             "status": status.code,
             "message": self.message(),
         });
 
-        // TODO add status to response
-        content.respond_to(req)
+        if let RbError::AuthBlockedUser(Some(reason)) = &self {
+            content["detail"] = json!(reason);
+        }
+
+        let mut response = content.respond_to(req)?;
+        response.set_status(status);
+
+        if let RbError::AuthTooManyAttempts(retry_after) = self {
+            response.set_raw_header("Retry-After", retry_after.to_string());
+        }
+
+        Ok(response)
+    }
+}
+
+/// Allows running code returning an RbError inside a Diesel transaction. Errors returned by the
+/// queries themselves are mapped to their own message before reaching the transaction, so this only
+/// covers e.g. failing to commit.
+impl From<diesel::result::Error> for RbError
+{
+    fn from(_: diesel::result::Error) -> Self
+    {
+        RbError::DbError("Database transaction failed.")
     }
 }
 
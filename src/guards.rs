@@ -1,14 +1,27 @@
-use hmac::{Hmac, NewMac};
-use jwt::VerifyWithKey;
+use std::marker::PhantomData;
+
+use diesel::PgConnection;
 use rocket::{
     http::Status,
     outcome::try_outcome,
     request::{FromRequest, Outcome, Request},
     State,
 };
-use sha2::Sha256;
+use uuid::Uuid;
 
-use crate::{auth::jwt::Claims, errors::RbError, RbConfig};
+use crate::{
+    auth::{
+        cookies,
+        jwt::Claims,
+        keys::JwtKeys,
+        permissions::{Permission, SectionLevel, UsersWrite},
+        personal_tokens,
+        revocation::TokenVersions,
+    },
+    db::{self, audit_events::Actor},
+    errors::{RbError, RbResult},
+    RbConfig, RbDbConn,
+};
 
 /// Extracts an "Authorization: Bearer" string from the headers.
 pub struct Bearer<'a>(&'a str);
@@ -37,7 +50,9 @@ impl<'r> FromRequest<'r> for Bearer<'r>
     }
 }
 
-/// Verifies the provided JWT is valid.
+/// Verifies the provided JWT is valid. The JWT is read from the "Authorization: Bearer" header, or
+/// from the access token cookie if the header isn't present. Requests authenticated using the
+/// cookie need a valid CSRF token.
 pub struct Jwt(Claims);
 
 #[rocket::async_trait]
@@ -47,69 +62,321 @@ impl<'r> FromRequest<'r> for Jwt
 
     async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error>
     {
-        let bearer = try_outcome!(req.guard::<Bearer>().await).0;
+        let bearer = match req.guard::<Bearer>().await {
+            Outcome::Success(bearer) => bearer.0.to_string(),
+            Outcome::Failure(err) => return Outcome::Failure(err),
+            Outcome::Forward(()) => match req.cookies().get(cookies::ACCESS_COOKIE) {
+                Some(cookie) if cookies::check_csrf(req) => cookie.value().to_string(),
+                Some(_) => {
+                    return Outcome::Failure((Status::Forbidden, RbError::AuthInvalidCsrfToken))
+                },
+                None => return Outcome::Forward(()),
+            },
+        };
+
+        // Personal access tokens are handled by their own guard
+        if bearer.starts_with(personal_tokens::PREFIX) {
+            return Outcome::Forward(());
+        }
+
+        let keys = try_outcome!(req.guard::<&State<JwtKeys>>().await.map_failure(|_| (
+            Status::InternalServerError,
+            RbError::Custom("Couldn't get JWT keys guard.")
+        )));
+
+        // Verify token using the matching key
+        match keys.verify(&bearer) {
+            Ok(claims) => Outcome::Success(Self(claims)),
+            Err(err) => Outcome::Failure((err.status(), err)),
+        }
+    }
+}
+
+/// Verifies the JWT's claims, e.g. that it has not expired & was issued by this instance, & that it
+/// hasn't been revoked since.
+pub struct User(pub Claims);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for User
+{
+    type Error = crate::errors::RbError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error>
+    {
+        let claims = try_outcome!(req.guard::<Jwt>().await).0;
         let config = try_outcome!(req.guard::<&State<RbConfig>>().await.map_failure(|_| (
             Status::InternalServerError,
             RbError::Custom("Couldn't get config guard.")
         )));
 
-        let key: Hmac<Sha256> = match Hmac::new_from_slice(&config.jwt.key.as_bytes()) {
-            Ok(key) => key,
-            Err(_) => {
-                return Outcome::Failure((
+        if let Err(err) = claims.validate(&config.jwt) {
+            return Outcome::Failure((err.status(), err));
+        }
+
+        let versions = try_outcome!(req.guard::<&State<TokenVersions>>().await.map_failure(|_| (
+            Status::InternalServerError,
+            RbError::Custom("Couldn't get token versions guard.")
+        )));
+        let ttl = std::time::Duration::from_secs(config.jwt.version_cache_ttl);
+
+        // A newer token than the cached version means the cache missed a change
+        let version = match versions.get(claims.id, ttl) {
+            Some(version) if version >= claims.ver => version,
+            _ => {
+                let conn = try_outcome!(req.guard::<RbDbConn>().await.map_failure(|_| (
                     Status::InternalServerError,
-                    Self::Error::Custom("Failed to do Hmac thing."),
-                ))
+                    RbError::Custom("Couldn't get database connection.")
+                )));
+                let user_id = claims.id;
+
+                match conn
+                    .run(move |c| db::users::find_token_version(c, user_id))
+                    .await
+                {
+                    Ok(Some(version)) => {
+                        versions.insert(user_id, version, ttl);
+                        version
+                    },
+                    // The user has been removed
+                    Ok(None) => {
+                        return Outcome::Failure((Status::Unauthorized, RbError::AuthTokenRevoked))
+                    },
+                    Err(err) => return Outcome::Failure((err.status(), err)),
+                }
             },
         };
 
-        // Verify token using key
-        match bearer.verify_with_key(&key) {
-            Ok(claims) => Outcome::Success(Self(claims)),
-            Err(_) => {
-                return Outcome::Failure((Status::Unauthorized, Self::Error::AuthUnauthorized))
-            },
+        if claims.ver < version {
+            Outcome::Failure((Status::Unauthorized, RbError::AuthTokenRevoked))
+        } else {
+            Outcome::Success(Self(claims))
         }
     }
 }
 
-/// Verifies the JWT has not expired.
-pub struct User(Claims);
+/// Extracts the refresh token from its cookie, making sure the request has a valid CSRF token.
+/// Forwards if the cookie isn't present, i.e. if the client isn't using cookie mode.
+pub struct RefreshCookie(pub String);
 
 #[rocket::async_trait]
-impl<'r> FromRequest<'r> for User
+impl<'r> FromRequest<'r> for RefreshCookie
 {
-    type Error = crate::errors::RbError;
+    type Error = RbError;
 
     async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error>
     {
-        let claims = try_outcome!(req.guard::<Jwt>().await).0;
+        let refresh_token = match req.cookies().get(cookies::REFRESH_COOKIE) {
+            Some(cookie) => cookie.value().to_string(),
+            None => return Outcome::Forward(()),
+        };
 
-        // Verify key hasn't yet expired
-        if chrono::Utc::now().timestamp() > claims.exp {
-            Outcome::Failure((Status::Forbidden, Self::Error::AuthTokenExpired))
+        if cookies::check_csrf(req) {
+            Outcome::Success(Self(refresh_token))
         } else {
-            Outcome::Success(Self(claims))
+            Outcome::Failure((Status::Forbidden, RbError::AuthInvalidCsrfToken))
         }
     }
 }
 
-/// Verifies the JWT belongs to an admin.
-pub struct Admin(Claims);
+/// Information about the client sending the request, stored alongside its sessions. This guard
+/// never fails.
+#[derive(Clone)]
+pub struct ClientInfo
+{
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl ClientInfo
+{
+    /// Describes the given user as performing actions from this client, for the audit log.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - ID of the user; `None` if the client isn't logged in
+    pub fn actor(&self, user_id: Option<Uuid>) -> Actor
+    {
+        Actor {
+            id: user_id,
+            ip_address: self.ip_address.clone(),
+            user_agent: self.user_agent.clone(),
+        }
+    }
+}
 
 #[rocket::async_trait]
-impl<'r> FromRequest<'r> for Admin
+impl<'r> FromRequest<'r> for ClientInfo
 {
-    type Error = crate::errors::RbError;
+    type Error = std::convert::Infallible;
 
     async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error>
     {
-        let user = try_outcome!(req.guard::<User>().await).0;
+        Outcome::Success(Self {
+            ip_address: req.client_ip().map(|ip| ip.to_string()),
+            user_agent: req.headers().get_one("User-Agent").map(String::from),
+        })
+    }
+}
 
-        if user.admin {
-            Outcome::Success(Self(user))
+/// Verifies the bearer token is a valid personal access token. The last field contains the
+/// permissions granted by the user's role.
+pub struct PersonalToken(pub db::PersonalToken, pub db::User, pub Vec<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for PersonalToken
+{
+    type Error = RbError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error>
+    {
+        let bearer = try_outcome!(req.guard::<Bearer>().await).0;
+
+        if !bearer.starts_with(personal_tokens::PREFIX) {
+            return Outcome::Forward(());
+        }
+
+        let conn = try_outcome!(req.guard::<RbDbConn>().await.map_failure(|_| (
+            Status::InternalServerError,
+            RbError::Custom("Couldn't get database connection.")
+        )));
+        let bearer = bearer.to_string();
+
+        match conn.run(move |c| personal_tokens::verify(c, &bearer)).await {
+            Ok((token, user, permissions)) => Outcome::Success(Self(token, user, permissions)),
+            Err(err) => Outcome::Failure((err.status(), err)),
+        }
+    }
+}
+
+/// The user a request is made by, authenticated using either a JWT or a personal access token.
+pub struct Principal
+{
+    pub id: Uuid,
+    pub username: String,
+    pub role: String,
+    /// Permissions granted by the user's role
+    pub permissions: Vec<String>,
+    /// Scopes the request is limited to; `None` if authenticated using a JWT, which isn't limited
+    pub scopes: Option<Vec<String>>,
+}
+
+impl Principal
+{
+    /// Returns whether the user's role grants the given permission.
+    pub fn has_permission(&self, permission: &str) -> bool
+    {
+        self.permissions.iter().any(|p| p == permission)
+    }
+
+    /// Returns whether the request isn't limited to scopes excluding the given permission.
+    pub fn has_scope(&self, permission: &str) -> bool
+    {
+        match &self.scopes {
+            Some(scopes) => scopes.iter().any(|s| s == permission),
+            None => true,
+        }
+    }
+
+    /// Checks whether the request is allowed to use the given permission.
+    pub fn check(&self, permission: &str) -> RbResult<()>
+    {
+        if !self.has_permission(permission) {
+            Err(RbError::AuthMissingPermission)
+        } else if !self.has_scope(permission) {
+            Err(RbError::AuthMissingScope)
         } else {
-            Outcome::Failure((Status::Unauthorized, RbError::AuthUnauthorized))
+            Ok(())
+        }
+    }
+
+    /// Checks whether the request is allowed to access the given section at the given level. Users
+    /// whose role grants the level's permission can access every section, while others need to have
+    /// been granted access to the section itself.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - database connection to use
+    /// * `section_id` - ID of the section being accessed
+    /// * `level` - required level of access
+    pub fn check_section(
+        &self,
+        conn: &PgConnection,
+        section_id: Uuid,
+        level: SectionLevel,
+    ) -> RbResult<()>
+    {
+        let permission = level.permission();
+
+        if !self.has_permission(permission) {
+            let granted = db::section_grants::find(conn, self.id, section_id)?
+                .and_then(|grant| SectionLevel::from_name(&grant.level));
+
+            if !matches!(granted, Some(granted) if granted >= level) {
+                return Err(RbError::AuthMissingPermission);
+            }
+        }
+
+        if self.has_scope(permission) {
+            Ok(())
+        } else {
+            Err(RbError::AuthMissingScope)
         }
     }
 }
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Principal
+{
+    type Error = RbError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error>
+    {
+        match req.guard::<PersonalToken>().await {
+            Outcome::Success(PersonalToken(token, user, permissions)) => {
+                return Outcome::Success(Self {
+                    id: user.id,
+                    username: user.username,
+                    role: user.role,
+                    permissions,
+                    scopes: Some(token.scopes),
+                })
+            },
+            Outcome::Failure(err) => return Outcome::Failure(err),
+            // Not a personal access token, so it should be a JWT
+            Outcome::Forward(()) => {},
+        }
+
+        let claims = try_outcome!(req.guard::<User>().await).0;
+
+        Outcome::Success(Self {
+            id: claims.id,
+            username: claims.username,
+            role: claims.role,
+            permissions: claims.permissions,
+            scopes: None,
+        })
+    }
+}
+
+/// Verifies the user's role grants the given permission, & that the request isn't limited to
+/// scopes excluding it.
+pub struct Authorized<P: Permission>(pub Principal, PhantomData<P>);
+
+#[rocket::async_trait]
+impl<'r, P: Permission + Send> FromRequest<'r> for Authorized<P>
+{
+    type Error = RbError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error>
+    {
+        let principal = try_outcome!(req.guard::<Principal>().await);
+
+        match principal.check(P::NAME) {
+            Ok(()) => Outcome::Success(Self(principal, PhantomData)),
+            Err(err) => Outcome::Failure((err.status(), err)),
+        }
+    }
+}
+
+/// Verifies the request is made by an admin, i.e. someone allowed to manage users.
+pub type Admin = Authorized<UsersWrite>;
@@ -0,0 +1,166 @@
+//! Sending of emails. The actual sending is done by an implementation of the [`Mailer`] trait,
+//! which is chosen using the `mail` configuration section. Besides an SMTP mailer, a file mailer is
+//! provided that writes emails to disk or the log instead, so no mail server is needed during
+//! development & testing.
+
+use std::{path::PathBuf, sync::Arc};
+
+use lettre::{
+    message::Mailbox,
+    transport::smtp::{authentication::Credentials, SmtpTransport},
+    Message, Transport,
+};
+
+use crate::{
+    errors::{RbError, RbResult},
+    RbMailConf, RbMailTransportConf, RbSmtpTls,
+};
+
+/// A plain-text email
+pub struct Email
+{
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Something that can deliver emails. Sending can block, so it shouldn't be called directly from
+/// async code.
+pub trait Mailer: Send + Sync
+{
+    fn send(&self, email: &Email) -> RbResult<()>;
+}
+
+/// Sends emails using an SMTP server.
+pub struct SmtpMailer
+{
+    from: Mailbox,
+    transport: SmtpTransport,
+}
+
+impl Mailer for SmtpMailer
+{
+    fn send(&self, email: &Email) -> RbResult<()>
+    {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(email
+                .to
+                .parse()
+                .map_err(|_| RbError::Custom("Invalid email address."))?)
+            .subject(&email.subject)
+            .body(email.body.clone())
+            .map_err(|_| RbError::Custom("Couldn't build email."))?;
+
+        self.transport
+            .send(&message)
+            .map(|_| ())
+            .map_err(|_| RbError::Custom("Couldn't send email."))
+    }
+}
+
+/// Writes emails to a directory, or logs them if no directory is configured.
+pub struct FileMailer
+{
+    from: String,
+    dir: Option<PathBuf>,
+}
+
+impl Mailer for FileMailer
+{
+    fn send(&self, email: &Email) -> RbResult<()>
+    {
+        let contents = format!(
+            "From: {}\nTo: {}\nSubject: {}\n\n{}\n",
+            self.from, email.to, email.subject, email.body
+        );
+
+        match &self.dir {
+            Some(dir) => {
+                let path = dir.join(format!(
+                    "{}-{}.eml",
+                    chrono::Utc::now().format("%Y%m%dT%H%M%S"),
+                    uuid::Uuid::new_v4()
+                ));
+
+                std::fs::write(path, contents)
+                    .map_err(|_| RbError::Custom("Couldn't write email to file."))
+            },
+            None => {
+                info!("Sending email:\n{}", contents);
+
+                Ok(())
+            },
+        }
+    }
+}
+
+/// Creates the mailer described by the configuration.
+///
+/// # Arguments
+///
+/// * `mail` - mail configuration
+pub fn from_config(mail: &RbMailConf) -> RbResult<Arc<dyn Mailer>>
+{
+    match &mail.transport {
+        RbMailTransportConf::Smtp {
+            host,
+            port,
+            tls,
+            username,
+            password,
+        } => {
+            let from = mail
+                .from
+                .parse()
+                .map_err(|_| RbError::Custom("Invalid sender address."))?;
+
+            let mut builder = match tls {
+                RbSmtpTls::Tls => SmtpTransport::relay(host),
+                RbSmtpTls::StartTls => SmtpTransport::starttls_relay(host),
+                RbSmtpTls::None => Ok(SmtpTransport::builder_dangerous(host)),
+            }
+            .map_err(|_| RbError::Custom("Invalid SMTP configuration."))?;
+
+            if let Some(port) = port {
+                builder = builder.port(*port);
+            }
+
+            if let (Some(username), Some(password)) = (username, password) {
+                builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+            }
+
+            Ok(Arc::new(SmtpMailer {
+                from,
+                transport: builder.build(),
+            }))
+        },
+        RbMailTransportConf::File { dir } => {
+            if let Some(dir) = dir {
+                std::fs::create_dir_all(dir)
+                    .map_err(|_| RbError::Custom("Couldn't create mail directory."))?;
+            }
+
+            Ok(Arc::new(FileMailer {
+                from: mail.from.clone(),
+                dir: dir.as_ref().map(PathBuf::from),
+            }))
+        },
+    }
+}
+
+/// Sends the email in the background, logging any failure. This way, responses don't reveal
+/// whether an email was sent by how long they take.
+///
+/// # Arguments
+///
+/// * `mailer` - mailer to send the email with
+/// * `email` - email to send
+pub fn send_in_background(mailer: Arc<dyn Mailer>, email: Email)
+{
+    rocket::tokio::task::spawn_blocking(move || {
+        if let Err(err) = mailer.send(&email) {
+            error!("Couldn't send email: {}", err.message());
+        }
+    });
+}
@@ -22,15 +22,19 @@ use rocket::{
 };
 use rocket_sync_db_pools::database;
 use serde::{Deserialize, Serialize};
+use structopt::StructOpt;
 
 mod admin;
 pub mod auth;
+mod cli;
 pub mod db;
 pub mod errors;
 pub mod guards;
+pub mod mail;
 pub mod posts;
 pub(crate) mod schema;
 pub mod sections;
+pub mod users;
 
 #[global_allocator]
 static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
@@ -58,44 +62,415 @@ async fn run_db_migrations(rocket: Rocket<Build>) -> Result<Rocket<Build>, Rocke
     .await
 }
 
-async fn create_admin_user<'a>(rocket: &'a Rocket<Orbit>)
+/// Returns the password hash the initial admin should be created with, if one was configured.
+fn bootstrap_password_hash(conf: &RbBootstrapConf) -> Result<Option<String>, String>
+{
+    let hash = match (&conf.password_hash, &conf.password_file) {
+        (Some(_), Some(_)) => {
+            return Err(String::from(
+                "only one of password_hash & password_file can be provided",
+            ))
+        },
+        (Some(hash), None) => hash.clone(),
+        (None, Some(path)) => std::fs::read_to_string(path)
+            .map_err(|err| format!("couldn't read {}: {}", path, err))?
+            .trim_end_matches(&['\r', '\n'][..])
+            .to_string(),
+        (None, None) => return Ok(None),
+    };
+
+    if !auth::pass::is_encoded_hash(&hash) {
+        return Err(String::from("the password isn't an encoded Argon2 hash"));
+    }
+
+    Ok(Some(hash))
+}
+
+/// Creates the initial admin user if no admin exists yet. Without a configured password, a
+/// one-time setup token is printed instead, which can be used to create the admin through the API.
+/// Existing users are never modified, so this is safe to run on every start.
+async fn bootstrap_admin(rocket: Rocket<Build>) -> Result<Rocket<Build>, Rocket<Build>>
 {
     let config = rocket.state::<RbConfig>().expect("RbConfig instance");
-    let admin_user = config.admin_user.clone();
-    let admin_pass = config.admin_pass.clone();
+    let username = config.bootstrap.username.clone();
+
+    let password_hash = match bootstrap_password_hash(&config.bootstrap) {
+        Ok(hash) => hash,
+        Err(err) => {
+            error!("Invalid bootstrap configuration: {}", err);
+            return Err(rocket);
+        },
+    };
 
     let conn = RbDbConn::get_one(&rocket)
         .await
         .expect("database connection");
-    conn.run(move |c| {
-        admin::create_admin_user(c, &admin_user, &admin_pass).expect("failed to create admin user")
-    })
-    .await;
+    let setup_token = auth::setup::SetupToken::default();
+
+    let admin_exists = conn.run(|c| db::users::admin_exists(c)).await;
+
+    let res = match (admin_exists, password_hash) {
+        (Ok(true), _) => Ok(false),
+        // Without a password, the admin has to be created using the setup token
+        (Ok(false), None) => {
+            warn!(
+                "No admin user exists. Create one by sending the setup token {} to /api/auth/setup",
+                setup_token.generate()
+            );
+            Ok(false)
+        },
+        (Ok(false), Some(hash)) => {
+            let actor = db::audit_events::Actor {
+                id: None,
+                ip_address: None,
+                user_agent: None,
+            };
+
+            conn.run(move |c| admin::create_admin_user(c, &username, hash, &actor))
+                .await
+        },
+        (Err(err), _) => Err(err),
+    };
+
+    match res {
+        Ok(true) => info!("Created admin user \"{}\"", config.bootstrap.username),
+        Ok(false) => (),
+        // The existing user isn't touched, so another username or the setup token has to be used
+        Err(errors::RbError::UMDuplicateUser) => warn!(
+            "A non-admin user called \"{}\" already exists. Create an admin by sending the setup \
+             token {} to /api/auth/setup",
+            config.bootstrap.username,
+            setup_token.generate()
+        ),
+        Err(err) => {
+            error!("Couldn't create admin user: {}", err.message());
+            return Err(rocket);
+        },
+    }
+
+    Ok(rocket.manage(setup_token))
+}
+
+/// Periodically removes audit events older than the configured retention period. The task holds on
+/// to a single database connection for as long as the server runs.
+async fn prune_audit_log(rocket: &Rocket<Orbit>)
+{
+    let config = rocket.state::<RbConfig>().expect("RbConfig instance");
+
+    let retention = match config.audit.retention {
+        Some(retention) => chrono::Duration::seconds(retention),
+        None => return,
+    };
+
+    let conn = RbDbConn::get_one(rocket)
+        .await
+        .expect("database connection");
+
+    rocket::tokio::spawn(async move {
+        let mut interval = rocket::tokio::time::interval(std::time::Duration::from_secs(3600));
+
+        loop {
+            interval.tick().await;
+
+            let cutoff = (chrono::Utc::now() - retention).naive_utc();
+
+            match conn
+                .run(move |c| db::audit_events::delete_older_than(c, cutoff))
+                .await
+            {
+                Ok(0) => (),
+                Ok(removed) => info!("Removed {} expired audit events", removed),
+                Err(err) => error!("Couldn't prune audit log: {}", err.message()),
+            }
+        }
+    });
+}
+
+async fn check_config(rocket: Rocket<Build>) -> Result<Rocket<Build>, Rocket<Build>>
+{
+    let config = rocket.state::<RbConfig>().expect("RbConfig instance");
+
+    // Access tokens are renewed using the refresh token, so the latter has to outlive them
+    if config.jwt.access_token_expire >= config.jwt.refresh_token_expire {
+        error!("jwt.access_token_expire has to be shorter than jwt.refresh_token_expire");
+        return Err(rocket);
+    }
+
+    Ok(rocket)
+}
+
+async fn load_jwt_keys(rocket: Rocket<Build>) -> Result<Rocket<Build>, Rocket<Build>>
+{
+    let config = rocket.state::<RbConfig>().expect("RbConfig instance");
+
+    match auth::keys::JwtKeys::load(&config.jwt) {
+        Ok(keys) => Ok(rocket.manage(keys)),
+        Err(err) => {
+            error!("Couldn't load JWT keys: {}", err.message());
+            Err(rocket)
+        },
+    }
+}
+
+async fn create_oidc_provider(rocket: Rocket<Build>) -> Result<Rocket<Build>, Rocket<Build>>
+{
+    match auth::oidc::OidcProvider::new() {
+        Ok(provider) => Ok(rocket.manage(provider)),
+        Err(err) => {
+            error!("Couldn't set up OIDC client: {}", err.message());
+            Err(rocket)
+        },
+    }
+}
+
+async fn create_mailer(rocket: Rocket<Build>) -> Result<Rocket<Build>, Rocket<Build>>
+{
+    let config = rocket.state::<RbConfig>().expect("RbConfig instance");
+
+    match mail::from_config(&config.mail) {
+        Ok(mailer) => Ok(rocket.manage(mailer)),
+        Err(err) => {
+            error!("Couldn't set up mailer: {}", err.message());
+            Err(rocket)
+        },
+    }
+}
+
+/// An asymmetric key used to sign or verify JWTs
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RbJwtKeyConf
+{
+    /// Key ID, used to match a token to the key it was signed with
+    kid: String,
+    /// Either "EdDSA" (Ed25519) or "RS256"
+    algorithm: jsonwebtoken::Algorithm,
+    /// Path to the PEM-encoded key. This should be a private key for the signing key; verification
+    /// keys can be either public or private keys.
+    path: String,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct RbJwtConf
 {
+    /// HMAC secret; used to sign tokens if no signing key is configured, and to verify tokens
+    /// without a key ID
     key: String,
+    /// Asymmetric key to sign tokens with instead of the HMAC secret
+    signing_key: Option<RbJwtKeyConf>,
+    /// Keys accepted when verifying tokens on top of the signing key, e.g. the previous signing key
+    /// while rotating keys
+    #[serde(default)]
+    verification_keys: Vec<RbJwtKeyConf>,
+    /// Whether to keep accepting tokens signed using the HMAC secret once a signing key is
+    /// configured, e.g. while switching to the signing key
+    #[serde(default)]
+    accept_hmac_tokens: bool,
+    /// Value of the "iss" claim; tokens with another issuer are rejected
+    issuer: String,
+    /// Value of the "aud" claim; tokens meant for another audience are rejected
+    audience: String,
+    /// Lifetime of access tokens, in seconds
+    access_token_expire: i64,
+    /// Allowed clock skew when validating time-based claims, in seconds
+    leeway: i64,
+    /// How long a user's token version is cached before checking the database again, in seconds.
+    /// Access tokens revoked by another instance can be used for at most this long.
+    version_cache_ttl: u64,
     refresh_token_size: usize,
+    /// Lifetime of refresh tokens, in seconds; has to be longer than `access_token_expire`
     refresh_token_expire: i64,
+    /// Whether to block a user when one of their refresh tokens is reused, on top of revoking the
+    /// token's family
+    block_on_reuse: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RbTotpConf
+{
+    /// Name shown in authenticator apps
+    issuer: String,
+    /// Lifetime of the challenge token returned when logging in with 2FA enabled, in seconds
+    challenge_expire: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RbThrottleConf
+{
+    /// Amount of failed logins for a single username before attempts start getting delayed
+    free_attempts: i32,
+    /// Amount of failed logins from a single IP address before attempts start getting delayed
+    ip_free_attempts: i32,
+    /// Delay after the first delayed attempt, in seconds; this doubles with each further failure
+    base_delay: i64,
+    /// Maximum delay before a lockout, in seconds
+    max_delay: i64,
+    /// Amount of failed logins for a single username after which it gets locked out
+    lockout_threshold: i32,
+    /// Amount of failed logins from a single IP address after which it gets locked out
+    ip_lockout_threshold: i32,
+    /// Duration of a lockout, in seconds
+    lockout_duration: i64,
+    /// Failed logins are forgotten after this many seconds without new failures
+    reset_after: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum RbSmtpTls
+{
+    /// Unencrypted connection; only use this for local mail servers
+    None,
+    /// Upgrade the connection using STARTTLS
+    StartTls,
+    /// Connect using TLS
+    Tls,
+}
+
+/// How emails are delivered
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(tag = "transport", rename_all = "lowercase")]
+pub enum RbMailTransportConf
+{
+    Smtp
+    {
+        host: String,
+        /// Defaults to the standard port for the TLS mode
+        port: Option<u16>,
+        tls: RbSmtpTls,
+        username: Option<String>,
+        password: Option<String>,
+    },
+    /// Writes emails to the given directory, or logs them if no directory is provided
+    File
+    {
+        dir: Option<String>
+    },
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RbMailConf
+{
+    /// Sender of all emails, e.g. "Rusty Bever <noreply@example.com>"
+    from: String,
+    #[serde(flatten)]
+    transport: RbMailTransportConf,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RbPasswordResetConf
+{
+    /// Link sent to users who forgot their password; `{token}` is replaced with the reset token
+    link: String,
+    /// Lifetime of reset tokens, in seconds
+    token_expire: i64,
+    /// Minimum time between two reset emails sent to the same user, in seconds
+    resend_after: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RbCookieConf
+{
+    /// Whether cookies should only be sent over HTTPS; this should only be disabled for local
+    /// development
+    secure: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RbOidcConf
+{
+    /// Issuer URL of the provider; its configuration is discovered at
+    /// `<issuer>/.well-known/openid-configuration`
+    issuer: String,
+    client_id: String,
+    client_secret: Option<String>,
+    /// Page the provider redirects to after logging in; this page should pass the code & state on to
+    /// `/api/auth/oidc/callback`
+    redirect_uri: String,
+    scopes: Vec<String>,
+    /// Whether to create a new user when someone logs in for the first time
+    provision: bool,
+    /// Role of provisioned users; defaults to a reader if not provided
+    default_role: Option<String>,
+    /// Claim containing the groups the user is a member of
+    groups_claim: String,
+    /// Members of these groups are made admins when logging in, & demoted to `default_role` again
+    /// once they leave them
+    #[serde(default)]
+    admin_groups: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RbWebauthnConf
+{
+    /// Domain credentials are scoped to, e.g. "example.com". Changing this makes every registered
+    /// credential unusable.
+    rp_id: String,
+    /// Name shown by authenticators when registering or using a credential
+    rp_name: String,
+    /// Origin of the frontend, e.g. "https://example.com"; needs to be on the `rp_id` domain
+    origin: url::Url,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RbAuditConf
+{
+    /// Events older than this many seconds are removed; events are kept forever if not provided
+    retention: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RbBootstrapConf
+{
+    /// Username of the admin created on first run
+    username: String,
+    /// Encoded Argon2 hash of the admin's password
+    password_hash: Option<String>,
+    /// File containing the encoded hash instead, e.g. a mounted secret
+    password_file: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RbArgon2Conf
+{
+    /// Memory used to hash a password, in KiB
+    mem_cost: u32,
+    /// Amount of passes over the memory
+    time_cost: u32,
+    /// Degree of parallelism
+    lanes: u32,
+    /// Optional secret mixed into every hash; unlike the salt, this isn't stored in the database
+    pepper: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct RbConfig
 {
-    admin_user: String,
-    admin_pass: String,
+    /// Admin created on first run
+    bootstrap: RbBootstrapConf,
     jwt: RbJwtConf,
+    totp: RbTotpConf,
+    throttle: RbThrottleConf,
+    mail: RbMailConf,
+    password_reset: RbPasswordResetConf,
+    argon2: RbArgon2Conf,
+    cookies: RbCookieConf,
+    audit: RbAuditConf,
+    /// Single sign-on is disabled if not provided
+    oidc: Option<RbOidcConf>,
+    /// Security key & passkey login is disabled if not provided
+    webauthn: Option<RbWebauthnConf>,
 }
 
-#[launch]
-fn rocket() -> _
+/// Loads the configuration from `Rb.yaml` & `RB_`-prefixed environment variables.
+fn figment() -> Figment
 {
-    let figment = Figment::from(rocket::config::Config::default())
+    Figment::from(rocket::config::Config::default())
         .merge(Yaml::file("Rb.yaml").nested())
-        .merge(Env::prefixed("RB_").global());
+        .merge(Env::prefixed("RB_").global())
+}
 
+fn rocket(figment: Figment) -> Rocket<Build>
+{
     // This mut is necessary when the "docs" or "web" feature is enabled, as these further modify
     // the instance variable
     #[allow(unused_mut)]
@@ -105,19 +480,113 @@ fn rocket() -> _
             "Run database migrations",
             run_db_migrations,
         ))
-        // .attach(AdHoc::try_on_ignite("Create admin user", create_admin_user))
         .attach(AdHoc::config::<RbConfig>())
+        .attach(AdHoc::try_on_ignite("Check configuration", check_config))
+        .attach(AdHoc::try_on_ignite(
+            "Bootstrap admin user",
+            bootstrap_admin,
+        ))
+        .attach(AdHoc::try_on_ignite("Load JWT keys", load_jwt_keys))
+        .attach(AdHoc::try_on_ignite("Set up mailer", create_mailer))
+        .attach(AdHoc::try_on_ignite(
+            "Set up OIDC client",
+            create_oidc_provider,
+        ))
+        .attach(AdHoc::on_liftoff("Prune audit log", |rocket| {
+            Box::pin(prune_audit_log(rocket))
+        }))
+        .manage(auth::revocation::TokenVersions::default())
         .register("/", catchers![default_catcher])
         .mount(
             "/api/auth",
-            routes![auth::already_logged_in, auth::login, auth::refresh_token,],
+            routes![
+                auth::already_logged_in,
+                auth::login,
+                auth::refresh_token,
+                auth::refresh_token_cookie,
+                auth::logout,
+                auth::logout_cookie,
+                auth::change_password,
+                auth::password_reset::forgot_password,
+                auth::password_reset::reset_password,
+                auth::invites::register,
+                auth::setup::setup,
+                auth::oidc::login,
+                auth::oidc::callback,
+                auth::jwks
+            ],
+        )
+        .mount(
+            "/api/auth/webauthn",
+            routes![
+                auth::webauthn::register_begin,
+                auth::webauthn::register_finish,
+                auth::webauthn::list,
+                auth::webauthn::delete,
+                auth::webauthn::login_begin,
+                auth::webauthn::login_finish
+            ],
+        )
+        .mount(
+            "/api/auth/2fa",
+            routes![
+                auth::two_factor::setup,
+                auth::two_factor::confirm,
+                auth::two_factor::disable,
+                auth::two_factor::verify
+            ],
+        )
+        .mount(
+            "/api/auth/sessions",
+            routes![auth::sessions::list, auth::sessions::revoke],
+        )
+        .mount(
+            "/api/auth/tokens",
+            routes![
+                auth::personal_tokens::list,
+                auth::personal_tokens::create,
+                auth::personal_tokens::revoke
+            ],
         )
         .mount(
             "/api/admin",
-            routes![admin::create_user, admin::get_user_info],
+            routes![
+                admin::get_users,
+                admin::create_user,
+                admin::get_user_info,
+                admin::patch_user,
+                admin::delete_user,
+                admin::get_roles,
+                admin::set_user_role,
+                admin::block_user,
+                admin::unblock_user,
+                admin::reset_password,
+                admin::get_section_grants,
+                admin::grant_section_access,
+                admin::revoke_section_access,
+                admin::get_lockouts,
+                admin::clear_lockout,
+                admin::get_invites,
+                admin::create_invite,
+                admin::delete_invite,
+                admin::get_audit_events
+            ],
         )
         .mount("/api/sections", routes![sections::create_section])
-        .mount("/api/posts", routes![posts::get, posts::create]);
+        .mount(
+            "/api/users",
+            routes![users::get_profile, users::update_profile],
+        )
+        .mount(
+            "/api/posts",
+            routes![
+                posts::get,
+                posts::create,
+                posts::find,
+                posts::patch,
+                posts::delete
+            ],
+        );
 
     // It's weird that this is allowed, but the line on its own isn't
     #[cfg(feature = "web")]
@@ -144,3 +613,22 @@ fn rocket() -> _
 
     instance
 }
+
+fn main()
+{
+    let opts = cli::Opts::from_args();
+    let figment = figment();
+
+    match opts.command {
+        None | Some(cli::Command::Serve) => {
+            // Launch errors are reported when they're dropped
+            let _res = rocket::async_main(rocket(figment).launch());
+        },
+        Some(command) => {
+            if let Err(err) = cli::run(&figment, command) {
+                eprintln!("Error: {}", err);
+                std::process::exit(1);
+            }
+        },
+    }
+}
@@ -1,9 +1,11 @@
+use diesel::Connection;
 use rocket::serde::json::Json;
 
 use crate::{
-    db,
-    errors::{RbOption, RbResult},
-    guards::Admin,
+    auth::permissions::SectionLevel,
+    db::{self, audit_events::Target},
+    errors::{RbError, RbOption, RbResult},
+    guards::{ClientInfo, Principal},
     RbDbConn,
 };
 
@@ -17,15 +19,34 @@ pub async fn get(conn: RbDbConn, offset: u32, limit: u32) -> RbResult<Json<Vec<d
 
 #[post("/", data = "<new_post>")]
 pub async fn create(
-    _admin: Admin,
+    principal: Principal,
     conn: RbDbConn,
+    client: ClientInfo,
     new_post: Json<db::NewPost>,
 ) -> RbResult<Json<db::Post>>
 {
-    Ok(Json(
-        conn.run(move |c| db::posts::create(c, &new_post.into_inner()))
-            .await?,
-    ))
+    let mut new_post = new_post.into_inner();
+    new_post.author_id = Some(principal.id);
+
+    conn.run(move |c| {
+        c.transaction::<_, RbError, _>(|| {
+            principal.check_section(c, new_post.section_id, SectionLevel::Write)?;
+
+            let post = db::posts::create(c, &new_post)?;
+
+            db::audit_events::record(
+                c,
+                &client.actor(Some(principal.id)),
+                "post.create",
+                Some(Target::Post(post.id)),
+                None,
+            )?;
+
+            Ok(post)
+        })
+    })
+    .await
+    .map(Json)
 }
 
 #[get("/<id>")]
@@ -37,22 +58,87 @@ pub async fn find(conn: RbDbConn, id: uuid::Uuid) -> RbOption<Json<db::Post>>
         .and_then(|p| Some(Json(p))))
 }
 
+/// Returns the level of access to the post's section required to modify it. Authors can modify
+/// their own posts, while other posts require `edit-any` access.
+fn required_level(principal: &Principal, post: &db::Post) -> SectionLevel
+{
+    if post.author_id == Some(principal.id) {
+        SectionLevel::Write
+    } else {
+        SectionLevel::EditAny
+    }
+}
+
 #[patch("/<id>", data = "<patch_post>")]
 pub async fn patch(
-    _admin: Admin,
+    principal: Principal,
     conn: RbDbConn,
+    client: ClientInfo,
     id: uuid::Uuid,
     patch_post: Json<db::PatchPost>,
-) -> RbResult<Json<db::Post>>
+) -> RbOption<Json<db::Post>>
 {
-    Ok(Json(
-        conn.run(move |c| db::posts::update(c, &id, &patch_post.into_inner()))
-            .await?,
-    ))
+    let patch_post = patch_post.into_inner();
+
+    conn.run(move |c| {
+        c.transaction::<_, RbError, _>(|| {
+            let post = match db::posts::find(c, &id)? {
+                Some(post) => post,
+                None => return Ok(None),
+            };
+
+            let level = required_level(&principal, &post);
+            principal.check_section(c, post.section_id, level)?;
+
+            // Moving a post requires access to the new section as well
+            if let Some(section_id) = patch_post.section_id {
+                principal.check_section(c, section_id, level)?;
+            }
+
+            let post = db::posts::update(c, &id, &patch_post)?;
+
+            db::audit_events::record(
+                c,
+                &client.actor(Some(principal.id)),
+                "post.update",
+                Some(Target::Post(id)),
+                None,
+            )?;
+
+            Ok(Some(Json(post)))
+        })
+    })
+    .await
 }
 
 #[delete("/<id>")]
-pub async fn delete(_admin: Admin, conn: RbDbConn, id: uuid::Uuid) -> RbResult<()>
+pub async fn delete(
+    principal: Principal,
+    conn: RbDbConn,
+    client: ClientInfo,
+    id: uuid::Uuid,
+) -> RbOption<()>
 {
-    Ok(conn.run(move |c| db::posts::delete(c, &id)).await?)
+    conn.run(move |c| {
+        c.transaction::<_, RbError, _>(|| {
+            let post = match db::posts::find(c, &id)? {
+                Some(post) => post,
+                None => return Ok(None),
+            };
+
+            principal.check_section(c, post.section_id, required_level(&principal, &post))?;
+
+            db::posts::delete(c, &id)?;
+
+            db::audit_events::record(
+                c,
+                &client.actor(Some(principal.id)),
+                "post.delete",
+                Some(Target::Post(id)),
+                None,
+            )
+            .map(Some)
+        })
+    })
+    .await
 }
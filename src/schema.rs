@@ -1,3 +1,79 @@
+table! {
+    audit_events (id) {
+        id -> Uuid,
+        actor_id -> Nullable<Uuid>,
+        action -> Varchar,
+        target_type -> Nullable<Varchar>,
+        target_id -> Nullable<Varchar>,
+        details -> Nullable<Text>,
+        ip_address -> Nullable<Text>,
+        user_agent -> Nullable<Text>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    invites (id) {
+        id -> Uuid,
+        code_hash -> Bytea,
+        role -> Nullable<Varchar>,
+        max_uses -> Int4,
+        uses -> Int4,
+        created_by -> Nullable<Uuid>,
+        created_at -> Timestamp,
+        expires_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    login_throttles (kind, subject) {
+        kind -> Varchar,
+        subject -> Varchar,
+        failures -> Int4,
+        last_failure_at -> Timestamp,
+        locked_until -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    oidc_identities (issuer, subject) {
+        issuer -> Varchar,
+        subject -> Varchar,
+        user_id -> Uuid,
+        created_at -> Timestamp,
+        role_from_groups -> Bool,
+    }
+}
+
+table! {
+    password_reset_tokens (token_hash) {
+        token_hash -> Bytea,
+        user_id -> Uuid,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+    }
+}
+
+table! {
+    permissions (name) {
+        name -> Varchar,
+        description -> Text,
+    }
+}
+
+table! {
+    personal_access_tokens (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        name -> Varchar,
+        token_hash -> Bytea,
+        scopes -> Array<Text>,
+        created_at -> Timestamp,
+        expires_at -> Nullable<Timestamp>,
+        last_used_at -> Nullable<Timestamp>,
+    }
+}
+
 table! {
     posts (id) {
         id -> Uuid,
@@ -5,6 +81,14 @@ table! {
         title -> Nullable<Varchar>,
         publish_date -> Date,
         content -> Text,
+        author_id -> Nullable<Uuid>,
+    }
+}
+
+table! {
+    recovery_codes (user_id, code_hash) {
+        user_id -> Uuid,
+        code_hash -> Bytea,
     }
 }
 
@@ -14,6 +98,48 @@ table! {
         user_id -> Uuid,
         expires_at -> Timestamp,
         last_used_at -> Nullable<Timestamp>,
+        id -> Uuid,
+        created_at -> Timestamp,
+        ip_address -> Nullable<Text>,
+        user_agent -> Nullable<Text>,
+        device_label -> Nullable<Varchar>,
+        family_id -> Uuid,
+        parent_id -> Nullable<Uuid>,
+    }
+}
+
+table! {
+    role_permissions (role, permission) {
+        role -> Varchar,
+        permission -> Varchar,
+    }
+}
+
+table! {
+    roles (name) {
+        name -> Varchar,
+        description -> Text,
+    }
+}
+
+table! {
+    security_events (id) {
+        id -> Uuid,
+        user_id -> Nullable<Uuid>,
+        kind -> Varchar,
+        details -> Nullable<Text>,
+        ip_address -> Nullable<Text>,
+        user_agent -> Nullable<Text>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    section_grants (user_id, section_id) {
+        user_id -> Uuid,
+        section_id -> Uuid,
+        level -> Varchar,
+        created_at -> Timestamp,
     }
 }
 
@@ -28,17 +154,98 @@ table! {
     }
 }
 
+table! {
+    user_profiles (user_id) {
+        user_id -> Uuid,
+        display_name -> Nullable<Varchar>,
+        bio -> Nullable<Text>,
+        avatar -> Nullable<Varchar>,
+        website -> Nullable<Varchar>,
+        social_links -> Array<Text>,
+        public_email -> Nullable<Varchar>,
+        updated_at -> Timestamp,
+    }
+}
+
 table! {
     users (id) {
         id -> Uuid,
         username -> Varchar,
         password -> Text,
         blocked -> Bool,
-        admin -> Bool,
+        totp_secret -> Nullable<Bytea>,
+        totp_enabled -> Bool,
+        totp_last_step -> Nullable<Int8>,
+        role -> Varchar,
+        must_change_password -> Bool,
+        email -> Nullable<Varchar>,
+        blocked_reason -> Nullable<Text>,
+        blocked_at -> Nullable<Timestamp>,
+        blocked_by -> Nullable<Uuid>,
+        token_version -> Int4,
+    }
+}
+
+table! {
+    webauthn_challenges (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        kind -> Varchar,
+        state -> Text,
+        expires_at -> Timestamp,
+    }
+}
+
+table! {
+    webauthn_credentials (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        name -> Varchar,
+        credential_id -> Bytea,
+        credential -> Text,
+        sign_count -> Int8,
+        created_at -> Timestamp,
+        last_used_at -> Nullable<Timestamp>,
     }
 }
 
+joinable!(invites -> roles (role));
+joinable!(invites -> users (created_by));
+joinable!(oidc_identities -> users (user_id));
+joinable!(password_reset_tokens -> users (user_id));
+joinable!(personal_access_tokens -> users (user_id));
 joinable!(posts -> sections (section_id));
+joinable!(posts -> users (author_id));
+joinable!(recovery_codes -> users (user_id));
 joinable!(refresh_tokens -> users (user_id));
+joinable!(role_permissions -> permissions (permission));
+joinable!(role_permissions -> roles (role));
+joinable!(section_grants -> sections (section_id));
+joinable!(section_grants -> users (user_id));
+joinable!(security_events -> users (user_id));
+joinable!(user_profiles -> users (user_id));
+joinable!(users -> roles (role));
+joinable!(webauthn_challenges -> users (user_id));
+joinable!(webauthn_credentials -> users (user_id));
 
-allow_tables_to_appear_in_same_query!(posts, refresh_tokens, sections, users,);
+allow_tables_to_appear_in_same_query!(
+    audit_events,
+    invites,
+    login_throttles,
+    oidc_identities,
+    password_reset_tokens,
+    permissions,
+    personal_access_tokens,
+    posts,
+    recovery_codes,
+    refresh_tokens,
+    role_permissions,
+    roles,
+    section_grants,
+    security_events,
+    sections,
+    user_profiles,
+    users,
+    webauthn_challenges,
+    webauthn_credentials,
+);
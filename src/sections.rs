@@ -1,25 +1,48 @@
 //! This module handles management of site sections (aka blogs).
 
+use diesel::Connection;
 use rocket::serde::json::Json;
 
-use crate::{db, errors::RbResult, guards::Admin, RbDbConn};
+use crate::{
+    auth::permissions::SectionsWrite,
+    db::{self, audit_events::Target},
+    errors::{RbError, RbResult},
+    guards::{Authorized, ClientInfo},
+    RbDbConn,
+};
 
 /// Route for creating a new section.
 ///
 /// # Arguments
 ///
-/// * `_admin` - guard ensuring user is admin
+/// * `auth` - guard ensuring the user is allowed to manage sections
 /// * `conn` - guard providing a connection to the database
+/// * `client` - information about the client making the request
 /// * `new_section` - Json-encoded NewSection object
 #[post("/", data = "<new_section>")]
 pub async fn create_section(
-    _admin: Admin,
+    auth: Authorized<SectionsWrite>,
     conn: RbDbConn,
+    client: ClientInfo,
     new_section: Json<db::NewSection>,
 ) -> RbResult<Json<db::Section>>
 {
     Ok(Json(
-        conn.run(move |c| db::sections::create(c, &new_section.into_inner()))
-            .await?,
+        conn.run(move |c| {
+            c.transaction::<_, RbError, _>(|| {
+                let section = db::sections::create(c, &new_section.into_inner())?;
+
+                db::audit_events::record(
+                    c,
+                    &client.actor(Some(auth.0.id)),
+                    "section.create",
+                    Some(Target::Section(section.id)),
+                    None,
+                )?;
+
+                Ok(section)
+            })
+        })
+        .await?,
     ))
 }
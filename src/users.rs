@@ -0,0 +1,218 @@
+//! Public user profiles, allowing the frontend to show e.g. an author card next to a post.
+
+use diesel::Connection;
+use rocket::serde::json::Json;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::permissions::{Permission, UsersWrite},
+    db::{self, audit_events::Target},
+    errors::{RbError, RbResult},
+    guards::{ClientInfo, User},
+    RbDbConn,
+};
+
+/// Maximum length of a display name, as enforced by the database
+const MAX_DISPLAY_NAME_LENGTH: usize = 64;
+/// Maximum length of a bio
+const MAX_BIO_LENGTH: usize = 2000;
+/// Maximum length of the avatar link, website, social links & email address
+const MAX_FIELD_LENGTH: usize = 255;
+/// Maximum amount of social links
+const MAX_SOCIAL_LINKS: usize = 10;
+
+/// The publicly visible information about a user
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicProfile
+{
+    username: String,
+    display_name: Option<String>,
+    bio: Option<String>,
+    avatar: Option<String>,
+    website: Option<String>,
+    social_links: Vec<String>,
+    public_email: Option<String>,
+}
+
+impl PublicProfile
+{
+    fn new(user: db::User, profile: Option<db::UserProfile>) -> Self
+    {
+        match profile {
+            Some(profile) => Self {
+                username: user.username,
+                display_name: profile.display_name,
+                bio: profile.bio,
+                avatar: profile.avatar,
+                website: profile.website,
+                social_links: profile.social_links,
+                public_email: profile.public_email,
+            },
+            None => Self {
+                username: user.username,
+                display_name: None,
+                bio: None,
+                avatar: None,
+                website: None,
+                social_links: Vec::new(),
+                public_email: None,
+            },
+        }
+    }
+}
+
+/// Returns a user's public profile. Blocked users are reported as unknown, so their profiles can't
+/// be used to spread spam.
+///
+/// # Arguments
+///
+/// * `conn` - guard providing a connection to the database
+/// * `username` - username of the user
+#[get("/<username>")]
+pub async fn get_profile(conn: RbDbConn, username: String) -> RbResult<Json<PublicProfile>>
+{
+    conn.run(move |c| {
+        let user = db::users::find_by_username(c, &username)?
+            .filter(|user| !user.blocked)
+            .ok_or(RbError::UMUnknownUser)?;
+        let profile = db::user_profiles::find(c, user.id)?;
+
+        Ok(PublicProfile::new(user, profile))
+    })
+    .await
+    .map(Json)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileRequest
+{
+    display_name: Option<String>,
+    bio: Option<String>,
+    /// Link to the user's avatar
+    avatar: Option<String>,
+    website: Option<String>,
+    #[serde(default)]
+    social_links: Vec<String>,
+    public_email: Option<String>,
+}
+
+/// Trims a field, treating an empty value as not provided.
+fn normalize(value: Option<String>) -> Option<String>
+{
+    value
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// Checks whether the value is an absolute HTTP(S) link, as others could be used to run scripts
+/// when clicked.
+fn is_link(value: &str) -> bool
+{
+    matches!(url::Url::parse(value), Ok(url) if url.scheme() == "http" || url.scheme() == "https")
+}
+
+/// Validates the requested profile, converting it into one that can be stored.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user the profile belongs to
+/// * `request` - the requested profile
+fn check_profile(user_id: uuid::Uuid, request: ProfileRequest) -> RbResult<db::NewUserProfile>
+{
+    let profile = db::NewUserProfile {
+        user_id,
+        display_name: normalize(request.display_name),
+        bio: normalize(request.bio),
+        avatar: normalize(request.avatar),
+        website: normalize(request.website),
+        social_links: request
+            .social_links
+            .into_iter()
+            .filter_map(|link| normalize(Some(link)))
+            .collect(),
+        public_email: normalize(request.public_email).map(|email| email.to_lowercase()),
+    };
+
+    let fits = |value: &Option<String>, max: usize| match value {
+        Some(value) => value.chars().count() <= max,
+        None => true,
+    };
+    let link = |value: &Option<String>| match value {
+        Some(value) => is_link(value),
+        None => true,
+    };
+    let valid_email = match &profile.public_email {
+        Some(email) => email.contains('@') && !email.contains(char::is_whitespace),
+        None => true,
+    };
+
+    let valid = fits(&profile.display_name, MAX_DISPLAY_NAME_LENGTH)
+        && fits(&profile.bio, MAX_BIO_LENGTH)
+        && fits(&profile.avatar, MAX_FIELD_LENGTH)
+        && fits(&profile.website, MAX_FIELD_LENGTH)
+        && fits(&profile.public_email, MAX_FIELD_LENGTH)
+        && link(&profile.avatar)
+        && link(&profile.website)
+        && profile.social_links.len() <= MAX_SOCIAL_LINKS
+        && profile
+            .social_links
+            .iter()
+            .all(|link| link.chars().count() <= MAX_FIELD_LENGTH && is_link(link))
+        && valid_email;
+
+    if !valid {
+        return Err(RbError::UMInvalidProfile);
+    }
+
+    Ok(profile)
+}
+
+/// Replaces a user's profile; fields that aren't provided are cleared. Users can only edit their
+/// own profile, while admins can edit anyone's.
+///
+/// # Arguments
+///
+/// * `user` - guard ensuring the user is logged in
+/// * `conn` - guard providing a connection to the database
+/// * `client` - information about the client making the request
+/// * `username` - username of the user whose profile should be replaced
+/// * `request` - the new profile
+#[put("/<username>/profile", data = "<request>")]
+pub async fn update_profile(
+    user: User,
+    conn: RbDbConn,
+    client: ClientInfo,
+    username: String,
+    request: Json<ProfileRequest>,
+) -> RbResult<Json<PublicProfile>>
+{
+    let claims = user.0;
+
+    conn.run(move |c| {
+        c.transaction::<_, RbError, _>(|| {
+            let target =
+                db::users::find_by_username(c, &username)?.ok_or(RbError::UMUnknownUser)?;
+
+            if target.id != claims.id && !claims.has_permission(UsersWrite::NAME) {
+                return Err(RbError::AuthUnauthorized);
+            }
+
+            let profile =
+                db::user_profiles::upsert(c, &check_profile(target.id, request.into_inner())?)?;
+
+            db::audit_events::record(
+                c,
+                &client.actor(Some(claims.id)),
+                "user.profile",
+                Some(Target::User(target.id)),
+                None,
+            )?;
+
+            Ok(PublicProfile::new(target, Some(profile)))
+        })
+    })
+    .await
+    .map(Json)
+}
@@ -0,0 +1,195 @@
+"""
+Tests single sign-on against a mock OpenID Connect issuer, which this script runs itself on port
+9000. The server should be started with the following configuration:
+
+RB_OIDC='{issuer="http://localhost:9000",client_id="rusty-bever",client_secret="secret",redirect_uri="http://localhost:8000/sso",scopes=["openid","profile"],provision=true,groups_claim="groups",admin_groups=["admins"]}'
+"""
+import base64
+import hashlib
+import json
+import secrets
+import threading
+import time
+from http.server import BaseHTTPRequestHandler, HTTPServer
+from urllib.parse import parse_qs, urlencode, urlparse
+
+import requests
+from cryptography.hazmat.primitives import hashes
+from cryptography.hazmat.primitives.asymmetric import padding, rsa
+
+
+ISSUER = "http://localhost:9000"
+CLIENT_ID = "rusty-bever"
+CLIENT_SECRET = "secret"
+BASE_URL = "http://localhost:8000/api"
+
+KEY = rsa.generate_private_key(public_exponent=65537, key_size=2048)
+
+
+def b64(data):
+    return base64.urlsafe_b64encode(data).rstrip(b"=").decode()
+
+
+def b64_int(n):
+    return b64(n.to_bytes((n.bit_length() + 7) // 8, "big"))
+
+
+def sign(claims):
+    header = b64(json.dumps({"alg": "RS256", "typ": "JWT", "kid": "mock"}).encode())
+    payload = b64(json.dumps(claims).encode())
+    signature = KEY.sign(f"{header}.{payload}".encode(), padding.PKCS1v15(), hashes.SHA256())
+
+    return f"{header}.{payload}.{b64(signature)}"
+
+
+class MockIssuer(BaseHTTPRequestHandler):
+    # Claims of the user that logs in next
+    user = {}
+    # Pending authorization codes
+    codes = {}
+
+    def _json(self, body, status=200):
+        self.send_response(status)
+        self.send_header("Content-Type", "application/json")
+        self.end_headers()
+        self.wfile.write(json.dumps(body).encode())
+
+    def do_GET(self):
+        url = urlparse(self.path)
+        query = {k: v[0] for k, v in parse_qs(url.query).items()}
+
+        if url.path == "/.well-known/openid-configuration":
+            self._json({
+                "issuer": ISSUER,
+                "authorization_endpoint": f"{ISSUER}/authorize",
+                "token_endpoint": f"{ISSUER}/token",
+                "jwks_uri": f"{ISSUER}/jwks",
+            })
+
+        elif url.path == "/jwks":
+            numbers = KEY.public_key().public_numbers()
+            self._json({"keys": [{
+                "kty": "RSA",
+                "kid": "mock",
+                "alg": "RS256",
+                "use": "sig",
+                "n": b64_int(numbers.n),
+                "e": b64_int(numbers.e),
+            }]})
+
+        elif url.path == "/authorize":
+            assert query["client_id"] == CLIENT_ID
+            assert query["code_challenge_method"] == "S256"
+
+            code = secrets.token_urlsafe()
+            MockIssuer.codes[code] = (query, dict(MockIssuer.user))
+
+            self.send_response(302)
+            self.send_header("Location", f"{query['redirect_uri']}?" + urlencode({
+                "code": code,
+                "state": query["state"],
+            }))
+            self.end_headers()
+
+        else:
+            self._json({}, 404)
+
+    def do_POST(self):
+        body = self.rfile.read(int(self.headers["Content-Length"])).decode()
+        form = {k: v[0] for k, v in parse_qs(body).items()}
+
+        if form.get("code") not in MockIssuer.codes:
+            return self._json({"error": "invalid_grant"}, 400)
+
+        query, user = MockIssuer.codes.pop(form["code"])
+        challenge = b64(hashlib.sha256(form["code_verifier"].encode()).digest())
+
+        if challenge != query["code_challenge"] or form["client_secret"] != CLIENT_SECRET:
+            return self._json({"error": "invalid_grant"}, 400)
+
+        now = int(time.time())
+        self._json({"id_token": sign({
+            "iss": ISSUER,
+            "aud": CLIENT_ID,
+            "iat": now,
+            "exp": now + 60,
+            "nonce": query["nonce"],
+            **user,
+        })})
+
+    def log_message(self, *args):
+        pass
+
+
+def start_login(session):
+    """Follows the redirects up to the frontend, returning the code & state it receives."""
+    r = session.get(f"{BASE_URL}/auth/oidc/login", allow_redirects=False)
+    assert r.status_code == 303, r.text
+
+    r = session.get(r.headers["Location"], allow_redirects=False)
+    query = parse_qs(urlparse(r.headers["Location"]).query)
+
+    return {"code": query["code"][0], "state": query["state"][0]}
+
+
+def claims_of(token):
+    payload = token.split(".")[1]
+    return json.loads(base64.urlsafe_b64decode(payload + "=" * (-len(payload) % 4)))
+
+
+def test_provisioning():
+    MockIssuer.user = {"sub": secrets.token_hex(8), "preferred_username": f"sso-{secrets.token_hex(4)}"}
+    session = requests.Session()
+
+    r = session.post(f"{BASE_URL}/auth/oidc/callback", json=start_login(session))
+    assert r.status_code == 200, r.text
+    claims = claims_of(r.json()["token"])
+    assert claims["username"] == MockIssuer.user["preferred_username"]
+    assert claims["role"] == "reader"
+
+    # Logging in again uses the same user
+    r = session.post(f"{BASE_URL}/auth/oidc/callback", json=start_login(session))
+    assert claims_of(r.json()["token"])["id"] == claims["id"]
+
+
+def test_admin_groups():
+    MockIssuer.user = {
+        "sub": secrets.token_hex(8),
+        "preferred_username": f"sso-{secrets.token_hex(4)}",
+        "groups": ["admins"],
+    }
+    session = requests.Session()
+
+    r = session.post(f"{BASE_URL}/auth/oidc/callback", json=start_login(session))
+    assert r.status_code == 200, r.text
+    assert claims_of(r.json()["token"])["role"] == "admin"
+
+
+def test_state_checks():
+    MockIssuer.user = {"sub": secrets.token_hex(8), "preferred_username": f"sso-{secrets.token_hex(4)}"}
+    session = requests.Session()
+
+    # The state has to match the one in the cookie
+    callback = start_login(session)
+    r = session.post(f"{BASE_URL}/auth/oidc/callback", json={**callback, "state": "forged"})
+    assert r.status_code == 400, r.text
+
+    # A login can only be completed once, & only by the browser that started it
+    callback = start_login(session)
+    r = requests.post(f"{BASE_URL}/auth/oidc/callback", json=callback)
+    assert r.status_code == 400, r.text
+
+    r = session.post(f"{BASE_URL}/auth/oidc/callback", json=callback)
+    assert r.status_code == 200, r.text
+
+    r = session.post(f"{BASE_URL}/auth/oidc/callback", json=callback)
+    assert r.status_code == 400, r.text
+
+
+if __name__ == "__main__":
+    server = HTTPServer(("localhost", 9000), MockIssuer)
+    threading.Thread(target=server.serve_forever, daemon=True).start()
+
+    for test in [test_provisioning, test_admin_groups, test_state_checks]:
+        test()
+        print(f"{test.__name__}: ok")
@@ -0,0 +1,216 @@
+"""
+Tests security key registration & login using a software authenticator. The server should be started
+with the default debug configuration, & an admin user with username "admin" & password "password"
+should exist.
+"""
+import base64
+import hashlib
+import json
+import secrets
+import struct
+
+import requests
+from cryptography.hazmat.primitives import hashes
+from cryptography.hazmat.primitives.asymmetric import ec
+
+
+BASE_URL = "http://localhost:8000/api"
+ORIGIN = "http://localhost:8000"
+RP_ID = "localhost"
+
+# User present & user verified flags
+FLAGS = 0x01 | 0x04
+# Attested credential data included
+FLAG_AT = 0x40
+
+
+def b64(data):
+    return base64.urlsafe_b64encode(data).rstrip(b"=").decode()
+
+
+def cbor(value):
+    """Encodes the handful of CBOR types needed for attestation objects & COSE keys."""
+    def head(major, n):
+        if n < 24:
+            return bytes([major << 5 | n])
+        elif n < 0x100:
+            return bytes([major << 5 | 24, n])
+        elif n < 0x10000:
+            return bytes([major << 5 | 25]) + struct.pack(">H", n)
+        return bytes([major << 5 | 26]) + struct.pack(">I", n)
+
+    if isinstance(value, int):
+        return head(0, value) if value >= 0 else head(1, -1 - value)
+    elif isinstance(value, bytes):
+        return head(2, len(value)) + value
+    elif isinstance(value, str):
+        return head(3, len(value.encode())) + value.encode()
+    elif isinstance(value, dict):
+        return head(5, len(value)) + b"".join(cbor(k) + cbor(v) for k, v in value.items())
+
+    raise TypeError(type(value))
+
+
+class Authenticator:
+    def __init__(self):
+        self.key = ec.generate_private_key(ec.SECP256R1())
+        self.credential_id = secrets.token_bytes(32)
+        self.counter = 0
+
+    def _client_data(self, kind, challenge):
+        return json.dumps({"type": kind, "challenge": challenge, "origin": ORIGIN}).encode()
+
+    def create(self, options):
+        numbers = self.key.public_key().public_numbers()
+        cose_key = cbor({
+            1: 2,
+            3: -7,
+            -1: 1,
+            -2: numbers.x.to_bytes(32, "big"),
+            -3: numbers.y.to_bytes(32, "big"),
+        })
+        auth_data = (
+            hashlib.sha256(RP_ID.encode()).digest()
+            + bytes([FLAGS | FLAG_AT])
+            + struct.pack(">I", self.counter)
+            + bytes(16)
+            + struct.pack(">H", len(self.credential_id))
+            + self.credential_id
+            + cose_key
+        )
+
+        return {
+            "id": b64(self.credential_id),
+            "rawId": b64(self.credential_id),
+            "type": "public-key",
+            "response": {
+                "attestationObject": b64(cbor({"fmt": "none", "attStmt": {}, "authData": auth_data})),
+                "clientDataJSON": b64(self._client_data("webauthn.create", options["challenge"])),
+            },
+        }
+
+    def get(self, options):
+        self.counter += 1
+
+        client_data = self._client_data("webauthn.get", options["challenge"])
+        auth_data = (
+            hashlib.sha256(RP_ID.encode()).digest()
+            + bytes([FLAGS])
+            + struct.pack(">I", self.counter)
+        )
+        signature = self.key.sign(
+            auth_data + hashlib.sha256(client_data).digest(), ec.ECDSA(hashes.SHA256())
+        )
+
+        return {
+            "id": b64(self.credential_id),
+            "rawId": b64(self.credential_id),
+            "type": "public-key",
+            "response": {
+                "authenticatorData": b64(auth_data),
+                "clientDataJSON": b64(client_data),
+                "signature": b64(signature),
+                "userHandle": None,
+            },
+            "extensions": None,
+        }
+
+
+def login():
+    r = requests.post(f"{BASE_URL}/auth/login", json={"username": "admin", "password": "password"})
+    assert r.status_code == 200, r.text
+
+    return {"Authorization": f"Bearer {r.json()['token']}"}
+
+
+def register(headers, authenticator):
+    r = requests.post(f"{BASE_URL}/auth/webauthn/register/begin", headers=headers)
+    assert r.status_code == 200, r.text
+    challenge = r.json()
+
+    return requests.post(f"{BASE_URL}/auth/webauthn/register/finish", headers=headers, json={
+        "challengeId": challenge["challengeId"],
+        "name": "Software key",
+        "credential": authenticator.create(challenge["publicKey"]),
+    })
+
+
+def begin_login():
+    r = requests.post(f"{BASE_URL}/auth/webauthn/login/begin", json={"username": "admin"})
+    assert r.status_code == 200, r.text
+
+    return r.json()
+
+
+def finish_login(challenge, credential):
+    return requests.post(f"{BASE_URL}/auth/webauthn/login/finish", json={
+        "challengeId": challenge["challengeId"],
+        "credential": credential,
+    })
+
+
+def test_login():
+    headers = login()
+    authenticator = Authenticator()
+
+    r = register(headers, authenticator)
+    assert r.status_code == 200, r.text
+    credential = r.json()
+
+    # The same key can't be registered twice
+    assert register(headers, authenticator).status_code == 400
+
+    r = requests.get(f"{BASE_URL}/auth/webauthn/credentials", headers=headers)
+    assert credential["id"] in [c["id"] for c in r.json()]
+
+    challenge = begin_login()
+    r = finish_login(challenge, authenticator.get(challenge["publicKey"]))
+    assert r.status_code == 200, r.text
+    assert "refreshToken" in r.json()
+
+    # Challenges can only be answered once
+    r = finish_login(challenge, authenticator.get(challenge["publicKey"]))
+    assert r.status_code == 400, r.text
+
+    r = requests.delete(f"{BASE_URL}/auth/webauthn/credentials/{credential['id']}", headers=headers)
+    assert r.status_code == 200, r.text
+
+
+def test_sign_counter():
+    headers = login()
+    authenticator = Authenticator()
+    assert register(headers, authenticator).status_code == 200
+
+    challenge = begin_login()
+    assert finish_login(challenge, authenticator.get(challenge["publicKey"])).status_code == 200
+
+    # A clone of the key would reuse counter values
+    authenticator.counter -= 1
+    challenge = begin_login()
+    r = finish_login(challenge, authenticator.get(challenge["publicKey"]))
+    assert r.status_code == 401, r.text
+
+    # The counter is also checked against logins completed since a login was started
+    first, second = begin_login(), begin_login()
+    first_credential = authenticator.get(first["publicKey"])
+    assert finish_login(second, authenticator.get(second["publicKey"])).status_code == 200
+    r = finish_login(first, first_credential)
+    assert r.status_code == 401, r.text
+
+    # A forged signature is rejected as well
+    challenge = begin_login()
+    credential = Authenticator().get(challenge["publicKey"])
+    credential["id"] = credential["rawId"] = b64(authenticator.credential_id)
+    r = finish_login(challenge, credential)
+    assert r.status_code == 401, r.text
+
+
+def test_unknown_user():
+    r = requests.post(f"{BASE_URL}/auth/webauthn/login/begin", json={"username": "nobody"})
+    assert r.status_code == 401, r.text
+
+
+if __name__ == "__main__":
+    for test in [test_login, test_sign_counter, test_unknown_user]:
+        test()
+        print(f"{test.__name__}: ok")